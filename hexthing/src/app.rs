@@ -0,0 +1,185 @@
+use input::Input;
+use picking::HexPicker;
+use rendering::RendererState;
+use std::time::{Duration, Instant};
+
+/// Upper bound on the frame time fed into the accumulator. Without it a long stall (debugger,
+/// window drag) would force the loop to run hundreds of ticks to catch up.
+const MAX_FRAME_TIME: Duration = Duration::from_millis(250);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Control {
+    Continue,
+    Exit,
+}
+
+/// State made available to `App::update`.
+#[derive(Default)]
+pub struct Context {
+    /// The hex under the cursor, kept up to date from window events before every batch of
+    /// updates, and the selected one.
+    pub picking: HexPicker,
+    /// Id of the topmost `PickInstance` under the cursor, as of the most recent ID buffer
+    /// readback. Lags the cursor by a frame or so and is always `None` while no pick instances are
+    /// set.
+    pub picked_entity: Option<u32>,
+    /// Key and mouse button state since the previous update, and the actions it maps to.
+    pub input: Input,
+}
+
+/// Callbacks driven by `RendererState::mainloop`.
+///
+/// `update` is invoked at a fixed rate independent of the frame rate, `render` once per frame with
+/// the interpolation factor between the previous and the current tick. Debug primitives are drawn
+/// from `render`, through `RendererState::debug_draw_mut`.
+pub trait App {
+    fn init(&mut self, _renderer: &mut RendererState, _context: &mut Context) {}
+
+    /// Called for every window event after `Context::input` has seen it. Most applications should
+    /// react to actions in `update` instead.
+    fn handle_event(&mut self, _event: &winit::WindowEvent) -> Control {
+        Control::Continue
+    }
+
+    fn update(&mut self, context: &mut Context, dt: f32) -> Control;
+
+    fn render(&mut self, _renderer: &mut RendererState, _alpha: f32) {}
+}
+
+pub struct FixedTimestep {
+    tick: Duration,
+    accumulator: Duration,
+    last_frame: Option<Instant>,
+}
+
+impl FixedTimestep {
+    pub fn new(ticks_per_second: u32) -> Self {
+        assert!(ticks_per_second > 0);
+        FixedTimestep {
+            tick: Duration::from_nanos(1_000_000_000 / u64::from(ticks_per_second)),
+            accumulator: Duration::from_secs(0),
+            last_frame: None,
+        }
+    }
+
+    pub fn dt(&self) -> f32 {
+        duration_as_secs(self.tick)
+    }
+
+    /// Adds the time elapsed since the previous call to the accumulator.
+    pub fn begin_frame(&mut self) {
+        let now = Instant::now();
+        if let Some(last_frame) = self.last_frame {
+            self.advance(now - last_frame);
+        }
+        self.last_frame = Some(now);
+    }
+
+    pub fn advance(&mut self, frame_time: Duration) {
+        self.accumulator += frame_time.min(MAX_FRAME_TIME);
+    }
+
+    /// Consumes one tick worth of accumulated time, returning `false` once less than a full tick
+    /// remains.
+    pub fn tick(&mut self) -> bool {
+        if self.accumulator >= self.tick {
+            self.accumulator -= self.tick;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// How far the accumulator is between the last tick and the next one, in `0.0..1.0`.
+    pub fn alpha(&self) -> f32 {
+        duration_as_secs(self.accumulator) / duration_as_secs(self.tick)
+    }
+}
+
+fn duration_as_secs(duration: Duration) -> f32 {
+    duration.as_secs() as f32 + duration.subsec_nanos() as f32 * 1e-9
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < 1e-4,
+            "{} isn't close to {}",
+            actual,
+            expected
+        );
+    }
+
+    /// Runs every tick the accumulated time allows, like the main loop does between frames.
+    fn ticks(timestep: &mut FixedTimestep) -> u32 {
+        let mut ticks = 0;
+        while timestep.tick() {
+            ticks += 1;
+        }
+        ticks
+    }
+
+    #[test]
+    fn tick_length() {
+        assert_close(FixedTimestep::new(100).dt(), 0.01);
+        assert_close(FixedTimestep::new(60).dt(), 1.0 / 60.0);
+    }
+
+    #[test]
+    fn ticks_per_frame() {
+        let mut timestep = FixedTimestep::new(100);
+        assert_eq!(ticks(&mut timestep), 0);
+
+        timestep.advance(Duration::from_millis(10));
+        assert_eq!(ticks(&mut timestep), 1);
+
+        timestep.advance(Duration::from_millis(35));
+        assert_eq!(ticks(&mut timestep), 3);
+
+        // The leftover 5ms carry over into the next frame.
+        timestep.advance(Duration::from_millis(5));
+        assert_eq!(ticks(&mut timestep), 1);
+
+        timestep.advance(Duration::from_millis(4));
+        assert_eq!(ticks(&mut timestep), 0);
+    }
+
+    #[test]
+    fn interpolation_alpha() {
+        let mut timestep = FixedTimestep::new(100);
+        assert_close(timestep.alpha(), 0.0);
+
+        timestep.advance(Duration::from_micros(2500));
+        assert_eq!(ticks(&mut timestep), 0);
+        assert_close(timestep.alpha(), 0.25);
+
+        timestep.advance(Duration::from_micros(15000));
+        assert_eq!(ticks(&mut timestep), 1);
+        assert_close(timestep.alpha(), 0.75);
+    }
+
+    #[test]
+    fn long_stall_is_clamped() {
+        let mut timestep = FixedTimestep::new(100);
+        timestep.advance(Duration::from_secs(10));
+        // Only `MAX_FRAME_TIME` worth of ticks run.
+        assert_eq!(ticks(&mut timestep), 25);
+        assert_close(timestep.alpha(), 0.0);
+
+        // Time accumulated before the stall is kept.
+        timestep.advance(Duration::from_millis(5));
+        timestep.advance(Duration::from_secs(1));
+        assert_eq!(ticks(&mut timestep), 25);
+        assert_close(timestep.alpha(), 0.5);
+    }
+
+    #[test]
+    fn first_frame_adds_no_time() {
+        let mut timestep = FixedTimestep::new(100);
+        timestep.begin_frame();
+        assert_eq!(ticks(&mut timestep), 0);
+    }
+}
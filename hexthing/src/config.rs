@@ -0,0 +1,542 @@
+use log::LevelFilter;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+pub const DEFAULT_CONFIG_PATH: &str = "hexthing.toml";
+
+/// Largest window dimension accepted, well above what any device can present.
+const MAX_WINDOW_DIMENSION: u32 = 16384;
+
+/// More frames in flight only add latency.
+const MAX_FRAMES_IN_FLIGHT: usize = 4;
+
+pub const USAGE: &str = "\
+Usage: hexthing [options]
+
+Options:
+    --config <path>          Configuration file, defaults to hexthing.toml
+    --width <pixels>         Window width
+    --height <pixels>        Window height
+    --title <title>          Window title
+    --fullscreen             Start fullscreen on the primary monitor
+    --windowed               Start in a window
+    --present-mode <modes>   Comma separated present modes in order of preference, out of
+                             immediate, mailbox, fifo and relaxed
+    --vsync                  Same as --present-mode fifo
+    --no-vsync               Same as --present-mode mailbox,immediate
+    --msaa <samples>         MSAA sample count: 1, 2, 4 or 8
+    --frames-in-flight <n>   Frames the CPU may record ahead of the GPU, 1 to 4
+    --adapter <index|name>   Adapter to render with, by index or part of its name
+    --profiler-overlay       Graph CPU and GPU frame times
+    --pipeline-statistics    Query pipeline statistics every frame, where supported
+    --font <path>            TrueType font for overlay text, text is disabled without one
+    --log-level <level>      One of off, error, warn, info, debug or trace
+    --help                   Print this message";
+
+/// Present modes tried when vsync is turned off, from the command line or at runtime. Mailbox
+/// doesn't tear, so it is preferred where available.
+pub const NO_VSYNC_PRESENT_MODES: [PresentMode; 2] = [PresentMode::Mailbox, PresentMode::Immediate];
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PresentMode {
+    Immediate,
+    Mailbox,
+    Fifo,
+    Relaxed,
+}
+
+impl PresentMode {
+    /// Parses a comma separated list of modes.
+    fn parse_list(names: &str) -> Result<Vec<Self>, String> {
+        names
+            .split(',')
+            .map(str::trim)
+            .map(PresentMode::parse)
+            .collect()
+    }
+
+    fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "immediate" => Ok(PresentMode::Immediate),
+            "mailbox" => Ok(PresentMode::Mailbox),
+            "fifo" => Ok(PresentMode::Fifo),
+            "relaxed" => Ok(PresentMode::Relaxed),
+            _ => Err(format!(
+                "unknown present mode `{}`, expected immediate, mailbox, fifo or relaxed",
+                name
+            )),
+        }
+    }
+}
+
+/// Forces a specific adapter instead of the best scoring one.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+pub enum AdapterOverride {
+    /// Index into the adapters in the order the backend reports them.
+    Index(usize),
+    /// Case insensitive substring of the adapter name.
+    Name(String),
+}
+
+impl AdapterOverride {
+    fn parse(value: &str) -> Self {
+        match value.parse() {
+            Ok(index) => AdapterOverride::Index(index),
+            Err(_) => AdapterOverride::Name(value.to_owned()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WindowConfig {
+    /// Size in logical pixels.
+    pub width: u32,
+    pub height: u32,
+    pub title: String,
+    pub fullscreen: bool,
+}
+
+impl Default for WindowConfig {
+    fn default() -> Self {
+        WindowConfig {
+            width: 768,
+            height: 768,
+            title: "hexthing".to_owned(),
+            fullscreen: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RendererConfig {
+    /// Present modes in order of preference. The first one the surface supports is used, falling
+    /// back to `Fifo` which every surface supports.
+    pub present_modes: Vec<PresentMode>,
+    pub msaa_samples: u8,
+    /// Frames the CPU may record while the GPU is still working on earlier ones, independent of
+    /// the number of swapchain images.
+    pub frames_in_flight: usize,
+    /// `None` picks the adapter that scores best.
+    pub adapter: Option<AdapterOverride>,
+    pub clear_color: [f32; 4],
+    /// Graph frame times in the top right corner from the start.
+    pub profiler_overlay: bool,
+    /// Query pipeline statistics every frame, where the adapter supports it.
+    pub pipeline_statistics: bool,
+}
+
+impl Default for RendererConfig {
+    fn default() -> Self {
+        RendererConfig {
+            present_modes: vec![PresentMode::Fifo],
+            msaa_samples: 4,
+            frames_in_flight: 2,
+            adapter: None,
+            clear_color: [0.0, 0.0, 0.0, 1.0],
+            profiler_overlay: false,
+            pipeline_statistics: false,
+        }
+    }
+}
+
+/// What the command line asks for.
+#[derive(Debug)]
+pub enum Command {
+    Run(Config),
+    /// `--help` was given as an option.
+    Help,
+}
+
+/// What the command line says besides the overrides `Config::apply_args` applies.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Args {
+    help: bool,
+    config: Option<String>,
+}
+
+/// Startup configuration, read from a TOML file with every field optional and then overridden
+/// from the command line.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub window: WindowConfig,
+    pub renderer: RendererConfig,
+    /// Overridden by `RUST_LOG` when it is set.
+    pub log_level: Option<String>,
+    /// TrueType font used for overlay text. No font ships with hexthing, so text is disabled
+    /// until one is configured.
+    pub font: Option<String>,
+}
+
+impl Config {
+    /// Reads the configuration file named by `--config` or the default one, applies the remaining
+    /// command line arguments on top and validates the result. A missing default file is not an
+    /// error, a missing file named on the command line is.
+    ///
+    /// `--help` takes precedence over any arguments after it, without reading any configuration.
+    pub fn from_args<I>(args: I) -> Result<Command, String>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let args: Vec<String> = args.into_iter().collect();
+        // The arguments are parsed once up front to find the file to apply them to.
+        let parsed = Config::default().apply_args(&args)?;
+        if parsed.help {
+            return Ok(Command::Help);
+        }
+
+        let mut config = match parsed.config {
+            Some(ref path) => Config::load(path)?,
+            None if Path::new(DEFAULT_CONFIG_PATH).exists() => Config::load(DEFAULT_CONFIG_PATH)?,
+            None => Config::default(),
+        };
+        config.apply_args(&args)?;
+        config.validate()?;
+        Ok(Command::Run(config))
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        let path = path.as_ref();
+        let source = fs::read_to_string(path).map_err(|err| match err.kind() {
+            io::ErrorKind::NotFound => format!("config file {} doesn't exist", path.display()),
+            _ => format!("can't read config file {}: {}", path.display(), err),
+        })?;
+        toml::from_str(&source).map_err(|err| format!("{}: {}", path.display(), err))
+    }
+
+    /// Applies the options in `args` on top of the current values. Stops at `--help`.
+    fn apply_args(&mut self, args: &[String]) -> Result<Args, String> {
+        let mut parsed = Args::default();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .map(String::as_str)
+                    .ok_or_else(|| format!("{} requires a value", arg))
+            };
+            match arg.as_str() {
+                "--help" => {
+                    parsed.help = true;
+                    break;
+                }
+                "--config" => {
+                    let path = value()?.to_owned();
+                    if parsed.config.is_some() {
+                        return Err("--config given more than once".to_owned());
+                    }
+                    parsed.config = Some(path);
+                }
+                "--width" => self.window.width = parse_number(arg, value()?)?,
+                "--height" => self.window.height = parse_number(arg, value()?)?,
+                "--title" => self.window.title = value()?.to_owned(),
+                "--fullscreen" => self.window.fullscreen = true,
+                "--windowed" => self.window.fullscreen = false,
+                "--present-mode" => {
+                    self.renderer.present_modes = PresentMode::parse_list(value()?)?
+                }
+                "--vsync" => self.renderer.present_modes = vec![PresentMode::Fifo],
+                "--no-vsync" => self.renderer.present_modes = NO_VSYNC_PRESENT_MODES.to_vec(),
+                "--msaa" => self.renderer.msaa_samples = parse_number(arg, value()?)?,
+                "--frames-in-flight" => {
+                    self.renderer.frames_in_flight = parse_number(arg, value()?)?
+                }
+                "--adapter" => self.renderer.adapter = Some(AdapterOverride::parse(value()?)),
+                "--profiler-overlay" => self.renderer.profiler_overlay = true,
+                "--pipeline-statistics" => self.renderer.pipeline_statistics = true,
+                "--font" => self.font = Some(value()?.to_owned()),
+                "--log-level" => self.log_level = Some(value()?.to_owned()),
+                _ => return Err(format!("unknown argument `{}`\n\n{}", arg, USAGE)),
+            }
+        }
+        Ok(parsed)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        let window = &self.window;
+        for &(name, value) in &[("width", window.width), ("height", window.height)] {
+            if value == 0 || value > MAX_WINDOW_DIMENSION {
+                return Err(format!(
+                    "window.{} is {}, expected a size between 1 and {} pixels",
+                    name, value, MAX_WINDOW_DIMENSION
+                ));
+            }
+        }
+
+        if self.renderer.present_modes.is_empty() {
+            return Err(
+                "renderer.present_modes is empty, list at least one of immediate, mailbox, fifo \
+                 or relaxed"
+                    .to_owned(),
+            );
+        }
+
+        let samples = self.renderer.msaa_samples;
+        if ![1, 2, 4, 8].contains(&samples) {
+            return Err(format!(
+                "renderer.msaa_samples is {}, expected 1, 2, 4 or 8 (1 disables MSAA)",
+                samples
+            ));
+        }
+
+        let frames_in_flight = self.renderer.frames_in_flight;
+        if frames_in_flight == 0 || frames_in_flight > MAX_FRAMES_IN_FLIGHT {
+            return Err(format!(
+                "renderer.frames_in_flight is {}, expected 1 to {}",
+                frames_in_flight, MAX_FRAMES_IN_FLIGHT
+            ));
+        }
+
+        if let Some(&component) = self
+            .renderer
+            .clear_color
+            .iter()
+            .find(|&&component| !(component >= 0.0 && component <= 1.0))
+        {
+            return Err(format!(
+                "renderer.clear_color component {} is outside of 0.0..=1.0",
+                component
+            ));
+        }
+
+        self.log_level().map(|_| ())
+    }
+
+    /// The configured log level, `Info` when unset.
+    pub fn log_level(&self) -> Result<LevelFilter, String> {
+        match self.log_level {
+            Some(ref level) => level.parse().map_err(|_| {
+                format!(
+                    "log_level is `{}`, expected off, error, warn, info, debug or trace",
+                    level
+                )
+            }),
+            None => Ok(LevelFilter::Info),
+        }
+    }
+}
+
+fn parse_number<T: ::std::str::FromStr>(arg: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("{} expects a number, got `{}`", arg, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|&arg| arg.to_owned()).collect()
+    }
+
+    fn apply(args_: &[&str]) -> Result<(Config, Args), String> {
+        let mut config = Config::default();
+        let parsed = config.apply_args(&args(args_))?;
+        Ok((config, parsed))
+    }
+
+    #[test]
+    fn parse_options() {
+        let (config, parsed) = apply(&[
+            "--width",
+            "1024",
+            "--height",
+            "600",
+            "--title",
+            "hex",
+            "--fullscreen",
+            "--present-mode",
+            "mailbox, fifo",
+            "--msaa",
+            "8",
+            "--frames-in-flight",
+            "3",
+            "--adapter",
+            "1",
+            "--profiler-overlay",
+            "--pipeline-statistics",
+            "--font",
+            "font.ttf",
+            "--log-level",
+            "debug",
+        ])
+        .unwrap();
+        assert_eq!(parsed, Args::default());
+        assert_eq!(config.window.width, 1024);
+        assert_eq!(config.window.height, 600);
+        assert_eq!(config.window.title, "hex");
+        assert!(config.window.fullscreen);
+        assert_eq!(
+            config.renderer.present_modes,
+            [PresentMode::Mailbox, PresentMode::Fifo]
+        );
+        assert_eq!(config.renderer.msaa_samples, 8);
+        assert_eq!(config.renderer.frames_in_flight, 3);
+        assert_eq!(config.renderer.adapter, Some(AdapterOverride::Index(1)));
+        assert!(config.renderer.profiler_overlay);
+        assert!(config.renderer.pipeline_statistics);
+        assert_eq!(config.font, Some("font.ttf".to_owned()));
+        assert_eq!(config.log_level(), Ok(LevelFilter::Debug));
+    }
+
+    #[test]
+    fn later_options_win() {
+        let (config, _) = apply(&["--fullscreen", "--windowed", "--no-vsync", "--vsync"]).unwrap();
+        assert!(!config.window.fullscreen);
+        assert_eq!(config.renderer.present_modes, [PresentMode::Fifo]);
+
+        let (config, _) = apply(&["--vsync", "--no-vsync"]).unwrap();
+        assert_eq!(config.renderer.present_modes, NO_VSYNC_PRESENT_MODES);
+
+        let (config, _) = apply(&["--adapter", "1", "--adapter", "GeForce"]).unwrap();
+        assert_eq!(
+            config.renderer.adapter,
+            Some(AdapterOverride::Name("GeForce".to_owned()))
+        );
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(apply(&["--width"])
+            .unwrap_err()
+            .contains("requires a value"));
+        assert!(apply(&["--width", "wide"])
+            .unwrap_err()
+            .contains("expects a number"));
+        assert!(apply(&["--present-mode", "fifo,sometimes"])
+            .unwrap_err()
+            .contains("unknown present mode"));
+        assert!(apply(&["--bogus"])
+            .unwrap_err()
+            .starts_with("unknown argument `--bogus`"));
+    }
+
+    #[test]
+    fn help_is_a_flag() {
+        let (_, parsed) = apply(&["--width", "800", "--help", "--bogus"]).unwrap();
+        assert!(parsed.help);
+        match Config::from_args(args(&["--fullscreen", "--help"])) {
+            Ok(Command::Help) => (),
+            other => panic!("expected help, got {:?}", other),
+        }
+
+        // Where a value is expected `--help` is the value.
+        let (config, parsed) = apply(&["--title", "--help"]).unwrap();
+        assert!(!parsed.help);
+        assert_eq!(config.window.title, "--help");
+        let err = Config::from_args(args(&["--config", "--help"])).unwrap_err();
+        assert!(err.contains("config file --help doesn't exist"), "{}", err);
+    }
+
+    #[test]
+    fn config_path() {
+        let (_, parsed) = apply(&["--config", "other.toml", "--width", "640"]).unwrap();
+        assert_eq!(parsed.config, Some("other.toml".to_owned()));
+
+        assert!(apply(&["--config"])
+            .unwrap_err()
+            .contains("requires a value"));
+        let err =
+            Config::from_args(args(&["--config", "a.toml", "--config", "b.toml"])).unwrap_err();
+        assert!(err.contains("--config given more than once"), "{}", err);
+    }
+
+    #[test]
+    fn arguments_override_file() {
+        let mut config: Config = toml::from_str(
+            "log_level = \"warn\"\n\
+             [window]\n\
+             width = 1280\n\
+             height = 720\n\
+             title = \"from file\"\n\
+             [renderer]\n\
+             present_modes = [\"relaxed\", \"fifo\"]\n\
+             msaa_samples = 2\n\
+             adapter = \"radeon\"\n",
+        )
+        .unwrap();
+        config
+            .apply_args(&args(&[
+                "--height",
+                "800",
+                "--no-vsync",
+                "--log-level",
+                "trace",
+            ]))
+            .unwrap();
+
+        assert_eq!(config.window.width, 1280);
+        assert_eq!(config.window.height, 800);
+        assert_eq!(config.window.title, "from file");
+        assert_eq!(config.renderer.present_modes, NO_VSYNC_PRESENT_MODES);
+        assert_eq!(config.renderer.msaa_samples, 2);
+        assert_eq!(
+            config.renderer.adapter,
+            Some(AdapterOverride::Name("radeon".to_owned()))
+        );
+        // Unset in the file, so the default.
+        assert_eq!(config.renderer.frames_in_flight, 2);
+        assert_eq!(config.log_level(), Ok(LevelFilter::Trace));
+        config.validate().unwrap();
+    }
+
+    #[test]
+    fn bundled_config_parses() {
+        let config: Config = toml::from_str(include_str!("../hexthing.toml")).unwrap();
+        config.validate().unwrap();
+    }
+
+    #[test]
+    fn validate_rejects_bad_sample_counts() {
+        let mut config = Config::default();
+        for &samples in &[1, 2, 4, 8] {
+            config.renderer.msaa_samples = samples;
+            config.validate().unwrap();
+        }
+        for &samples in &[0, 3, 16] {
+            config.renderer.msaa_samples = samples;
+            let err = config.validate().unwrap_err();
+            assert!(err.starts_with("renderer.msaa_samples"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn validate_rejects_bad_sizes() {
+        let mut config = Config::default();
+        config.window.width = MAX_WINDOW_DIMENSION;
+        config.validate().unwrap();
+        for &(width, height) in &[(0, 768), (768, 0), (MAX_WINDOW_DIMENSION + 1, 768)] {
+            config.window.width = width;
+            config.window.height = height;
+            assert!(config.validate().unwrap_err().starts_with("window."));
+        }
+    }
+
+    #[test]
+    fn validate_rejects_other_bad_values() {
+        let mut config = Config::default();
+        config.renderer.frames_in_flight = 0;
+        assert!(config.validate().is_err());
+        config.renderer.frames_in_flight = MAX_FRAMES_IN_FLIGHT + 1;
+        assert!(config.validate().is_err());
+
+        let mut config = Config::default();
+        config.renderer.present_modes.clear();
+        assert!(config.validate().is_err());
+
+        let mut config = Config::default();
+        config.renderer.clear_color = [0.0, 1.5, 0.0, 1.0];
+        assert!(config.validate().is_err());
+
+        let config = Config {
+            log_level: Some("loud".to_owned()),
+            ..Config::default()
+        };
+        assert!(config.validate().is_err());
+    }
+}
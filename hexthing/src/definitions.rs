@@ -4,6 +4,26 @@ use nalgebra::Vector2;
 
 pub type Vertex = Vector2<f32>;
 
+/// Per-instance data for a hex tile: its position and the atlas region it is textured with.
+#[derive(Debug, Copy, Clone)]
+#[repr(C)]
+pub struct HexInstance {
+    pub position: Vector2<f32>,
+    pub uv_rect: [f32; 4],
+}
+
+/// Per-instance data for the ID buffer picking pass. The hex mesh is scaled by `scale` around
+/// `position`, so sprites overhanging their tile can be given a matching footprint, and drawn at
+/// `depth` so the topmost instance wins. An `id` of 0 is reserved for "nothing".
+#[derive(Debug, Copy, Clone)]
+#[repr(C)]
+pub struct PickInstance {
+    pub position: Vector2<f32>,
+    pub scale: f32,
+    pub depth: f32,
+    pub id: u32,
+}
+
 #[derive(Debug)]
 pub struct RenderableDefinition {
     pub id: String,
@@ -0,0 +1,143 @@
+use nalgebra::Vector2;
+
+const SQRT_3: f32 = 1.732_050_8;
+
+/// Axial coordinates of a pointy top hex. Hexes have a circumradius of 1 world unit, matching the
+/// tile mesh, with `q` increasing to the right and `r` increasing downwards.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct HexCoord {
+    pub q: i32,
+    pub r: i32,
+}
+
+impl HexCoord {
+    pub fn new(q: i32, r: i32) -> Self {
+        HexCoord { q, r }
+    }
+
+    pub fn to_world(self) -> Vector2<f32> {
+        Vector2::new(
+            SQRT_3 * (self.q as f32 + self.r as f32 / 2.0),
+            1.5 * self.r as f32,
+        )
+    }
+
+    /// The hex containing the world space `point`.
+    pub fn from_world(point: Vector2<f32>) -> Self {
+        let q = SQRT_3 / 3.0 * point.x - point.y / 3.0;
+        let r = 2.0 / 3.0 * point.y;
+        HexCoord::round(q, r)
+    }
+
+    /// Rounds fractional axial coordinates to the nearest hex by rounding in cube space and
+    /// fixing up the component with the largest rounding error.
+    fn round(q: f32, r: f32) -> Self {
+        let s = -q - r;
+        let (mut rq, mut rr, rs) = (q.round(), r.round(), s.round());
+        let (dq, dr, ds) = ((rq - q).abs(), (rr - r).abs(), (rs - s).abs());
+        if dq > dr && dq > ds {
+            rq = -rr - rs;
+        } else if dr > ds {
+            rr = -rq - rs;
+        }
+        HexCoord::new(rq as i32, rr as i32)
+    }
+
+    pub fn distance(self, other: HexCoord) -> i32 {
+        let dq = self.q - other.q;
+        let dr = self.r - other.r;
+        (dq.abs() + dr.abs() + (dq + dr).abs()) / 2
+    }
+
+    /// Every hex within `radius` steps of `self`, including `self`.
+    pub fn range(self, radius: i32) -> Vec<HexCoord> {
+        let mut hexes = Vec::new();
+        for q in -radius..=radius {
+            for r in (-radius).max(-q - radius)..=radius.min(-q + radius) {
+                hexes.push(HexCoord::new(self.q + q, self.r + r));
+            }
+        }
+        hexes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn world_round_trip() {
+        for coord in HexCoord::new(2, -3).range(6) {
+            assert_eq!(HexCoord::from_world(coord.to_world()), coord);
+        }
+    }
+
+    #[test]
+    fn to_world_spacing() {
+        assert_eq!(HexCoord::new(0, 0).to_world(), Vector2::new(0.0, 0.0));
+        assert_eq!(HexCoord::new(1, 0).to_world(), Vector2::new(SQRT_3, 0.0));
+        assert_eq!(
+            HexCoord::new(0, 1).to_world(),
+            Vector2::new(SQRT_3 / 2.0, 1.5)
+        );
+        assert_eq!(HexCoord::new(-1, 2).to_world(), Vector2::new(0.0, 3.0));
+    }
+
+    #[test]
+    fn points_inside_a_hex_map_to_it() {
+        // Just inside the inradius towards every edge and the circumradius towards every corner.
+        let inradius = SQRT_3 / 2.0;
+        for coord in HexCoord::new(0, 0).range(3) {
+            let center = coord.to_world();
+            for i in 0..6 {
+                let edge = (i as f32 * 60.0).to_radians();
+                let corner = (i as f32 * 60.0 + 30.0).to_radians();
+                for &(angle, radius) in &[(edge, inradius), (corner, 1.0)] {
+                    let offset = Vector2::new(angle.cos(), angle.sin()) * radius * 0.95;
+                    assert_eq!(HexCoord::from_world(center + offset), coord);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rounds_to_the_nearest_center() {
+        for y in -40..=40 {
+            for x in -40..=40 {
+                let point = Vector2::new(x as f32 * 0.1, y as f32 * 0.1);
+                let hex = HexCoord::from_world(point);
+                let distance = (hex.to_world() - point).norm();
+                for neighbor in hex.range(1) {
+                    assert!(
+                        distance <= (neighbor.to_world() - point).norm() + 1e-4,
+                        "{:?} rounded to {:?}, {:?} is closer",
+                        point,
+                        hex,
+                        neighbor
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn round_fixes_up_the_largest_error() {
+        assert_eq!(HexCoord::round(0.4, 0.4), HexCoord::new(0, 1));
+        assert_eq!(HexCoord::round(0.6, -0.2), HexCoord::new(1, 0));
+        assert_eq!(HexCoord::round(-0.3, -0.3), HexCoord::new(0, 0));
+        assert_eq!(HexCoord::round(1.45, -2.9), HexCoord::new(1, -3));
+    }
+
+    #[test]
+    fn distance_and_range() {
+        let origin = HexCoord::new(1, -1);
+        assert_eq!(origin.distance(origin), 0);
+        assert_eq!(origin.distance(HexCoord::new(4, -1)), 3);
+        assert_eq!(origin.distance(HexCoord::new(-1, 2)), 3);
+        for radius in 0..5 {
+            let range = origin.range(radius);
+            assert_eq!(range.len() as i32, 1 + 3 * radius * (radius + 1));
+            assert!(range.iter().all(|&coord| origin.distance(coord) <= radius));
+        }
+    }
+}
@@ -0,0 +1,464 @@
+use fnv::{FnvHashMap, FnvHashSet};
+use winit::{ElementState, ModifiersState, MouseButton, VirtualKeyCode};
+
+/// A physical key or mouse button.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum InputCode {
+    Key(VirtualKeyCode),
+    Mouse(MouseButton),
+}
+
+/// Keys that can be named in a bindings file, by their `VirtualKeyCode` variant name.
+macro_rules! key_names {
+    ($($key:ident),* $(,)*) => {
+        fn parse_key(name: &str) -> Option<VirtualKeyCode> {
+            match name {
+                $(stringify!($key) => Some(VirtualKeyCode::$key),)*
+                _ => None,
+            }
+        }
+    };
+}
+
+key_names![
+    Key1, Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9, Key0, A, B, C, D, E, F, G, H, I, J, K, L,
+    M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z, Escape, F1, F2, F3, F4, F5, F6, F7, F8, F9, F10, F11,
+    F12, Insert, Home, Delete, End, PageDown, PageUp, Left, Up, Right, Down, Back, Return, Space,
+    Tab, Minus, Equals, Comma, Period, Slash, Semicolon, Apostrophe, Grave, Backslash, LBracket,
+    RBracket, Add, Subtract, Multiply, Divide, Numpad0, Numpad1, Numpad2, Numpad3, Numpad4,
+    Numpad5, Numpad6, Numpad7, Numpad8, Numpad9,
+];
+
+impl InputCode {
+    /// Parses a key name such as `Escape` or `A`, or a mouse button as `MouseLeft`, `MouseRight`,
+    /// `MouseMiddle` or `Mouse<n>` for any other button.
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "MouseLeft" => Some(InputCode::Mouse(MouseButton::Left)),
+            "MouseRight" => Some(InputCode::Mouse(MouseButton::Right)),
+            "MouseMiddle" => Some(InputCode::Mouse(MouseButton::Middle)),
+            _ if name.starts_with("Mouse") => name["Mouse".len()..]
+                .parse()
+                .ok()
+                .map(|button| InputCode::Mouse(MouseButton::Other(button))),
+            _ => parse_key(name).map(InputCode::Key),
+        }
+    }
+}
+
+/// Modifier keys that have to be down, and no others, for a binding to trigger.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Modifiers {
+    pub shift: bool,
+    pub ctrl: bool,
+    pub alt: bool,
+    pub logo: bool,
+}
+
+impl From<ModifiersState> for Modifiers {
+    fn from(state: ModifiersState) -> Self {
+        Modifiers {
+            shift: state.shift,
+            ctrl: state.ctrl,
+            alt: state.alt,
+            logo: state.logo,
+        }
+    }
+}
+
+/// A chord of one or more inputs that have to be held together, plus the required modifiers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Binding {
+    pub modifiers: Modifiers,
+    pub inputs: Vec<InputCode>,
+}
+
+impl Binding {
+    /// Parses a `+` separated binding such as `Ctrl+Shift+S`, `F3+G` or `MouseRight`.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut modifiers = Modifiers::default();
+        let mut inputs = Vec::new();
+        for part in text.split('+').map(str::trim) {
+            match part {
+                "Shift" => modifiers.shift = true,
+                "Ctrl" => modifiers.ctrl = true,
+                "Alt" => modifiers.alt = true,
+                "Logo" => modifiers.logo = true,
+                _ => inputs.push(
+                    InputCode::parse(part).ok_or_else(|| format!("unknown input `{}`", part))?,
+                ),
+            }
+        }
+        if inputs.is_empty() {
+            return Err(format!("`{}` binds only modifiers", text));
+        }
+        Ok(Binding { modifiers, inputs })
+    }
+}
+
+/// Maps action names to the bindings that trigger them.
+#[derive(Debug, Default)]
+pub struct InputMap {
+    actions: FnvHashMap<String, Vec<Binding>>,
+}
+
+impl InputMap {
+    /// Parses a bindings file. Each non-empty line that isn't a `#` comment has the form
+    /// `action = binding, binding, ...`, and an action may be listed on several lines.
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let mut map = InputMap::default();
+        for (index, line) in source.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let error = |message: String| format!("line {}: {}", index + 1, message);
+            let mut parts = line.splitn(2, '=');
+            let action = parts.next().unwrap().trim();
+            let bindings = parts
+                .next()
+                .ok_or_else(|| error("expected `action = binding`".to_owned()))?;
+            if action.is_empty() {
+                return Err(error("missing action name".to_owned()));
+            }
+            for binding in bindings.split(',') {
+                let binding = Binding::parse(binding).map_err(&error)?;
+                map.bind(action, binding);
+            }
+        }
+        Ok(map)
+    }
+
+    pub fn bind(&mut self, action: &str, binding: Binding) {
+        self.actions
+            .entry(action.to_owned())
+            .or_insert_with(Vec::new)
+            .push(binding);
+    }
+
+    /// Removes every binding of `action`.
+    pub fn unbind(&mut self, action: &str) {
+        self.actions.remove(action);
+    }
+
+    pub fn bindings(&self, action: &str) -> &[Binding] {
+        self.actions.get(action).map_or(&[][..], Vec::as_slice)
+    }
+}
+
+/// Key and mouse button state, and the actions it maps to.
+///
+/// Presses and releases are collected from window events and kept until the next update has seen
+/// them, so an update observes every edge exactly once no matter how many frames or ticks passed.
+#[derive(Debug, Default)]
+pub struct Input {
+    pub map: InputMap,
+    held: FnvHashSet<InputCode>,
+    pressed: FnvHashSet<InputCode>,
+    released: FnvHashSet<InputCode>,
+    /// Actions whose chord was completed by a press since the last update.
+    pressed_actions: FnvHashSet<String>,
+    modifiers: Modifiers,
+}
+
+impl Input {
+    pub fn handle_event(&mut self, event: &winit::WindowEvent) {
+        match *event {
+            winit::WindowEvent::KeyboardInput { input, .. } => {
+                self.modifiers = input.modifiers.into();
+                if let Some(key) = input.virtual_keycode {
+                    self.set_state(InputCode::Key(key), input.state);
+                }
+            }
+            winit::WindowEvent::MouseInput {
+                state,
+                button,
+                modifiers,
+                ..
+            } => {
+                self.modifiers = modifiers.into();
+                self.set_state(InputCode::Mouse(button), state);
+            }
+            // Releases that happen while the window is unfocused are never reported.
+            winit::WindowEvent::Focused(false) => {
+                self.released.extend(self.held.drain());
+                self.modifiers = Modifiers::default();
+            }
+            _ => (),
+        }
+    }
+
+    fn set_state(&mut self, code: InputCode, state: ElementState) {
+        match state {
+            // Key repeat reports presses of keys that are already held.
+            ElementState::Pressed => {
+                if self.held.insert(code) {
+                    self.pressed.insert(code);
+                    self.record_pressed_actions(code);
+                }
+            }
+            ElementState::Released => {
+                if self.held.remove(&code) {
+                    self.released.insert(code);
+                }
+            }
+        }
+    }
+
+    /// Remembers every action with a binding that the press of `code` completed, so the action
+    /// is reported as pressed even if it's released again before the next update.
+    fn record_pressed_actions(&mut self, code: InputCode) {
+        let actions: Vec<String> = self
+            .map
+            .actions
+            .iter()
+            .filter(|&(_, bindings)| {
+                bindings
+                    .iter()
+                    .any(|binding| binding.inputs.contains(&code) && self.binding_held(binding))
+            })
+            .map(|(action, _)| action.clone())
+            .collect();
+        self.pressed_actions.extend(actions);
+    }
+
+    /// Forgets this update's presses and releases. Called after every update.
+    pub fn end_update(&mut self) {
+        self.pressed.clear();
+        self.released.clear();
+        self.pressed_actions.clear();
+    }
+
+    pub fn is_held(&self, code: InputCode) -> bool {
+        self.held.contains(&code)
+    }
+
+    pub fn was_pressed(&self, code: InputCode) -> bool {
+        self.pressed.contains(&code)
+    }
+
+    pub fn was_released(&self, code: InputCode) -> bool {
+        self.released.contains(&code)
+    }
+
+    pub fn modifiers(&self) -> Modifiers {
+        self.modifiers
+    }
+
+    /// Whether any binding of `action` is fully held with exactly its modifiers down.
+    pub fn action_held(&self, action: &str) -> bool {
+        self.map
+            .bindings(action)
+            .iter()
+            .any(|binding| self.binding_held(binding))
+    }
+
+    /// Whether a press since the last update completed the chord of a binding of `action` with
+    /// exactly its modifiers down. The binding may have been released again since.
+    pub fn action_pressed(&self, action: &str) -> bool {
+        self.pressed_actions.contains(action)
+    }
+
+    /// Whether a binding of `action` stopped being held since the last update. Modifiers are not
+    /// checked, so releasing them first doesn't swallow the release.
+    pub fn action_released(&self, action: &str) -> bool {
+        self.map.bindings(action).iter().any(|binding| {
+            binding
+                .inputs
+                .iter()
+                .any(|code| self.released.contains(code))
+                && binding
+                    .inputs
+                    .iter()
+                    .all(|code| self.held.contains(code) || self.released.contains(code))
+        })
+    }
+
+    fn binding_held(&self, binding: &Binding) -> bool {
+        binding.modifiers == self.modifiers
+            && binding.inputs.iter().all(|code| self.held.contains(code))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(key: VirtualKeyCode) -> InputCode {
+        InputCode::Key(key)
+    }
+
+    fn ctrl() -> Modifiers {
+        Modifiers {
+            ctrl: true,
+            ..Modifiers::default()
+        }
+    }
+
+    fn input(bindings: &str) -> Input {
+        Input {
+            map: InputMap::parse(bindings).unwrap(),
+            ..Input::default()
+        }
+    }
+
+    #[test]
+    fn parse_modifier_binding() {
+        assert_eq!(
+            Binding::parse("Ctrl+D"),
+            Ok(Binding {
+                modifiers: ctrl(),
+                inputs: vec![key(VirtualKeyCode::D)],
+            })
+        );
+        assert_eq!(
+            Binding::parse(" Shift + Alt + Logo + S ")
+                .unwrap()
+                .modifiers,
+            Modifiers {
+                shift: true,
+                ctrl: false,
+                alt: true,
+                logo: true,
+            }
+        );
+    }
+
+    #[test]
+    fn parse_chord() {
+        assert_eq!(
+            Binding::parse("F3+U"),
+            Ok(Binding {
+                modifiers: Modifiers::default(),
+                inputs: vec![key(VirtualKeyCode::F3), key(VirtualKeyCode::U)],
+            })
+        );
+    }
+
+    #[test]
+    fn parse_mouse_buttons() {
+        let mouse = |name| Binding::parse(name).unwrap().inputs;
+        assert_eq!(mouse("MouseLeft"), [InputCode::Mouse(MouseButton::Left)]);
+        assert_eq!(mouse("MouseRight"), [InputCode::Mouse(MouseButton::Right)]);
+        assert_eq!(
+            mouse("MouseMiddle"),
+            [InputCode::Mouse(MouseButton::Middle)]
+        );
+        assert_eq!(mouse("Mouse4"), [InputCode::Mouse(MouseButton::Other(4))]);
+        assert!(Binding::parse("MouseSide").is_err());
+    }
+
+    #[test]
+    fn parse_rejects_bad_bindings() {
+        assert!(Binding::parse("Ctrl+Nope").is_err());
+        assert!(Binding::parse("Ctrl+Shift").is_err());
+        assert!(Binding::parse("").is_err());
+    }
+
+    #[test]
+    fn parse_map() {
+        let map = InputMap::parse(
+            "# comment\n\
+             \n\
+             clear_selection = Ctrl+D, MouseRight\n\
+             exit = Escape\n\
+             exit = Q\n",
+        )
+        .unwrap();
+        assert_eq!(
+            map.bindings("clear_selection"),
+            &[
+                Binding::parse("Ctrl+D").unwrap(),
+                Binding::parse("MouseRight").unwrap(),
+            ][..]
+        );
+        assert_eq!(map.bindings("exit").len(), 2);
+        assert!(map.bindings("missing").is_empty());
+    }
+
+    #[test]
+    fn parse_map_reports_lines() {
+        let err = InputMap::parse("exit = Escape\nexit\n").unwrap_err();
+        assert!(err.starts_with("line 2:"), "{}", err);
+        let err = InputMap::parse("exit = Escape\n= G\n").unwrap_err();
+        assert!(err.starts_with("line 2:"), "{}", err);
+        let err = InputMap::parse("\nexit = Escape, Bogus\n").unwrap_err();
+        assert!(err.starts_with("line 2:"), "{}", err);
+    }
+
+    #[test]
+    fn bundled_bindings_parse() {
+        InputMap::parse(include_str!("../assets/input.cfg")).unwrap();
+    }
+
+    #[test]
+    fn press_requires_exact_modifiers() {
+        let mut input = input("clear = Ctrl+D\nplain = D");
+
+        input.modifiers = ctrl();
+        input.set_state(key(VirtualKeyCode::D), ElementState::Pressed);
+        assert!(input.action_pressed("clear"));
+        assert!(!input.action_pressed("plain"));
+        assert!(input.action_held("clear"));
+
+        // Pressed is an edge, held lasts until release.
+        input.end_update();
+        assert!(!input.action_pressed("clear"));
+        assert!(input.action_held("clear"));
+
+        // Releasing the modifier first still reports the release.
+        input.modifiers = Modifiers::default();
+        assert!(!input.action_held("clear"));
+        input.set_state(key(VirtualKeyCode::D), ElementState::Released);
+        assert!(input.action_released("clear"));
+    }
+
+    #[test]
+    fn chord_triggers_once_complete() {
+        let mut input = input("outlines = F3+U");
+
+        input.set_state(key(VirtualKeyCode::F3), ElementState::Pressed);
+        assert!(!input.action_pressed("outlines"));
+        input.end_update();
+
+        input.set_state(key(VirtualKeyCode::U), ElementState::Pressed);
+        assert!(input.action_pressed("outlines"));
+        input.end_update();
+
+        // Key repeat doesn't press again.
+        input.set_state(key(VirtualKeyCode::U), ElementState::Pressed);
+        assert!(!input.action_pressed("outlines"));
+
+        // Pressing U again while F3 is still held completes the chord again.
+        input.set_state(key(VirtualKeyCode::U), ElementState::Released);
+        input.end_update();
+        input.set_state(key(VirtualKeyCode::U), ElementState::Pressed);
+        assert!(input.action_pressed("outlines"));
+        input.end_update();
+
+        input.set_state(key(VirtualKeyCode::F3), ElementState::Released);
+        assert!(input.action_released("outlines"));
+        assert!(!input.action_held("outlines"));
+    }
+
+    #[test]
+    fn press_and_release_within_one_update() {
+        let mut input = input("select = MouseLeft");
+        let left = InputCode::Mouse(MouseButton::Left);
+
+        input.set_state(left, ElementState::Pressed);
+        input.set_state(left, ElementState::Released);
+        assert!(input.was_pressed(left));
+        assert!(input.was_released(left));
+        assert!(!input.is_held(left));
+        // A click within one update reports both edges of the action.
+        assert!(input.action_pressed("select"));
+        assert!(input.action_released("select"));
+        assert!(!input.action_held("select"));
+
+        input.end_update();
+        assert!(!input.action_pressed("select"));
+        assert!(!input.action_released("select"));
+    }
+}
@@ -13,29 +13,261 @@ extern crate gfx_backend_metal as back;
 #[cfg(feature = "vulkan")]
 extern crate gfx_backend_vulkan as back;
 
+extern crate dirs;
 extern crate fnv;
 extern crate gfx;
 extern crate gfx_hal as hal;
 extern crate glsl_to_spirv;
+#[macro_use]
+extern crate log;
 extern crate nalgebra;
+extern crate rayon;
+extern crate rusttype;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+extern crate toml;
 extern crate winit;
 
+mod app;
+mod config;
 mod definitions;
+mod hex;
+mod input;
+mod picking;
 mod rendering;
 
-use rendering::RendererState;
+use app::{App, Context, Control};
+use config::{Command, Config, PresentMode, NO_VSYNC_PRESENT_MODES};
+use hex::HexCoord;
+use input::InputMap;
+use rendering::{Atlas, AtlasBuilder, DepthMode, GridStyle, Layer, RendererState, UvRect};
 
-use hal::{buffer, window::Extent2D, Primitive};
+use hal::{buffer, Primitive};
 
+use definitions::HexInstance;
 use definitions::InputDescriptor;
+use definitions::PickInstance;
 use definitions::RenderableDefinition;
 use definitions::Vertex;
+use std::env;
 use std::fs;
+use std::process;
 
-const DIMS: Extent2D = Extent2D {
-    width: 768,
-    height: 768,
-};
+const TICKS_PER_SECOND: u32 = 60;
+
+const FONT_PIXEL_HEIGHT: f32 = 18.0;
+
+const INPUT_CONFIG_PATH: &str = "assets/input.cfg";
+
+/// Where the `export_trace` action writes the profiler history.
+const TRACE_PATH: &str = "hexthing-trace.json";
+
+/// Bindings used when `INPUT_CONFIG_PATH` can't be read.
+const DEFAULT_INPUT_CONFIG: &str = include_str!("../assets/input.cfg");
+
+const TILE_SIZE: u32 = 64;
+
+/// Tiles are 64x64, so five levels take them down to 4x4 while keeping the atlas padding (16
+/// texels per side) reasonable.
+const TILE_MIP_LEVELS: u8 = 5;
+
+/// Number of rings of hexes around the origin in the demo map.
+const MAP_RADIUS: i32 = 4;
+
+/// Placeholder units, drawn as debug circles, that overhang their tile and are picked through the
+/// ID buffer. Ids start at 1 since 0 means nothing was picked.
+const UNITS: [(u32, HexCoord); 2] = [(1, HexCoord { q: 1, r: 0 }), (2, HexCoord { q: -1, r: 1 })];
+
+/// Radius of a unit relative to its tile.
+const UNIT_SCALE: f32 = 1.3;
+
+const TILE_KINDS: [(&str, [u8; 3]); 4] = [
+    ("grass", [76, 153, 0]),
+    ("water", [0, 102, 204]),
+    ("sand", [230, 204, 128]),
+    ("rock", [128, 128, 128]),
+];
+
+struct HexApp {
+    /// Atlas regions of `TILE_KINDS`, in the same order.
+    tile_rects: Vec<UvRect>,
+    selected: Option<HexCoord>,
+    picked_unit: Option<u32>,
+    show_grid: bool,
+    show_unit_outlines: bool,
+    vsync: bool,
+    /// Set by `update` when the present mode should change, applied in `render`.
+    present_modes: Option<Vec<PresentMode>>,
+    /// Set by `update`, applied in `render` like `present_modes`.
+    toggle_profiler: bool,
+    export_trace: bool,
+    /// From `Config::font`.
+    font: Option<String>,
+}
+
+/// Placeholder tile art until real textures are loaded from disk: a two tone checkerboard in the
+/// tile's base color.
+fn checker_tile(base: [u8; 3]) -> Vec<u8> {
+    let mut pixels = Vec::with_capacity((TILE_SIZE * TILE_SIZE * 4) as usize);
+    for y in 0..TILE_SIZE {
+        for x in 0..TILE_SIZE {
+            let shade = if (x / 8 + y / 8) % 2 == 0 { 255 } else { 204 };
+            for &channel in &base {
+                pixels.push((u32::from(channel) * shade / 255) as u8);
+            }
+            pixels.push(255);
+        }
+    }
+    pixels
+}
+
+fn build_tile_atlas() -> Atlas {
+    let mut builder = AtlasBuilder::new(512).with_mip_levels(TILE_MIP_LEVELS);
+    for &(id, base) in &TILE_KINDS {
+        builder.add_tile(id, TILE_SIZE, TILE_SIZE, checker_tile(base));
+    }
+    builder.build()
+}
+
+impl App for HexApp {
+    fn init(&mut self, renderer: &mut RendererState, context: &mut Context) {
+        let input_config = fs::read_to_string(INPUT_CONFIG_PATH).unwrap_or_else(|err| {
+            println!(
+                "Using default bindings, can't read {}: {}",
+                INPUT_CONFIG_PATH, err
+            );
+            DEFAULT_INPUT_CONFIG.to_owned()
+        });
+        match InputMap::parse(&input_config) {
+            Ok(map) => context.input.map = map,
+            Err(err) => println!("Invalid bindings in {}: {}", INPUT_CONFIG_PATH, err),
+        }
+
+        let origin = HexCoord::new(0, 0);
+        let instances: Vec<_> = origin
+            .range(MAP_RADIUS)
+            .into_iter()
+            .map(|coord| {
+                let rect = self.tile_rects[coord.distance(origin) as usize % self.tile_rects.len()];
+                HexInstance {
+                    position: coord.to_world(),
+                    uv_rect: [rect.u, rect.v, rect.width, rect.height],
+                }
+            })
+            .collect();
+        renderer.set_hex_instances(&instances);
+        self.vsync = renderer.present_mode() == PresentMode::Fifo;
+        let units: Vec<_> = UNITS
+            .iter()
+            .map(|&(id, coord)| PickInstance {
+                position: coord.to_world(),
+                scale: UNIT_SCALE,
+                depth: Layer::Units.depth(),
+                id,
+            })
+            .collect();
+        renderer.set_pick_instances(&units);
+        renderer.camera_mut().half_height = MAP_RADIUS as f32 * 2.0 + 1.0;
+
+        match self.font {
+            Some(ref path) => match fs::read(path) {
+                Ok(font_data) => {
+                    if let Err(err) = renderer.load_font(font_data, FONT_PIXEL_HEIGHT) {
+                        println!("Failed to load font {}: {}", path, err);
+                    }
+                }
+                Err(err) => println!("Text disabled, can't read {}: {}", path, err),
+            },
+            None => println!("Text disabled, no font configured"),
+        }
+    }
+
+    fn render(&mut self, renderer: &mut RendererState, _alpha: f32) {
+        if let Some(present_modes) = self.present_modes.take() {
+            renderer.set_present_modes(&present_modes);
+            println!("Present mode: {:?}", renderer.present_mode());
+        }
+        if self.toggle_profiler {
+            self.toggle_profiler = false;
+            let enabled = !renderer.profiler_overlay();
+            renderer.set_profiler_overlay(enabled);
+        }
+        if self.export_trace {
+            self.export_trace = false;
+            match renderer.profiler().write_chrome_trace(TRACE_PATH) {
+                Ok(()) => println!("Wrote frame trace to {}", TRACE_PATH),
+                Err(err) => println!("Failed to export frame trace: {}", err),
+            }
+        }
+        renderer.set_grid_style(if self.show_grid {
+            Some(GridStyle::default())
+        } else {
+            None
+        });
+        renderer.draw_text("hexthing", [8.0, 8.0], [1.0, 1.0, 1.0, 1.0]);
+
+        if self.show_unit_outlines {
+            for &(id, coord) in &UNITS {
+                let color = if Some(id) == self.picked_unit {
+                    [1.0, 1.0, 0.0, 1.0]
+                } else {
+                    [1.0, 0.0, 0.0, 1.0]
+                };
+                renderer
+                    .debug_draw_mut()
+                    .circle(coord.to_world(), UNIT_SCALE * 0.8, color);
+            }
+        }
+    }
+
+    fn update(&mut self, context: &mut Context, _dt: f32) -> Control {
+        if context.input.action_pressed("exit") {
+            return Control::Exit;
+        }
+        if context.input.action_pressed("toggle_grid") {
+            self.show_grid = !self.show_grid;
+        }
+        if context.input.action_pressed("toggle_unit_outlines") {
+            self.show_unit_outlines = !self.show_unit_outlines;
+        }
+        if context.input.action_pressed("toggle_vsync") {
+            self.vsync = !self.vsync;
+            self.present_modes = Some(if self.vsync {
+                vec![PresentMode::Fifo]
+            } else {
+                NO_VSYNC_PRESENT_MODES.to_vec()
+            });
+        }
+        if context.input.action_pressed("toggle_profiler") {
+            self.toggle_profiler = true;
+        }
+        if context.input.action_pressed("export_trace") {
+            self.export_trace = true;
+        }
+        if context.input.action_pressed("select") {
+            context.picking.selected = context.picking.hovered;
+        }
+        if context.input.action_pressed("clear_selection") {
+            context.picking.selected = None;
+        }
+
+        if context.picking.selected != self.selected {
+            self.selected = context.picking.selected;
+            if let Some(coord) = self.selected {
+                println!("Selected hex ({}, {})", coord.q, coord.r);
+            }
+        }
+        if context.picked_entity != self.picked_unit {
+            self.picked_unit = context.picked_entity;
+            if let Some(id) = self.picked_unit {
+                println!("Hovering unit {}", id);
+            }
+        }
+        Control::Continue
+    }
+}
 
 #[allow(clippy::unreadable_literal)]
 #[allow(clippy::excessive_precision)]
@@ -46,7 +278,24 @@ const DIMS: Extent2D = Extent2D {
     feature = "gl"
 ))]
 fn main() {
-    env_logger::init();
+    let config = match Config::from_args(env::args().skip(1)) {
+        Ok(Command::Run(config)) => config,
+        Ok(Command::Help) => {
+            println!("{}", config::USAGE);
+            return;
+        }
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    };
+
+    let mut logger = env_logger::Builder::new();
+    logger.filter_level(config.log_level().unwrap());
+    if let Ok(filters) = env::var("RUST_LOG") {
+        logger.parse(&filters);
+    }
+    logger.init();
 
     let _hex_definition = RenderableDefinition {
         id: "hex".to_owned(),
@@ -103,8 +352,29 @@ fn main() {
         Vertex::new(0.8660254037844387, -0.5),
     ];
 
-    let mut renderer_state = RendererState::new(DIMS, &quad);
-    renderer_state.mainloop();
+    let atlas = build_tile_atlas();
+    let mut app = HexApp {
+        tile_rects: TILE_KINDS
+            .iter()
+            .map(|&(id, _)| atlas.uv_rect(id).unwrap())
+            .collect(),
+        selected: None,
+        picked_unit: None,
+        show_grid: true,
+        show_unit_outlines: true,
+        vsync: true,
+        present_modes: None,
+        toggle_profiler: false,
+        export_trace: false,
+        font: config.font.clone(),
+    };
+
+    let mut renderer_state = RendererState::new(&config, &quad, &atlas, DepthMode::DepthStencil)
+        .unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            process::exit(1);
+        });
+    renderer_state.mainloop(&mut app, TICKS_PER_SECOND);
 }
 
 #[cfg(not(any(feature = "vulkan", feature = "dx12", feature = "metal")))]
@@ -0,0 +1,45 @@
+use hex::HexCoord;
+use nalgebra::Vector2;
+use rendering::Camera;
+
+/// Tracks which hex is under the cursor. `selected` is set by the application, usually to the
+/// hovered hex when the `select` action is pressed.
+#[derive(Debug, Default)]
+pub struct HexPicker {
+    /// Cursor position in physical pixels from the top left corner of the window.
+    cursor: Option<Vector2<f32>>,
+    pub cursor_world: Option<Vector2<f32>>,
+    pub hovered: Option<HexCoord>,
+    pub selected: Option<HexCoord>,
+}
+
+impl HexPicker {
+    /// Cursor position in physical pixels, if the cursor is inside the window.
+    pub fn cursor(&self) -> Option<Vector2<f32>> {
+        self.cursor
+    }
+
+    /// Updates the cursor position from window events. Positions reported by winit are logical,
+    /// `hidpi_factor` converts them to the physical pixels the surface is sized in.
+    pub fn handle_event(&mut self, event: &winit::WindowEvent, hidpi_factor: f64) {
+        match *event {
+            winit::WindowEvent::CursorMoved { position, .. } => {
+                let position = position.to_physical(hidpi_factor);
+                self.cursor = Some(Vector2::new(position.x as f32, position.y as f32));
+            }
+            winit::WindowEvent::CursorLeft { .. } => {
+                self.cursor = None;
+            }
+            _ => (),
+        }
+    }
+
+    /// Recomputes the hovered hex. Has to be called whenever the camera or surface size change as
+    /// well as after cursor movement.
+    pub fn update(&mut self, camera: &Camera, surface_size: Vector2<f32>) {
+        self.cursor_world = self
+            .cursor
+            .map(|cursor| camera.screen_to_world(cursor, surface_size));
+        self.hovered = self.cursor_world.map(HexCoord::from_world);
+    }
+}
@@ -1,31 +1,379 @@
-use hal::{self, PhysicalDevice};
+use config::AdapterOverride;
+use hal::adapter::DeviceType;
+use hal::{self, image, memory, PhysicalDevice, QueueFamily, Surface};
+use std::cmp::Reverse;
 
-use super::BackendImpl;
+use super::{BackendImpl, SurfaceImpl};
+
+/// What an adapter has to support to be considered at all.
+#[derive(Debug, Clone)]
+pub struct AdapterRequirements {
+    pub features: hal::Features,
+    /// Smallest acceptable maximum 2D image dimension, e.g. to fit the texture atlas.
+    pub min_texture_size: usize,
+}
+
+impl Default for AdapterRequirements {
+    fn default() -> Self {
+        AdapterRequirements {
+            features: hal::Features::empty(),
+            min_texture_size: 1,
+        }
+    }
+}
+
+/// Why an adapter was ruled out, or how well it fits. Scores compare by device type first and
+/// device local memory second.
+enum Suitability {
+    Unsuitable(String),
+    Score(u32, u64),
+}
+
+fn device_type_rank(device_type: &DeviceType) -> u32 {
+    match *device_type {
+        DeviceType::DiscreteGpu => 4,
+        DeviceType::IntegratedGpu => 3,
+        DeviceType::VirtualGpu => 2,
+        DeviceType::Other => 1,
+        DeviceType::Cpu => 0,
+    }
+}
+
+/// Total size of the heaps backing device local memory types.
+fn device_local_memory(properties: &hal::MemoryProperties) -> u64 {
+    let mut heaps: Vec<_> = properties
+        .memory_types
+        .iter()
+        .filter(|memory_type| {
+            memory_type
+                .properties
+                .contains(memory::Properties::DEVICE_LOCAL)
+        })
+        .map(|memory_type| memory_type.heap_index)
+        .collect();
+    heaps.sort();
+    heaps.dedup();
+    heaps
+        .into_iter()
+        .filter_map(|heap| properties.memory_heaps.get(heap))
+        .sum()
+}
+
+fn suitability(
+    adapter: &hal::Adapter<BackendImpl>,
+    surface: &SurfaceImpl,
+    requirements: &AdapterRequirements,
+) -> Suitability {
+    let can_present = adapter
+        .queue_families
+        .iter()
+        .any(|family| family.supports_graphics() && surface.supports_queue_family(family));
+    if !can_present {
+        return Suitability::Unsuitable(
+            "no graphics queue family can present to the window".to_owned(),
+        );
+    }
+
+    let missing_features = requirements.features - adapter.physical_device.features();
+    if !missing_features.is_empty() {
+        return Suitability::Unsuitable(format!("missing features {:?}", missing_features));
+    }
+
+    let limits = adapter.physical_device.limits();
+    if limits.max_texture_size < requirements.min_texture_size {
+        return Suitability::Unsuitable(format!(
+            "maximum texture size {} is below the required {}",
+            limits.max_texture_size, requirements.min_texture_size
+        ));
+    }
+
+    Suitability::Score(
+        device_type_rank(&adapter.info.device_type),
+        device_local_memory(&adapter.physical_device.memory_properties()),
+    )
+}
+
+/// Index of the best of `scores`, comparing the device type rank first and device local memory
+/// second, or `None` when every adapter is unsuitable. Ties go to the adapter reported first.
+fn best_adapter(scores: &[Option<(u32, u64)>]) -> Option<usize> {
+    scores
+        .iter()
+        .enumerate()
+        .filter_map(|(index, score)| score.map(|score| (index, score)))
+        .max_by_key(|&(index, score)| (score, Reverse(index)))
+        .map(|(index, _)| index)
+}
+
+fn describe(names: &[String], index: usize) -> String {
+    format!("{}: {}", index, names[index])
+}
+
+fn describe_all(names: &[String]) -> String {
+    (0..names.len())
+        .map(|index| describe(names, index))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Index of the adapter `adapter_override` names among the adapters called `names`. A name
+/// matches the first adapter whose name contains it, ignoring case.
+fn resolve_override(names: &[String], adapter_override: &AdapterOverride) -> Result<usize, String> {
+    match *adapter_override {
+        AdapterOverride::Index(index) if index < names.len() => Ok(index),
+        AdapterOverride::Index(index) => Err(format!(
+            "Adapter index {} is out of range, available adapters are {}",
+            index,
+            describe_all(names)
+        )),
+        AdapterOverride::Name(ref name) => {
+            let name = name.to_lowercase();
+            names
+                .iter()
+                .position(|adapter| adapter.to_lowercase().contains(&name))
+                .ok_or_else(|| {
+                    format!(
+                        "No adapter name contains `{}`, available adapters are {}",
+                        name,
+                        describe_all(names)
+                    )
+                })
+        }
+    }
+}
+
+/// The highest power of two not above `requested`, and at most 8, that is set in the `supported`
+/// sample count mask. 1 is always supported.
+fn clamp_samples(requested: image::NumSamples, supported: image::NumSamples) -> image::NumSamples {
+    let mut samples = requested.min(8).next_power_of_two();
+    if samples > requested {
+        samples >>= 1;
+    }
+    while samples > 1 && supported & samples == 0 {
+        samples >>= 1;
+    }
+    samples.max(1)
+}
 
 pub struct AdapterState {
     pub adapter: Option<hal::Adapter<BackendImpl>>,
     pub memory_types: Vec<hal::MemoryType>,
+    pub limits: hal::Limits,
 }
 
 impl AdapterState {
-    pub fn new(adapters: &mut Vec<hal::Adapter<BackendImpl>>) -> Self {
-        print!("Chosen: ");
+    /// Takes the adapter that best meets `requirements` out of `adapters`, or the one named by
+    /// `adapter_override` as long as it meets them too.
+    pub fn new(
+        adapters: &mut Vec<hal::Adapter<BackendImpl>>,
+        surface: &SurfaceImpl,
+        requirements: &AdapterRequirements,
+        adapter_override: Option<&AdapterOverride>,
+    ) -> Result<Self, String> {
+        if adapters.is_empty() {
+            return Err("No graphics adapters found".to_owned());
+        }
+
+        let candidates: Vec<_> = adapters
+            .iter()
+            .map(|adapter| suitability(adapter, surface, requirements))
+            .collect();
 
-        for adapter in adapters.iter() {
-            println!("{:?}", adapter.info);
+        let names: Vec<String> = adapters
+            .iter()
+            .map(|adapter| adapter.info.name.clone())
+            .collect();
+
+        for (index, (adapter, suitability)) in adapters.iter().zip(&candidates).enumerate() {
+            match *suitability {
+                Suitability::Unsuitable(ref reason) => info!(
+                    "Adapter {}: {:?} (unsuitable: {})",
+                    index, adapter.info, reason
+                ),
+                Suitability::Score(..) => info!("Adapter {}: {:?}", index, adapter.info),
+            }
         }
 
-        AdapterState::new_adapter(adapters.remove(0))
+        let chosen = match adapter_override {
+            Some(adapter_override) => {
+                let index = resolve_override(&names, adapter_override)?;
+                if let Suitability::Unsuitable(ref reason) = candidates[index] {
+                    return Err(format!(
+                        "Adapter {} can't be used: {}",
+                        describe(&names, index),
+                        reason
+                    ));
+                }
+                index
+            }
+            None => {
+                let scores: Vec<_> = candidates
+                    .iter()
+                    .map(|suitability| match *suitability {
+                        Suitability::Score(rank, memory) => Some((rank, memory)),
+                        Suitability::Unsuitable(_) => None,
+                    })
+                    .collect();
+                best_adapter(&scores).ok_or_else(|| {
+                    let reasons: Vec<_> = candidates
+                        .iter()
+                        .enumerate()
+                        .filter_map(|(index, suitability)| match *suitability {
+                            Suitability::Unsuitable(ref reason) => {
+                                Some(format!("\n  {}: {}", describe(&names, index), reason))
+                            }
+                            Suitability::Score(..) => None,
+                        })
+                        .collect();
+                    format!(
+                        "No adapter meets the renderer's requirements:{}",
+                        reasons.concat()
+                    )
+                })?
+            }
+        };
+
+        info!("Using adapter {}", describe(&names, chosen));
+        Ok(AdapterState::new_adapter(adapters.remove(chosen)))
     }
 
     fn new_adapter(adapter: hal::Adapter<BackendImpl>) -> Self {
         let memory_types = adapter.physical_device.memory_properties().memory_types;
         let limits = adapter.physical_device.limits();
-        println!("{:?}", limits);
+        debug!("{:?}", limits);
 
         AdapterState {
             adapter: Some(adapter),
             memory_types,
+            limits,
         }
     }
+
+    /// Clamps a requested MSAA sample count to the highest power of two not above it that the
+    /// device supports for both color and depth attachments.
+    pub fn supported_samples(&self, requested: image::NumSamples) -> image::NumSamples {
+        clamp_samples(
+            requested,
+            self.limits.framebuffer_color_samples_count
+                & self.limits.framebuffer_depth_samples_count,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|&name| name.to_owned()).collect()
+    }
+
+    #[test]
+    fn device_types_rank_discrete_first() {
+        let ranks: Vec<_> = [
+            DeviceType::DiscreteGpu,
+            DeviceType::IntegratedGpu,
+            DeviceType::VirtualGpu,
+            DeviceType::Other,
+            DeviceType::Cpu,
+        ]
+        .iter()
+        .map(device_type_rank)
+        .collect();
+        assert!(
+            ranks.windows(2).all(|pair| pair[0] > pair[1]),
+            "{:?}",
+            ranks
+        );
+    }
+
+    #[test]
+    fn best_adapter_by_rank_then_memory() {
+        let integrated = device_type_rank(&DeviceType::IntegratedGpu);
+        let discrete = device_type_rank(&DeviceType::DiscreteGpu);
+
+        // A discrete GPU wins over an integrated one with more memory.
+        assert_eq!(
+            best_adapter(&[Some((integrated, 16 << 30)), Some((discrete, 2 << 30))]),
+            Some(1)
+        );
+        // Between equal types the one with more device local memory wins.
+        assert_eq!(
+            best_adapter(&[
+                Some((discrete, 4 << 30)),
+                Some((discrete, 8 << 30)),
+                Some((integrated, 32 << 30)),
+            ]),
+            Some(1)
+        );
+        // Full ties keep the order the backend reported.
+        assert_eq!(
+            best_adapter(&[Some((discrete, 4 << 30)), Some((discrete, 4 << 30))]),
+            Some(0)
+        );
+    }
+
+    #[test]
+    fn best_adapter_skips_unsuitable() {
+        let discrete = device_type_rank(&DeviceType::DiscreteGpu);
+        let cpu = device_type_rank(&DeviceType::Cpu);
+        assert_eq!(best_adapter(&[None, Some((cpu, 0))]), Some(1));
+        assert_eq!(best_adapter(&[Some((cpu, 0)), None]), Some(0));
+        assert_eq!(best_adapter(&[None, None]), None);
+        assert_eq!(best_adapter(&[]), None);
+        assert_eq!(best_adapter(&[None, Some((discrete, 0))]), Some(1));
+    }
+
+    #[test]
+    fn override_by_index() {
+        let adapters = names(&["Intel HD", "GeForce GTX"]);
+        assert_eq!(
+            resolve_override(&adapters, &AdapterOverride::Index(1)),
+            Ok(1)
+        );
+
+        let err = resolve_override(&adapters, &AdapterOverride::Index(2)).unwrap_err();
+        assert_eq!(
+            err,
+            "Adapter index 2 is out of range, available adapters are 0: Intel HD, 1: GeForce GTX"
+        );
+    }
+
+    #[test]
+    fn override_by_name() {
+        let adapters = names(&["Intel HD", "NVIDIA GeForce GTX", "NVIDIA Quadro"]);
+        let by_name =
+            |name: &str| resolve_override(&adapters, &AdapterOverride::Name(name.to_owned()));
+        assert_eq!(by_name("geforce"), Ok(1));
+        assert_eq!(by_name("INTEL"), Ok(0));
+        // The first match wins.
+        assert_eq!(by_name("nvidia"), Ok(1));
+
+        let err = by_name("Radeon").unwrap_err();
+        assert!(
+            err.starts_with("No adapter name contains `radeon`"),
+            "{}",
+            err
+        );
+        assert!(err.ends_with("0: Intel HD, 1: NVIDIA GeForce GTX, 2: NVIDIA Quadro"));
+    }
+
+    #[test]
+    fn samples_round_down_to_powers_of_two() {
+        let all = 1 | 2 | 4 | 8;
+        assert_eq!(clamp_samples(0, all), 1);
+        assert_eq!(clamp_samples(1, all), 1);
+        assert_eq!(clamp_samples(3, all), 2);
+        assert_eq!(clamp_samples(4, all), 4);
+        assert_eq!(clamp_samples(6, all), 4);
+        assert_eq!(clamp_samples(8, all), 8);
+        assert_eq!(clamp_samples(16, all | 16), 8);
+    }
+
+    #[test]
+    fn samples_fall_back_to_supported_counts() {
+        assert_eq!(clamp_samples(8, 1 | 2 | 4), 4);
+        assert_eq!(clamp_samples(8, 1 | 2), 2);
+        assert_eq!(clamp_samples(4, 1 | 8), 1);
+        assert_eq!(clamp_samples(4, 0), 1);
+    }
 }
@@ -0,0 +1,250 @@
+use fnv::FnvHashMap;
+
+const BYTES_PER_PIXEL: usize = 4;
+
+/// Normalized texture coordinates of a tile inside an atlas: `(u, v)` of the top left corner
+/// followed by the width and height.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct UvRect {
+    pub u: f32,
+    pub v: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PixelRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+struct PendingTile {
+    id: String,
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+}
+
+/// Collects RGBA tile images and packs them into a single texture using shelf packing: tiles are
+/// sorted by height and placed left to right in rows, starting a new row when the current one is
+/// full.
+pub struct AtlasBuilder {
+    max_width: u32,
+    padding: u32,
+    alignment: u32,
+    mip_levels: u8,
+    tiles: Vec<PendingTile>,
+}
+
+pub struct Atlas {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+    /// Number of mip levels that can be generated without tiles bleeding into each other.
+    pub mip_levels: u8,
+    rects: FnvHashMap<String, PixelRect>,
+}
+
+impl AtlasBuilder {
+    pub fn new(max_width: u32) -> Self {
+        AtlasBuilder {
+            max_width,
+            padding: 0,
+            alignment: 1,
+            mip_levels: 1,
+            tiles: Vec::new(),
+        }
+    }
+
+    /// Border left around every tile so that filtering doesn't pick up texels from its neighbors.
+    /// The border is filled by extending the tile's edge texels outwards.
+    pub fn with_padding(mut self, padding: u32) -> Self {
+        self.padding = self.padding.max(padding);
+        self
+    }
+
+    /// Pads and aligns tiles so that `levels` mip levels can be generated by box filtering. At
+    /// level `n` every `2^n` texel block must belong to a single tile, so tiles and their borders
+    /// are placed on `2^(levels - 1)` boundaries and get a border of at least that many texels,
+    /// which leaves a one texel border around each tile on the smallest level.
+    pub fn with_mip_levels(mut self, levels: u8) -> Self {
+        assert!(levels >= 1);
+        self.mip_levels = levels;
+        self.alignment = 1 << (levels - 1);
+        self.padding = self.padding.max(self.alignment);
+        self
+    }
+
+    pub fn add_tile(&mut self, id: &str, width: u32, height: u32, pixels: Vec<u8>) {
+        assert_eq!(
+            pixels.len(),
+            width as usize * height as usize * BYTES_PER_PIXEL
+        );
+        assert!(
+            width > 0 && height > 0 && width + 2 * self.padding <= self.max_width,
+            "Tile {} is wider than the atlas",
+            id
+        );
+        self.tiles.push(PendingTile {
+            id: id.to_owned(),
+            width,
+            height,
+            pixels,
+        });
+    }
+
+    pub fn build(mut self) -> Atlas {
+        self.tiles.sort_by(|a, b| b.height.cmp(&a.height));
+
+        let padding = self.padding;
+        let alignment = self.alignment;
+        let align = |value: u32| (value + alignment - 1) / alignment * alignment;
+
+        let mut rects = FnvHashMap::default();
+        let (mut x, mut y, mut row_height, mut width) = (0, 0, 0, 0);
+        for tile in &self.tiles {
+            let padded_width = align(tile.width + 2 * padding);
+            let padded_height = align(tile.height + 2 * padding);
+            if x + padded_width > self.max_width {
+                x = 0;
+                y += row_height;
+                row_height = 0;
+            }
+
+            rects.insert(
+                tile.id.clone(),
+                PixelRect {
+                    x: x + padding,
+                    y: y + padding,
+                    width: tile.width,
+                    height: tile.height,
+                },
+            );
+
+            x += padded_width;
+            width = width.max(x);
+            row_height = row_height.max(padded_height);
+        }
+
+        let width = width.max(1);
+        let height = (y + row_height).max(1);
+        let mut pixels = vec![0u8; width as usize * height as usize * BYTES_PER_PIXEL];
+        for tile in &self.tiles {
+            let rect = rects[&tile.id];
+            let left = rect.x - padding;
+            let top = rect.y - padding;
+            let right = (rect.x + tile.width + padding).min(width);
+            let bottom = (rect.y + tile.height + padding).min(height);
+            for y in top..bottom {
+                let src_y = (y.max(rect.y) - rect.y).min(tile.height - 1);
+                for x in left..right {
+                    let src_x = (x.max(rect.x) - rect.x).min(tile.width - 1);
+                    let src = (src_y * tile.width + src_x) as usize * BYTES_PER_PIXEL;
+                    let dst = (y * width + x) as usize * BYTES_PER_PIXEL;
+                    pixels[dst..dst + BYTES_PER_PIXEL]
+                        .copy_from_slice(&tile.pixels[src..src + BYTES_PER_PIXEL]);
+                }
+            }
+        }
+
+        Atlas {
+            width,
+            height,
+            pixels,
+            mip_levels: self.mip_levels,
+            rects,
+        }
+    }
+}
+
+impl Atlas {
+    pub fn pixel_rect(&self, id: &str) -> Option<PixelRect> {
+        self.rects.get(id).cloned()
+    }
+
+    pub fn uv_rect(&self, id: &str) -> Option<UvRect> {
+        self.rects.get(id).map(|rect| UvRect {
+            u: rect.x as f32 / self.width as f32,
+            v: rect.y as f32 / self.height as f32,
+            width: rect.width as f32 / self.width as f32,
+            height: rect.height as f32 / self.height as f32,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tile(width: u32, height: u32, value: u8) -> Vec<u8> {
+        vec![value; width as usize * height as usize * BYTES_PER_PIXEL]
+    }
+
+    #[test]
+    fn mip_levels_align_and_pad_tiles() {
+        let mut builder = AtlasBuilder::new(256).with_mip_levels(4);
+        builder.add_tile("a", 20, 12, tile(20, 12, 1));
+        builder.add_tile("b", 8, 8, tile(8, 8, 2));
+        let atlas = builder.build();
+        assert_eq!(atlas.mip_levels, 4);
+
+        // Tiles get an 8 texel border and start and end on 8 texel boundaries together with it.
+        for id in &["a", "b"] {
+            let rect = atlas.pixel_rect(id).unwrap();
+            assert_eq!((rect.x - 8) % 8, 0, "{} is misaligned", id);
+            assert_eq!((rect.y - 8) % 8, 0, "{} is misaligned", id);
+        }
+        let a = atlas.pixel_rect("a").unwrap();
+        let b = atlas.pixel_rect("b").unwrap();
+        assert_eq!((a.x, a.y), (8, 8));
+        // 20 + 2 * 8 rounded up to 40.
+        assert_eq!((b.x, b.y), (48, 8));
+        assert_eq!(atlas.width % 8, 0);
+        assert_eq!(atlas.height % 8, 0);
+    }
+
+    #[test]
+    fn padding_extends_edge_texels() {
+        let mut builder = AtlasBuilder::new(64).with_padding(2);
+        let mut pixels = tile(2, 1, 0);
+        pixels[..BYTES_PER_PIXEL].copy_from_slice(&[10, 10, 10, 10]);
+        pixels[BYTES_PER_PIXEL..].copy_from_slice(&[20, 20, 20, 20]);
+        builder.add_tile("a", 2, 1, pixels);
+        let atlas = builder.build();
+        assert_eq!((atlas.width, atlas.height), (6, 5));
+
+        let texel = |x: u32, y: u32| atlas.pixels[(y * atlas.width + x) as usize * BYTES_PER_PIXEL];
+        for y in 0..atlas.height {
+            assert_eq!(texel(0, y), 10);
+            assert_eq!(texel(2, y), 10);
+            assert_eq!(texel(3, y), 20);
+            assert_eq!(texel(5, y), 20);
+        }
+    }
+
+    #[test]
+    fn larger_mip_padding_wins_over_smaller_explicit_padding() {
+        let builder = AtlasBuilder::new(64).with_padding(1).with_mip_levels(3);
+        assert_eq!(builder.padding, 4);
+        assert_eq!(builder.alignment, 4);
+    }
+
+    #[test]
+    fn full_rows_wrap() {
+        let mut builder = AtlasBuilder::new(32).with_mip_levels(2);
+        for id in &["a", "b", "c"] {
+            builder.add_tile(id, 10, 10, tile(10, 10, 0));
+        }
+        let atlas = builder.build();
+        // Each tile takes 14 texels, so two fit in a row.
+        let rows: Vec<_> = ["a", "b", "c"]
+            .iter()
+            .map(|id| atlas.pixel_rect(id).unwrap().y)
+            .collect();
+        assert_eq!(rows.iter().filter(|&&y| y == 2).count(), 2);
+        assert_eq!(rows.iter().filter(|&&y| y == 16).count(), 1);
+        assert_eq!(atlas.height, 28);
+    }
+}
@@ -1,35 +1,66 @@
+use config::{AdapterOverride, WindowConfig};
 use hal::{window, Instance};
 
-use super::{AdapterState, SurfaceImpl};
+use super::{AdapterRequirements, AdapterState, SurfaceImpl};
 
 pub struct BackendState {
     pub adapter: AdapterState,
     pub surface: SurfaceImpl,
     pub events_loop: winit::EventsLoop,
-    _window: winit::Window,
+    pub window: winit::Window,
 }
 
 impl BackendState {
-    pub fn new(window_dimensions: window::Extent2D) -> Self {
-        let instance = back::Instance::create("gfx-rs quad", 1);
+    pub fn new(
+        config: &WindowConfig,
+        requirements: &AdapterRequirements,
+        adapter_override: Option<&AdapterOverride>,
+    ) -> Result<Self, String> {
+        let instance = back::Instance::create(&config.title, 1);
         let events_loop = winit::EventsLoop::new();
 
+        let fullscreen = if config.fullscreen {
+            Some(events_loop.get_primary_monitor())
+        } else {
+            None
+        };
         let window = winit::WindowBuilder::new()
             .with_dimensions(winit::dpi::LogicalSize::new(
-                f64::from(window_dimensions.width),
-                f64::from(window_dimensions.height),
+                f64::from(config.width),
+                f64::from(config.height),
             ))
-            .with_title("quad".to_owned())
+            .with_fullscreen(fullscreen)
+            .with_title(config.title.clone())
             .build(&events_loop)
-            .unwrap();
+            .map_err(|err| format!("Can't create window: {}", err))?;
 
         let surface = instance.create_surface(&window);
         let mut adapters = instance.enumerate_adapters();
-        BackendState {
-            adapter: AdapterState::new(&mut adapters),
+        Ok(BackendState {
+            adapter: AdapterState::new(&mut adapters, &surface, requirements, adapter_override)?,
             surface,
             events_loop,
-            _window: window,
+            window,
+        })
+    }
+
+    /// Converts a logical window size to the physical pixels the surface is sized in.
+    pub fn physical_extent(&self, size: winit::dpi::LogicalSize) -> window::Extent2D {
+        let size = size.to_physical(self.window.get_hidpi_factor());
+        window::Extent2D {
+            width: size.width.round() as u32,
+            height: size.height.round() as u32,
+        }
+    }
+
+    /// Current size of the window's client area in physical pixels, zero while minimized.
+    pub fn window_extent(&self) -> window::Extent2D {
+        match self.window.get_inner_size() {
+            Some(size) => self.physical_extent(size),
+            None => window::Extent2D {
+                width: 0,
+                height: 0,
+            },
         }
     }
 }
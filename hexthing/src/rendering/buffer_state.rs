@@ -1,14 +1,13 @@
-use super::{BufferImpl, DeviceState, MemoryImpl};
-use hal::{self, buffer, memory, Device};
-use std::cell::RefCell;
+use super::{BufferImpl, DeviceState, MemoryImpl, PendingUpload, UploadPools};
+use hal::{self, buffer, memory, pso, Device};
 use std::mem::size_of;
-use std::rc::Rc;
+use std::sync::Arc;
 
 pub struct BufferState {
     memory: Option<MemoryImpl>,
     buffer: Option<BufferImpl>,
-    device: Rc<RefCell<DeviceState>>,
-    _size: u64,
+    device: Arc<DeviceState>,
+    size: u64,
 }
 
 impl BufferState {
@@ -17,7 +16,7 @@ impl BufferState {
     }
 
     pub fn new<T>(
-        device_ptr: Rc<RefCell<DeviceState>>,
+        device_ptr: Arc<DeviceState>,
         data_source: &[T],
         usage: buffer::Usage,
         memory_types: &[hal::MemoryType],
@@ -33,7 +32,7 @@ impl BufferState {
         let upload_size = data_source.len() as u64 * stride;
 
         {
-            let device = &device_ptr.borrow().device;
+            let device = &device_ptr.device;
 
             let unbound = device.create_buffer(upload_size, usage).unwrap();
             let mem_req = device.get_buffer_requirements(&unbound);
@@ -73,32 +72,161 @@ impl BufferState {
             memory: Some(memory),
             buffer: Some(buffer),
             device: device_ptr,
-            _size: size,
+            size,
         }
     }
 
-    fn _update_data<T>(&mut self, offset: u64, data_source: &[T])
+    /// Creates a buffer in device local memory, filled from a staging buffer on the transfer
+    /// queue. Meant for data that doesn't change after creation: the memory may not be mappable,
+    /// so `update_data` and `read_data` can't be used on the result.
+    ///
+    /// The buffer can't be used before the returned upload has been submitted, see
+    /// `DeviceState::upload`.
+    pub fn device_local<T>(
+        device_ptr: Arc<DeviceState>,
+        upload_pools: &mut UploadPools,
+        data_source: &[T],
+        usage: buffer::Usage,
+        memory_types: &[hal::MemoryType],
+    ) -> (Self, PendingUpload)
+    where
+        T: Copy,
+    {
+        let staging = BufferState::new(
+            Arc::clone(&device_ptr),
+            data_source,
+            buffer::Usage::TRANSFER_SRC,
+            memory_types,
+        );
+
+        let memory: MemoryImpl;
+        let buffer: BufferImpl;
+        let size = data_source.len() as u64 * size_of::<T>() as u64;
+
+        {
+            let device = &device_ptr.device;
+
+            let unbound = device
+                .create_buffer(size, usage | buffer::Usage::TRANSFER_DST)
+                .unwrap();
+            let mem_req = device.get_buffer_requirements(&unbound);
+
+            let device_type = memory_types
+                .iter()
+                .enumerate()
+                .position(|(id, mem_type)| {
+                    mem_req.type_mask & (1 << id) != 0
+                        && mem_type
+                            .properties
+                            .contains(memory::Properties::DEVICE_LOCAL)
+                })
+                .unwrap()
+                .into();
+
+            memory = device.allocate_memory(device_type, mem_req.size).unwrap();
+            buffer = device.bind_buffer_memory(&memory, 0, unbound).unwrap();
+        }
+
+        let read_access = buffer::Access::VERTEX_BUFFER_READ
+            | buffer::Access::INDEX_BUFFER_READ
+            | buffer::Access::UNIFORM_READ;
+        let upload = device_ptr.upload(
+            upload_pools,
+            staging,
+            |cmd_buffer, staging, families| {
+                cmd_buffer.copy_buffer(
+                    staging.get_buffer(),
+                    &buffer,
+                    &[hal::command::BufferCopy {
+                        src: 0,
+                        dst: 0,
+                        size,
+                    }],
+                );
+                if families.is_some() {
+                    cmd_buffer.pipeline_barrier(
+                        pso::PipelineStage::TRANSFER..pso::PipelineStage::BOTTOM_OF_PIPE,
+                        memory::Dependencies::empty(),
+                        &[memory::Barrier::Buffer {
+                            states: buffer::Access::TRANSFER_WRITE..buffer::Access::empty(),
+                            target: &buffer,
+                            families: families.clone(),
+                            range: None..None,
+                        }],
+                    );
+                }
+            },
+            |cmd_buffer, families| {
+                let src_access = if families.is_some() {
+                    buffer::Access::empty()
+                } else {
+                    buffer::Access::TRANSFER_WRITE
+                };
+                cmd_buffer.pipeline_barrier(
+                    pso::PipelineStage::TRANSFER
+                        ..pso::PipelineStage::VERTEX_INPUT | pso::PipelineStage::VERTEX_SHADER,
+                    memory::Dependencies::empty(),
+                    &[memory::Barrier::Buffer {
+                        states: src_access..read_access,
+                        target: &buffer,
+                        families: families.clone(),
+                        range: None..None,
+                    }],
+                );
+            },
+        );
+
+        let buffer = BufferState {
+            memory: Some(memory),
+            buffer: Some(buffer),
+            device: device_ptr,
+            size,
+        };
+        (buffer, upload)
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn update_data<T>(&mut self, offset: u64, data_source: &[T])
     where
         T: Copy,
     {
-        let device = &self.device.borrow().device;
+        let device = &self.device.device;
 
         let stride = size_of::<T>() as u64;
         let upload_size = data_source.len() as u64 * stride;
 
-        assert!(offset + upload_size <= self._size);
+        assert!(offset + upload_size <= self.size);
 
         let mut data_target = device
-            .acquire_mapping_writer::<T>(self.memory.as_ref().unwrap(), offset..self._size)
+            .acquire_mapping_writer::<T>(self.memory.as_ref().unwrap(), offset..self.size)
             .unwrap();
         data_target[0..data_source.len()].copy_from_slice(data_source);
         device.release_mapping_writer(data_target).unwrap();
     }
+
+    /// Copies the first `count` elements out of the buffer. The caller is responsible for making
+    /// sure the GPU is done writing to it.
+    pub fn read_data<T>(&self, count: usize) -> Vec<T>
+    where
+        T: Copy,
+    {
+        let device = &self.device.device;
+
+        let data_source = device
+            .acquire_mapping_reader::<T>(self.memory.as_ref().unwrap(), 0..self.size)
+            .unwrap();
+        let data = data_source[0..count].to_vec();
+        device.release_mapping_reader(data_source);
+        data
+    }
 }
 
 impl Drop for BufferState {
     fn drop(&mut self) {
-        let device = &self.device.borrow().device;
+        let device = &self.device.device;
         device.destroy_buffer(self.buffer.take().unwrap());
         device.free_memory(self.memory.take().unwrap());
     }
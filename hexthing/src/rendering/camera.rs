@@ -0,0 +1,74 @@
+use nalgebra::{Matrix4, Vector2, Vector3, Vector4};
+
+/// A 2D orthographic camera looking at the hex plane.
+#[derive(Debug, Copy, Clone)]
+pub struct Camera {
+    pub position: Vector2<f32>,
+    /// Half the height of the visible area, in world units.
+    pub half_height: f32,
+    aspect: f32,
+}
+
+impl Camera {
+    pub fn new(position: Vector2<f32>, half_height: f32) -> Self {
+        Camera {
+            position,
+            half_height,
+            aspect: 1.0,
+        }
+    }
+
+    /// Width divided by height of the surface the camera renders to.
+    pub fn set_aspect(&mut self, aspect: f32) {
+        self.aspect = aspect;
+    }
+
+    pub fn aspect(&self) -> f32 {
+        self.aspect
+    }
+
+    /// Maps world space to clip space, with `+y` pointing down the screen.
+    pub fn view_projection(&self) -> Matrix4<f32> {
+        let scale = Vector3::new(
+            1.0 / (self.half_height * self.aspect),
+            1.0 / self.half_height,
+            1.0,
+        );
+        let translation = Vector3::new(-self.position.x, -self.position.y, 0.0);
+        Matrix4::new_nonuniform_scaling(&scale) * Matrix4::new_translation(&translation)
+    }
+
+    /// Converts a position in normalized device coordinates (`-1..1` on both axes, `+y` down) back
+    /// into world space.
+    pub fn ndc_to_world(&self, ndc: Vector2<f32>) -> Vector2<f32> {
+        let inverse = self
+            .view_projection()
+            .try_inverse()
+            .expect("Camera projection is not invertible");
+        let world = inverse * Vector4::new(ndc.x, ndc.y, 0.0, 1.0);
+        Vector2::new(world.x, world.y)
+    }
+
+    /// Converts a position in physical pixels from the top left corner of a surface of
+    /// `surface_size` pixels into world space.
+    pub fn screen_to_world(
+        &self,
+        position: Vector2<f32>,
+        surface_size: Vector2<f32>,
+    ) -> Vector2<f32> {
+        let ndc = Vector2::new(
+            position.x / surface_size.x * 2.0 - 1.0,
+            position.y / surface_size.y * 2.0 - 1.0,
+        );
+        self.ndc_to_world(ndc)
+    }
+
+    /// Push constant words for the `view_proj` matrix shared by the world space shaders.
+    pub fn push_constants(&self) -> [u32; 16] {
+        let mut constants = [0u32; 16];
+        for (constant, value) in constants.iter_mut().zip(self.view_projection().as_slice()) {
+            *constant = value.to_bits();
+        }
+        constants
+    }
+}
@@ -0,0 +1,122 @@
+use super::PipelineConfig;
+use hal::{self, format, pso};
+use nalgebra::Vector2;
+use std::f32::consts::PI;
+use std::mem::size_of;
+
+const CIRCLE_SEGMENTS: usize = 24;
+
+#[derive(Debug, Copy, Clone)]
+#[repr(C)]
+pub struct DebugVertex {
+    pub position: [f32; 2],
+    pub color: [f32; 4],
+}
+
+/// Collects line primitives for a single frame. Everything is expressed as a line list in the same
+/// coordinate space as the hex instances, and the renderer clears the collector once the frame has
+/// been uploaded.
+#[derive(Default)]
+pub struct DebugDraw {
+    vertices: Vec<DebugVertex>,
+}
+
+impl DebugDraw {
+    pub fn clear(&mut self) {
+        self.vertices.clear();
+    }
+
+    pub fn vertices(&self) -> &[DebugVertex] {
+        &self.vertices
+    }
+
+    pub fn line(&mut self, from: Vector2<f32>, to: Vector2<f32>, color: [f32; 4]) {
+        self.vertices.push(DebugVertex {
+            position: [from.x, from.y],
+            color,
+        });
+        self.vertices.push(DebugVertex {
+            position: [to.x, to.y],
+            color,
+        });
+    }
+
+    /// Draws a closed polygon through `points`.
+    pub fn polygon(&mut self, points: &[Vector2<f32>], color: [f32; 4]) {
+        for (i, &from) in points.iter().enumerate() {
+            let to = points[(i + 1) % points.len()];
+            self.line(from, to, color);
+        }
+    }
+
+    pub fn circle(&mut self, center: Vector2<f32>, radius: f32, color: [f32; 4]) {
+        let points: Vec<_> = (0..CIRCLE_SEGMENTS)
+            .map(|i| {
+                let angle = i as f32 / CIRCLE_SEGMENTS as f32 * 2.0 * PI;
+                center + Vector2::new(angle.cos(), angle.sin()) * radius
+            })
+            .collect();
+        self.polygon(&points, color);
+    }
+
+    /// Outlines a pointy top hex with the given circumradius, matching the tile mesh.
+    pub fn hex_outline(&mut self, center: Vector2<f32>, radius: f32, color: [f32; 4]) {
+        let points: Vec<_> = (0..6)
+            .map(|i| {
+                let angle = PI / 6.0 + i as f32 * PI / 3.0;
+                center + Vector2::new(angle.cos(), angle.sin()) * radius
+            })
+            .collect();
+        self.polygon(&points, color);
+    }
+
+    /// Marks a point with a small cross `size` units across.
+    pub fn point(&mut self, position: Vector2<f32>, size: f32, color: [f32; 4]) {
+        let half = size * 0.5;
+        self.line(
+            position - Vector2::new(half, half),
+            position + Vector2::new(half, half),
+            color,
+        );
+        self.line(
+            position - Vector2::new(half, -half),
+            position + Vector2::new(half, -half),
+            color,
+        );
+    }
+}
+
+/// Debug lines are drawn on top of everything else, so depth is never tested or written.
+pub fn pipeline_config() -> PipelineConfig {
+    PipelineConfig {
+        vertex_shader: "src/shaders/debug.vert".to_owned(),
+        fragment_shader: "src/shaders/debug.frag".to_owned(),
+        primitive: hal::Primitive::LineList,
+        blend: pso::BlendState::ALPHA,
+        depth_stencil: pso::DepthStencilDesc::default(),
+        push_constants: vec![(pso::ShaderStageFlags::VERTEX, 0..64)],
+        vertex_buffers: vec![pso::VertexBufferDesc {
+            binding: 0,
+            stride: size_of::<DebugVertex>() as u32,
+            rate: 0,
+        }],
+        attributes: vec![
+            pso::AttributeDesc {
+                location: 0,
+                binding: 0,
+                element: pso::Element {
+                    format: format::Format::Rg32Float,
+                    offset: 0,
+                },
+            },
+            pso::AttributeDesc {
+                location: 1,
+                binding: 0,
+                element: pso::Element {
+                    format: format::Format::Rgba32Float,
+                    offset: 8,
+                },
+            },
+        ],
+    }
+}
@@ -0,0 +1,119 @@
+use hal::{format, pso};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DepthMode {
+    None,
+    Depth,
+    DepthStencil,
+}
+
+impl DepthMode {
+    /// Formats to try in order of preference. Not every device supports every depth format as an
+    /// optimally tiled attachment, so the first supported one wins.
+    pub fn candidate_formats(self) -> &'static [format::Format] {
+        match self {
+            DepthMode::None => &[],
+            DepthMode::Depth => &[
+                format::Format::D32Float,
+                format::Format::D24UnormS8Uint,
+                format::Format::D16Unorm,
+            ],
+            DepthMode::DepthStencil => &[
+                format::Format::D24UnormS8Uint,
+                format::Format::D32FloatS8Uint,
+                format::Format::D16UnormS8Uint,
+            ],
+        }
+    }
+}
+
+pub fn aspects_of(format: format::Format) -> format::Aspects {
+    match format {
+        format::Format::D24UnormS8Uint
+        | format::Format::D32FloatS8Uint
+        | format::Format::D16UnormS8Uint => format::Aspects::DEPTH | format::Aspects::STENCIL,
+        _ => format::Aspects::DEPTH,
+    }
+}
+
+/// Draw layers, back to front. Each layer is drawn at a fixed depth so that later layers always
+/// end up on top regardless of submission order.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Layer {
+    Terrain,
+    Units,
+    Effects,
+    Ui,
+}
+
+impl Layer {
+    pub fn depth(self) -> f32 {
+        match self {
+            Layer::Terrain => 0.8,
+            Layer::Units => 0.6,
+            Layer::Effects => 0.4,
+            Layer::Ui => 0.2,
+        }
+    }
+}
+
+pub fn depth_test() -> pso::DepthStencilDesc {
+    pso::DepthStencilDesc {
+        depth: pso::DepthTest::On {
+            fun: pso::Comparison::LessEqual,
+            write: true,
+        },
+        depth_bounds: false,
+        stencil: pso::StencilTest::Off,
+    }
+}
+
+/// Stencil value marking the tiles that are currently visible, outside of which fog is drawn.
+pub const VISIBLE: pso::StencilValue = 1;
+
+/// Whether attachments of `format` have a stencil aspect to write and test against.
+pub fn has_stencil(format: format::Format) -> bool {
+    aspects_of(format).contains(format::Aspects::STENCIL)
+}
+
+/// Depth test that additionally writes `reference` into the stencil buffer wherever a fragment
+/// passes. Used to mark regions such as the currently visible tiles.
+pub fn stencil_write(reference: pso::StencilValue) -> pso::DepthStencilDesc {
+    let face = pso::StencilFace {
+        fun: pso::Comparison::Always,
+        mask_read: pso::State::Static(!0),
+        mask_write: pso::State::Static(!0),
+        op_fail: pso::StencilOp::Keep,
+        op_depth_fail: pso::StencilOp::Keep,
+        op_pass: pso::StencilOp::Replace,
+        reference: pso::State::Static(reference),
+    };
+    pso::DepthStencilDesc {
+        stencil: pso::StencilTest::On {
+            front: face,
+            back: face,
+        },
+        ..depth_test()
+    }
+}
+
+/// Depth test that only lets fragments through where the stencil buffer does not equal
+/// `reference`, e.g. to draw fog over everything that was not marked by `stencil_write`.
+pub fn stencil_mask(reference: pso::StencilValue) -> pso::DepthStencilDesc {
+    let face = pso::StencilFace {
+        fun: pso::Comparison::NotEqual,
+        mask_read: pso::State::Static(!0),
+        mask_write: pso::State::Static(0),
+        op_fail: pso::StencilOp::Keep,
+        op_depth_fail: pso::StencilOp::Keep,
+        op_pass: pso::StencilOp::Keep,
+        reference: pso::State::Static(reference),
+    };
+    pso::DepthStencilDesc {
+        stencil: pso::StencilTest::On {
+            front: face,
+            back: face,
+        },
+        ..depth_test()
+    }
+}
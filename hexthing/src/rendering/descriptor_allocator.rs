@@ -0,0 +1,134 @@
+use super::{DescriptorPoolImpl, DescriptorSetImpl, DescriptorSetLayoutImpl, DeviceImpl};
+use fnv::FnvHashMap;
+use hal::{pso, DescriptorPool, Device};
+
+/// Sets in the first pool of each mix. Every further pool holds twice as many as the previous one,
+/// up to `MAX_SETS_PER_POOL`.
+const INITIAL_SETS_PER_POOL: usize = 8;
+const MAX_SETS_PER_POOL: usize = 1024;
+
+/// Descriptor counts per type needed by one set, sorted by type.
+type DescriptorMix = Vec<(pso::DescriptorType, usize)>;
+
+/// Identifies the pool a set was allocated from, needed to free it again.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct PoolId {
+    mix: usize,
+    pool: usize,
+}
+
+struct Pool {
+    pool: DescriptorPoolImpl,
+    capacity: usize,
+    allocated: usize,
+    /// Set when an allocation failed below capacity, which fragmentation can cause. Cleared when a
+    /// set is freed.
+    exhausted: bool,
+}
+
+/// Hands out descriptor sets from pools that are created on demand.
+///
+/// Sets that need the same number of descriptors of each type share pools sized for exactly that
+/// mix, so a pool never runs out of one descriptor type while it still has room for sets. When all
+/// pools of a mix are full, a larger one is added.
+#[derive(Default)]
+pub struct DescriptorAllocator {
+    mixes: FnvHashMap<DescriptorMix, usize>,
+    pools: Vec<Vec<Pool>>,
+}
+
+impl DescriptorAllocator {
+    /// Allocates a set with `layout`, which has to have been created from `bindings`.
+    pub fn allocate(
+        &mut self,
+        device: &DeviceImpl,
+        layout: &DescriptorSetLayoutImpl,
+        bindings: &[pso::DescriptorSetLayoutBinding],
+    ) -> (DescriptorSetImpl, PoolId) {
+        let mix = descriptor_mix(bindings);
+        let mix_index = match self.mixes.get(&mix) {
+            Some(&index) => index,
+            None => {
+                self.pools.push(Vec::new());
+                self.mixes.insert(mix.clone(), self.pools.len() - 1);
+                self.pools.len() - 1
+            }
+        };
+        let pools = &mut self.pools[mix_index];
+
+        for (index, pool) in pools.iter_mut().enumerate() {
+            if pool.exhausted || pool.allocated == pool.capacity {
+                continue;
+            }
+            match pool.pool.allocate_set(layout) {
+                Ok(set) => {
+                    pool.allocated += 1;
+                    return (
+                        set,
+                        PoolId {
+                            mix: mix_index,
+                            pool: index,
+                        },
+                    );
+                }
+                Err(_) => pool.exhausted = true,
+            }
+        }
+
+        let capacity = (INITIAL_SETS_PER_POOL << pools.len()).min(MAX_SETS_PER_POOL);
+        let ranges: Vec<_> = mix
+            .iter()
+            .map(|&(ty, count)| pso::DescriptorRangeDesc {
+                ty,
+                count: count * capacity,
+            })
+            .collect();
+        let mut pool = device
+            .create_descriptor_pool(capacity, &ranges)
+            .expect("Can't create descriptor pool");
+        let set = pool
+            .allocate_set(layout)
+            .expect("Can't allocate from a new descriptor pool");
+        pools.push(Pool {
+            pool,
+            capacity,
+            allocated: 1,
+            exhausted: false,
+        });
+
+        (
+            set,
+            PoolId {
+                mix: mix_index,
+                pool: pools.len() - 1,
+            },
+        )
+    }
+
+    /// Returns `set` to the pool it was allocated from. The GPU must be done using it.
+    pub fn free(&mut self, set: DescriptorSetImpl, id: PoolId) {
+        let pool = &mut self.pools[id.mix][id.pool];
+        pool.pool.free_sets(Some(set));
+        pool.allocated -= 1;
+        pool.exhausted = false;
+    }
+
+    pub fn destroy(&mut self, device: &DeviceImpl) {
+        self.mixes.clear();
+        for pool in self.pools.drain(..).flat_map(|pools| pools) {
+            device.destroy_descriptor_pool(pool.pool);
+        }
+    }
+}
+
+fn descriptor_mix(bindings: &[pso::DescriptorSetLayoutBinding]) -> DescriptorMix {
+    let mut mix: DescriptorMix = Vec::new();
+    for binding in bindings {
+        match mix.iter_mut().find(|&&mut (ty, _)| ty == binding.ty) {
+            Some(&mut (_, ref mut count)) => *count += binding.count,
+            None => mix.push((binding.ty, binding.count)),
+        }
+    }
+    mix.sort_by_key(|&(ty, _)| ty as u32);
+    mix
+}
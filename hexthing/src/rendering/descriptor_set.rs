@@ -1,47 +1,45 @@
 use super::{
-    BackendImpl, DescriptorPoolImpl, DescriptorSetImpl, DescriptorSetLayoutImpl, DeviceImpl,
-    DeviceState,
+    BackendImpl, DescriptorSetImpl, DescriptorSetLayoutImpl, DeviceImpl, DeviceState, PoolId,
 };
-use hal::{pso, DescriptorPool, Device};
-use std::cell::RefCell;
-use std::rc::Rc;
+use hal::{pso, Device};
+use std::sync::Arc;
 
 pub struct DescSetLayout {
     layout: Option<DescriptorSetLayoutImpl>,
-    device: Rc<RefCell<DeviceState>>,
+    bindings: Vec<pso::DescriptorSetLayoutBinding>,
+    device: Arc<DeviceState>,
 }
 
 impl DescSetLayout {
-    pub fn new(
-        device: Rc<RefCell<DeviceState>>,
-        bindings: Vec<pso::DescriptorSetLayoutBinding>,
-    ) -> Self {
+    pub fn new(device: Arc<DeviceState>, bindings: Vec<pso::DescriptorSetLayoutBinding>) -> Self {
         let desc_set_layout = device
-            .borrow()
             .device
-            .create_descriptor_set_layout(bindings, &[])
+            .create_descriptor_set_layout(bindings.iter().cloned(), &[])
             .ok();
 
         DescSetLayout {
             layout: desc_set_layout,
+            bindings,
             device,
         }
     }
 
-    pub fn create_desc_set(self, desc_pool: &mut DescriptorPoolImpl) -> DescSet {
-        let desc_set = desc_pool
-            .allocate_set(self.layout.as_ref().unwrap())
-            .unwrap();
+    /// Allocates a set with this layout from the device's descriptor allocator.
+    pub fn create_desc_set(self) -> DescSet {
+        let (desc_set, pool) = self
+            .device
+            .allocate_desc_set(self.layout.as_ref().unwrap(), &self.bindings);
         DescSet {
             layout: self,
             set: Some(desc_set),
+            pool,
         }
     }
 }
 
 impl Drop for DescSetLayout {
     fn drop(&mut self) {
-        let device = &self.device.borrow().device;
+        let device = &self.device.device;
         device.destroy_descriptor_set_layout(self.layout.take().unwrap());
     }
 }
@@ -55,13 +53,14 @@ pub struct DescSetWrite<W> {
 pub struct DescSet {
     pub set: Option<DescriptorSetImpl>,
     layout: DescSetLayout,
+    pool: PoolId,
 }
 
 impl DescSet {
     pub fn write_to_state<'a, 'b: 'a, W>(
         &'b mut self,
         write: Vec<DescSetWrite<W>>,
-        device: &mut DeviceImpl,
+        device: &DeviceImpl,
     ) where
         W: IntoIterator,
         W::Item: std::borrow::Borrow<pso::Descriptor<'a, BackendImpl>>,
@@ -83,3 +82,14 @@ impl DescSet {
         self.layout.layout.as_ref().unwrap()
     }
 }
+
+impl Drop for DescSet {
+    fn drop(&mut self) {
+        self.layout
+            .device
+            .descriptors
+            .lock()
+            .unwrap()
+            .free(self.set.take().unwrap(), self.pool);
+    }
+}
@@ -1,22 +1,258 @@
-use super::{BackendImpl, DeviceImpl, PhysicalDeviceImpl, SurfaceImpl};
-use hal::{Adapter, Graphics, QueueGroup, Surface};
+use super::{
+    BackendImpl, BufferState, DepthMode, DescriptorAllocator, DescriptorSetImpl,
+    DescriptorSetLayoutImpl, DeviceImpl, PhysicalDeviceImpl, PipelineCache, PoolId, SemaphoreImpl,
+    SurfaceImpl,
+};
+use hal::{
+    command, format, pool, pso, queue, Adapter, CommandPool, Device, Graphics, PhysicalDevice,
+    QueueFamily, QueueGroup, QueueType, Surface, Transfer,
+};
+use std::ops::Range;
+use std::sync::Mutex;
 
+/// Queue family ownership transfer between the transfer and the graphics queue, for the `families`
+/// of a barrier. `None` when uploads run on the graphics queue and no transfer is needed.
+pub type OwnershipTransfer = Option<Range<queue::QueueFamilyId>>;
+
+/// The graphics queue half of an upload, submitted by `FrameContext::submit_uploads`.
+pub struct UploadSubmission {
+    /// Signaled by the transfer queue once the copies are done, `None` when they were recorded
+    /// into `commands` instead.
+    pub semaphore: Option<SemaphoreImpl>,
+    /// Acquires the written resources and prepares them for use.
+    pub commands: command::Submit<BackendImpl, Graphics, command::OneShot, command::Primary>,
+}
+
+/// An upload that has been recorded, and on a transfer queue submitted, but not waited for.
+/// Nothing it writes may be used before `submission` has been submitted, and `staging` has to be
+/// kept alive until the frame it was submitted with has completed.
+pub struct PendingUpload {
+    pub staging: BufferState,
+    pub submission: UploadSubmission,
+}
+
+/// Command pools that uploads are recorded from, and semaphores for uploads through the transfer
+/// queue. Every frame in flight has its own, see `FrameContext::upload_pools`.
+pub struct UploadPools {
+    graphics: Option<CommandPool<BackendImpl, Graphics>>,
+    transfer: Option<CommandPool<BackendImpl, Transfer>>,
+    semaphores: Vec<SemaphoreImpl>,
+}
+
+impl UploadPools {
+    pub fn new(device: &DeviceState) -> Self {
+        UploadPools {
+            graphics: Some(
+                device
+                    .device
+                    .create_command_pool_typed(
+                        &*device.queues.lock().unwrap(),
+                        pool::CommandPoolCreateFlags::TRANSIENT,
+                        1,
+                    )
+                    .expect("Can't create command pool"),
+            ),
+            transfer: device.transfer_queues.as_ref().map(|transfer_queues| {
+                device
+                    .device
+                    .create_command_pool_typed(
+                        &*transfer_queues.lock().unwrap(),
+                        pool::CommandPoolCreateFlags::TRANSIENT,
+                        1,
+                    )
+                    .expect("Can't create command pool")
+            }),
+            semaphores: Vec::new(),
+        }
+    }
+
+    /// Resets the pools once the GPU is done with every upload recorded from them, and takes back
+    /// the semaphores those uploads were given, one entry per upload.
+    pub fn reset<I>(&mut self, semaphores: I)
+    where
+        I: IntoIterator<Item = Option<SemaphoreImpl>>,
+    {
+        self.graphics.as_mut().unwrap().reset();
+        if let Some(ref mut transfer_pool) = self.transfer {
+            transfer_pool.reset();
+        }
+        self.semaphores
+            .extend(semaphores.into_iter().flat_map(|semaphore| semaphore));
+    }
+
+    pub fn destroy(mut self, device: &DeviceState) {
+        for semaphore in self.semaphores.drain(..) {
+            device.device.destroy_semaphore(semaphore);
+        }
+        device
+            .device
+            .destroy_command_pool(self.graphics.take().unwrap().into_raw());
+        if let Some(transfer_pool) = self.transfer.take() {
+            device.device.destroy_command_pool(transfer_pool.into_raw());
+        }
+    }
+}
+
+/// The logical device and what goes with it, shared between threads as `Arc<DeviceState>`.
+///
+/// Device methods only need a shared reference, so only what gfx-hal requires `&mut` for is
+/// behind a lock: the queues for submission and the descriptor allocator.
+///
+/// When the adapter has a dedicated transfer queue family, a queue of it is opened next to the
+/// graphics queue and uploads run there, so they don't hold up rendering.
 pub struct DeviceState {
     pub device: DeviceImpl,
-    pub queues: QueueGroup<BackendImpl, hal::Graphics>,
+    pub queues: Mutex<QueueGroup<BackendImpl, hal::Graphics>>,
+    transfer_queues: Option<Mutex<QueueGroup<BackendImpl, Transfer>>>,
     pub physical_device: PhysicalDeviceImpl,
+    /// Used for every pipeline created on this device. Saved once the renderer has created its
+    /// pipelines, and again when the device is dropped.
+    pub pipeline_cache: PipelineCache,
+    pub descriptors: Mutex<DescriptorAllocator>,
 }
 
 impl DeviceState {
     pub fn new(adapter: Adapter<BackendImpl>, surface: &SurfaceImpl) -> Self {
-        let (device, queues) = adapter
-            .open_with::<_, Graphics>(1, |family| surface.supports_queue_family(family))
-            .unwrap();
+        let graphics_family = adapter
+            .queue_families
+            .iter()
+            .find(|family| family.supports_graphics() && surface.supports_queue_family(family))
+            .expect("No queue family supports graphics and presentation");
+        let transfer_family = adapter
+            .queue_families
+            .iter()
+            .find(|family| family.queue_type() == QueueType::Transfer);
+
+        let mut families = vec![(graphics_family, &[1.0][..])];
+        if let Some(transfer_family) = transfer_family {
+            families.push((transfer_family, &[1.0][..]));
+        }
+        let hal::Gpu { device, mut queues } = adapter.physical_device.open(&families).unwrap();
+
+        let graphics_queues = queues.take::<Graphics>(graphics_family.id()).unwrap();
+        let transfer_queues =
+            transfer_family.map(|family| Mutex::new(queues.take::<Transfer>(family.id()).unwrap()));
+        if transfer_queues.is_some() {
+            info!("Uploading through a dedicated transfer queue");
+        }
+
+        let pipeline_cache = PipelineCache::new(&device, &adapter.info);
 
         DeviceState {
             device,
-            queues,
+            queues: Mutex::new(graphics_queues),
+            transfer_queues,
             physical_device: adapter.physical_device,
+            pipeline_cache,
+            descriptors: Mutex::new(DescriptorAllocator::default()),
+        }
+    }
+
+    /// Records an upload out of `staging` from `pools` without waiting for it.
+    ///
+    /// `transfer` records the copies, and `acquire` whatever has to happen on the graphics queue
+    /// before the uploaded resources can be used. With a dedicated transfer queue, the copies are
+    /// submitted to it right away and signal a semaphore, and both closures get the queue families
+    /// to release and acquire the written resources with. Otherwise both are recorded into the
+    /// same graphics command buffer and get `None`.
+    ///
+    /// Either way the graphics commands are returned unsubmitted, to be passed to
+    /// `FrameContext::add_upload` and submitted ahead of the next frame.
+    pub fn upload<T, A>(
+        &self,
+        pools: &mut UploadPools,
+        staging: BufferState,
+        transfer: T,
+        acquire: A,
+    ) -> PendingUpload
+    where
+        T: FnOnce(
+            &mut command::CommandBuffer<BackendImpl, Transfer>,
+            &BufferState,
+            &OwnershipTransfer,
+        ),
+        A: FnOnce(&mut command::CommandBuffer<BackendImpl, Graphics>, &OwnershipTransfer),
+    {
+        let graphics_pool = pools.graphics.as_mut().unwrap();
+        let (transfer_pool, transfer_queues) = match (&mut pools.transfer, &self.transfer_queues) {
+            (&mut Some(ref mut transfer_pool), &Some(ref transfer_queues)) => {
+                (transfer_pool, transfer_queues)
+            }
+            _ => {
+                let commands = {
+                    let mut cmd_buffer = graphics_pool.acquire_command_buffer(false);
+                    transfer(cmd_buffer.downgrade(), &staging, &None);
+                    acquire(&mut cmd_buffer, &None);
+                    cmd_buffer.finish()
+                };
+                return PendingUpload {
+                    staging,
+                    submission: UploadSubmission {
+                        semaphore: None,
+                        commands,
+                    },
+                };
+            }
+        };
+
+        let families =
+            Some(transfer_queues.lock().unwrap().family()..self.queues.lock().unwrap().family());
+        let semaphore = pools
+            .semaphores
+            .pop()
+            .unwrap_or_else(|| self.device.create_semaphore().unwrap());
+
+        let transfer_submit = {
+            let mut cmd_buffer = transfer_pool.acquire_command_buffer(false);
+            transfer(&mut cmd_buffer, &staging, &families);
+            cmd_buffer.finish()
+        };
+        let submission = queue::Submission::new()
+            .submit(Some(transfer_submit))
+            .signal(&[&semaphore]);
+        transfer_queues.lock().unwrap().queues[0].submit(submission, None);
+
+        let commands = {
+            let mut cmd_buffer = graphics_pool.acquire_command_buffer(false);
+            acquire(&mut cmd_buffer, &families);
+            cmd_buffer.finish()
+        };
+
+        PendingUpload {
+            staging,
+            submission: UploadSubmission {
+                semaphore: Some(semaphore),
+                commands,
+            },
         }
     }
+
+    /// Allocates a descriptor set with `layout`, which has to have been created from `bindings`.
+    pub fn allocate_desc_set(
+        &self,
+        layout: &DescriptorSetLayoutImpl,
+        bindings: &[pso::DescriptorSetLayoutBinding],
+    ) -> (DescriptorSetImpl, PoolId) {
+        self.descriptors
+            .lock()
+            .unwrap()
+            .allocate(&self.device, layout, bindings)
+    }
+
+    pub fn find_depth_format(&self, mode: DepthMode) -> Option<format::Format> {
+        mode.candidate_formats().iter().cloned().find(|&format| {
+            self.physical_device
+                .format_properties(Some(format))
+                .optimal_tiling
+                .contains(format::ImageFeature::DEPTH_STENCIL_ATTACHMENT)
+        })
+    }
+}
+
+impl Drop for DeviceState {
+    fn drop(&mut self) {
+        self.pipeline_cache.save(&self.device);
+        self.pipeline_cache.destroy(&self.device);
+        self.descriptors.get_mut().unwrap().destroy(&self.device);
+    }
 }
@@ -0,0 +1,48 @@
+use super::depth::{self, Layer};
+use super::PipelineConfig;
+use hal::pso;
+
+/// Byte offset of the fragment stage constants, after the vertex stage's layer depth.
+pub const FRAGMENT_CONSTANTS_OFFSET: u32 = 16;
+
+/// Fog of war, drawn as a single triangle covering the screen on the effects layer. The stencil
+/// test keeps it off every tile the terrain pass marked as `depth::VISIBLE`.
+pub fn pipeline_config() -> PipelineConfig {
+    PipelineConfig {
+        vertex_shader: "src/shaders/fog.vert".to_owned(),
+        fragment_shader: "src/shaders/fog.frag".to_owned(),
+        primitive: hal::Primitive::TriangleList,
+        blend: pso::BlendState::ALPHA,
+        depth_stencil: pso::DepthStencilDesc {
+            depth: pso::DepthTest::On {
+                fun: pso::Comparison::LessEqual,
+                write: false,
+            },
+            ..depth::stencil_mask(depth::VISIBLE)
+        },
+        push_constants: vec![
+            (pso::ShaderStageFlags::VERTEX, 0..FRAGMENT_CONSTANTS_OFFSET),
+            (
+                pso::ShaderStageFlags::FRAGMENT,
+                FRAGMENT_CONSTANTS_OFFSET..FRAGMENT_CONSTANTS_OFFSET + 16,
+            ),
+        ],
+        vertex_buffers: Vec::new(),
+        attributes: Vec::new(),
+    }
+}
+
+/// Vertex stage push constants, laid out as `FogConsts` in `fog.vert`.
+pub fn vertex_constants() -> [u32; 1] {
+    [Layer::Effects.depth().to_bits()]
+}
+
+/// Fragment stage push constants, laid out as `FogConsts` in `fog.frag`.
+pub fn fragment_constants(color: [f32; 4]) -> [u32; 4] {
+    [
+        color[0].to_bits(),
+        color[1].to_bits(),
+        color[2].to_bits(),
+        color[3].to_bits(),
+    ]
+}
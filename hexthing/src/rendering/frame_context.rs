@@ -0,0 +1,234 @@
+use super::{
+    BackendImpl, DeviceState, FenceImpl, PendingUpload, ResourceRegistry, SemaphoreImpl,
+    SwapchainImpl, UploadArena, UploadPools, UploadSubmission,
+};
+use hal::{self, command, pool, pso, queue, Device, Graphics, Swapchain};
+use std::sync::Arc;
+
+/// Everything a single frame in flight needs from recording to presentation.
+pub struct Frame {
+    /// Index of this frame among the frames in flight, for resources that are kept per frame.
+    pub index: usize,
+    pub command_pool: hal::CommandPool<BackendImpl, Graphics>,
+    /// Signaled once the image acquired for this frame is ready to be rendered to.
+    pub acquire_semaphore: SemaphoreImpl,
+    /// Transient data read by this frame's commands.
+    pub arena: UploadArena,
+    present_semaphore: SemaphoreImpl,
+    /// Signaled once the GPU has finished this frame's commands.
+    fence: FenceImpl,
+    /// Pools the uploads submitted with this frame are recorded from.
+    upload_pools: UploadPools,
+    /// Semaphores of the uploads submitted with this frame, one entry per upload.
+    uploads: Vec<Option<SemaphoreImpl>>,
+    /// Serial of the last submission made with this frame, 0 before the first one.
+    serial: u64,
+    /// Whether the GPU is known to be done with the last submission and everything has been reset
+    /// for the next one.
+    ready: bool,
+}
+
+/// A ring of frames that the CPU records into while the GPU works through the previous ones.
+///
+/// The number of frames in flight is independent of the number of swapchain images. A frame's
+/// resources, including anything indexed by `Frame::index`, may be reused once `begin_frame` has
+/// returned it, which waits for the GPU to finish the last submission made with it.
+///
+/// Every submission gets a serial, increasing from 1, which resources that outlive a frame use to
+/// tell when the GPU is done with them.
+pub struct FrameContext {
+    frames: Vec<Frame>,
+    current: usize,
+    /// Serial of the frame being recorded.
+    serial: u64,
+    /// Highest serial known to have finished on the GPU.
+    completed_serial: u64,
+    /// Uploads waiting for `submit_uploads`.
+    pending_uploads: Vec<UploadSubmission>,
+    device: Arc<DeviceState>,
+}
+
+impl FrameContext {
+    pub fn new(
+        device: &Arc<DeviceState>,
+        memory_types: &[hal::MemoryType],
+        frames_in_flight: usize,
+    ) -> Self {
+        assert!(
+            frames_in_flight > 0,
+            "At least one frame has to be in flight"
+        );
+
+        let frames = (0..frames_in_flight)
+            .map(|index| {
+                let state = &**device;
+                Frame {
+                    index,
+                    command_pool: state
+                        .device
+                        .create_command_pool_typed(
+                            &*state.queues.lock().unwrap(),
+                            pool::CommandPoolCreateFlags::empty(),
+                            16,
+                        )
+                        .expect("Can't create command pool"),
+                    acquire_semaphore: state.device.create_semaphore().unwrap(),
+                    arena: UploadArena::new(device, memory_types),
+                    present_semaphore: state.device.create_semaphore().unwrap(),
+                    // Created signaled so that the first `begin_frame` doesn't wait.
+                    fence: state.device.create_fence(true).unwrap(),
+                    upload_pools: UploadPools::new(state),
+                    uploads: Vec::new(),
+                    serial: 0,
+                    ready: false,
+                }
+            })
+            .collect();
+
+        FrameContext {
+            frames,
+            current: 0,
+            serial: 1,
+            completed_serial: 0,
+            pending_uploads: Vec::new(),
+            device: Arc::clone(device),
+        }
+    }
+
+    pub fn frames_in_flight(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Serial of the frame being recorded, or of the next one between frames. Anything used by
+    /// this frame may be released once `completed_serial` reaches it.
+    pub fn serial(&self) -> u64 {
+        self.serial
+    }
+
+    /// Everything submitted with this serial or a lower one has finished executing. Updated by
+    /// `begin_frame`.
+    pub fn completed_serial(&self) -> u64 {
+        self.completed_serial
+    }
+
+    /// Waits until the GPU is done with the next frame and returns it with its command pool and
+    /// arena reset. Calling it again without `end_frame`, e.g. after failing to acquire an image, returns
+    /// the same frame.
+    pub fn begin_frame(&mut self) -> &mut Frame {
+        self.wait_for_current();
+        &mut self.frames[self.current]
+    }
+
+    /// Pools to record uploads from, which are then passed to `add_upload`. They belong to the
+    /// frame the uploads will be submitted with, so this waits for that frame like `begin_frame`.
+    pub fn upload_pools(&mut self) -> &mut UploadPools {
+        self.wait_for_current();
+        &mut self.frames[self.current].upload_pools
+    }
+
+    /// Waits for the last submission made with the current frame and resets everything it used,
+    /// once per frame. Uploads may be recorded before `begin_frame`, so its pools mustn't be reset
+    /// again after that.
+    fn wait_for_current(&mut self) {
+        let frame = &mut self.frames[self.current];
+        if frame.ready {
+            return;
+        }
+        self.device.device.wait_for_fence(&frame.fence, !0).unwrap();
+        // A fence also covers everything submitted to the queue before it.
+        self.completed_serial = self.completed_serial.max(frame.serial);
+        frame.upload_pools.reset(frame.uploads.drain(..));
+        frame.command_pool.reset();
+        frame.arena.reset();
+        frame.ready = true;
+    }
+
+    /// Queues `upload` for the next `submit_uploads`. Its staging buffer is retired into
+    /// `resources` against the serial of the frame it will be submitted with.
+    pub fn add_upload(&mut self, upload: PendingUpload, resources: &mut ResourceRegistry) {
+        resources.retire(upload.staging, self.serial);
+        self.pending_uploads.push(upload.submission);
+    }
+
+    /// Submits the queued uploads to the graphics queue, waiting for their transfers where they
+    /// have any. Has to be called between `begin_frame` and `end_frame`, once nothing can abort
+    /// the frame any more: the frame's fence covers the uploads, since it is signaled after
+    /// everything submitted to the queue before it.
+    pub fn submit_uploads(&mut self) {
+        if self.pending_uploads.is_empty() {
+            return;
+        }
+
+        let (semaphores, commands): (Vec<_>, Vec<_>) = self
+            .pending_uploads
+            .drain(..)
+            .map(|upload| (upload.semaphore, upload.commands))
+            .unzip();
+        {
+            let waits: Vec<_> = semaphores
+                .iter()
+                .filter_map(Option::as_ref)
+                .map(|semaphore| (semaphore, pso::PipelineStage::TRANSFER))
+                .collect();
+            let submission = queue::Submission::new().wait_on(&waits).submit(commands);
+            self.device.queues.lock().unwrap().queues[0].submit(submission, None);
+        }
+        self.frames[self.current].uploads.extend(semaphores);
+    }
+
+    /// Submits the frame's commands, presents `image` once they have finished and moves on to the
+    /// next frame. An error means the swapchain has to be recreated.
+    pub fn end_frame(
+        &mut self,
+        submit: command::Submit<BackendImpl, Graphics, command::OneShot, command::Primary>,
+        swapchain: &SwapchainImpl,
+        image: hal::SwapImageIndex,
+    ) -> Result<(), ()> {
+        let result = {
+            let frame = &mut self.frames[self.current];
+            frame.serial = self.serial;
+            frame.ready = false;
+            frame.arena.flush();
+            self.device.device.reset_fence(&frame.fence).unwrap();
+
+            let submission = queue::Submission::new()
+                .wait_on(&[(&frame.acquire_semaphore, pso::PipelineStage::BOTTOM_OF_PIPE)])
+                .signal(&[&frame.present_semaphore])
+                .submit(Some(submit));
+            let mut queues = self.device.queues.lock().unwrap();
+            let queue = &mut queues.queues[0];
+            queue.submit(submission, Some(&frame.fence));
+            swapchain.present(queue, image, Some(&frame.present_semaphore))
+        };
+
+        self.current = (self.current + 1) % self.frames.len();
+        self.serial += 1;
+        result
+    }
+}
+
+impl Drop for FrameContext {
+    fn drop(&mut self) {
+        let device = &self.device.device;
+        if !self.pending_uploads.is_empty() {
+            // Their transfers may still be running.
+            device.wait_idle().unwrap();
+            for semaphore in self
+                .pending_uploads
+                .drain(..)
+                .filter_map(|upload| upload.semaphore)
+            {
+                device.destroy_semaphore(semaphore);
+            }
+        }
+        for mut frame in self.frames.drain(..) {
+            device.wait_for_fence(&frame.fence, !0).unwrap();
+            frame.upload_pools.reset(frame.uploads.drain(..));
+            frame.upload_pools.destroy(&self.device);
+            device.destroy_fence(frame.fence);
+            device.destroy_command_pool(frame.command_pool.into_raw());
+            device.destroy_semaphore(frame.acquire_semaphore);
+            device.destroy_semaphore(frame.present_semaphore);
+        }
+    }
+}
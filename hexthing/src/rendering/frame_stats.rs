@@ -0,0 +1,226 @@
+use super::{BackendImpl, DescriptorSetImpl, GraphicsPipelineImpl, PipelineLayoutImpl};
+use hal::{command, pso, query, Graphics};
+use std::borrow::Borrow;
+use std::iter::Sum;
+use std::ops::{AddAssign, Deref, DerefMut, Range};
+
+/// Work recorded into a frame's command buffers, counted while recording.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct DrawStats {
+    pub draw_calls: u32,
+    pub instances: u32,
+    /// Vertices drawn over every instance.
+    pub vertices: u32,
+    pub pipeline_binds: u32,
+    pub descriptor_binds: u32,
+}
+
+impl DrawStats {
+    pub fn draw(&mut self, vertices: Range<u32>, instances: Range<u32>) {
+        let instance_count = instances.end - instances.start;
+        self.draw_calls += 1;
+        self.instances += instance_count;
+        self.vertices += (vertices.end - vertices.start) * instance_count;
+    }
+
+    pub fn bind_pipeline(&mut self) {
+        self.pipeline_binds += 1;
+    }
+
+    pub fn bind_descriptor_sets(&mut self) {
+        self.descriptor_binds += 1;
+    }
+}
+
+impl AddAssign for DrawStats {
+    fn add_assign(&mut self, other: DrawStats) {
+        self.draw_calls += other.draw_calls;
+        self.instances += other.instances;
+        self.vertices += other.vertices;
+        self.pipeline_binds += other.pipeline_binds;
+        self.descriptor_binds += other.descriptor_binds;
+    }
+}
+
+/// Merges the counts of the secondary command buffers recording a frame.
+impl Sum for DrawStats {
+    fn sum<I: Iterator<Item = DrawStats>>(iter: I) -> DrawStats {
+        iter.fold(DrawStats::default(), |mut total, stats| {
+            total += stats;
+            total
+        })
+    }
+}
+
+/// A secondary command buffer recording part of the main render pass.
+pub type SubpassCommandBuffer =
+    command::CommandBuffer<BackendImpl, Graphics, command::OneShot, command::Secondary>;
+
+/// Commands recorded by a `SubpassCommandBuffer`, executed inside the main render pass.
+pub type SubpassCommands =
+    command::Submit<BackendImpl, Graphics, command::OneShot, command::Secondary>;
+
+/// Records into a secondary command buffer of the main render pass and counts the binds and
+/// draws going through it. When given a pipeline statistics query, the query spans everything
+/// recorded.
+///
+/// Commands that aren't counted go to the command buffer through `Deref`.
+pub struct CountingEncoder<'a> {
+    cmd_buffer: SubpassCommandBuffer,
+    query: Option<query::Query<'a, BackendImpl>>,
+    stats: DrawStats,
+}
+
+impl<'a> CountingEncoder<'a> {
+    pub fn new(
+        mut cmd_buffer: SubpassCommandBuffer,
+        query: Option<query::Query<'a, BackendImpl>>,
+    ) -> Self {
+        if let Some(ref query) = query {
+            cmd_buffer.begin_query(
+                query::Query {
+                    pool: query.pool,
+                    id: query.id,
+                },
+                query::ControlFlags::empty(),
+            );
+        }
+        CountingEncoder {
+            cmd_buffer,
+            query,
+            stats: DrawStats::default(),
+        }
+    }
+
+    pub fn bind_graphics_pipeline(&mut self, pipeline: &GraphicsPipelineImpl) {
+        self.stats.bind_pipeline();
+        self.cmd_buffer.bind_graphics_pipeline(pipeline);
+    }
+
+    pub fn bind_graphics_descriptor_sets<I, J>(
+        &mut self,
+        layout: &PipelineLayoutImpl,
+        first_set: usize,
+        sets: I,
+        offsets: J,
+    ) where
+        I: IntoIterator,
+        I::Item: Borrow<DescriptorSetImpl>,
+        J: IntoIterator,
+        J::Item: Borrow<pso::DescriptorSetOffset>,
+    {
+        self.stats.bind_descriptor_sets();
+        self.cmd_buffer
+            .bind_graphics_descriptor_sets(layout, first_set, sets, offsets);
+    }
+
+    pub fn draw(&mut self, vertices: Range<u32>, instances: Range<u32>) {
+        self.stats.draw(vertices.clone(), instances.clone());
+        self.cmd_buffer.draw(vertices, instances);
+    }
+
+    /// Ends the query, if any, and returns the commands together with what they draw.
+    pub fn finish(mut self) -> (SubpassCommands, DrawStats) {
+        if let Some(query) = self.query.take() {
+            self.cmd_buffer.end_query(query);
+        }
+        (self.cmd_buffer.finish(), self.stats)
+    }
+}
+
+impl<'a> Deref for CountingEncoder<'a> {
+    type Target = SubpassCommandBuffer;
+
+    fn deref(&self) -> &SubpassCommandBuffer {
+        &self.cmd_buffer
+    }
+}
+
+impl<'a> DerefMut for CountingEncoder<'a> {
+    fn deref_mut(&mut self) -> &mut SubpassCommandBuffer {
+        &mut self.cmd_buffer
+    }
+}
+
+/// Counts from the pipeline statistics queries covering a frame's main render pass.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct PipelineStatistics {
+    pub input_vertices: u64,
+    pub input_primitives: u64,
+    pub vertex_shader_invocations: u64,
+    /// Primitives that made it through clipping.
+    pub clipped_primitives: u64,
+    pub fragment_shader_invocations: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// What `CountingEncoder` counts while a map chunk is recorded, with the grid drawn over it.
+    fn chunk(instances: Range<u32>) -> DrawStats {
+        let mut stats = DrawStats::default();
+        stats.bind_pipeline();
+        stats.bind_descriptor_sets();
+        stats.draw(0..18, instances.clone());
+        stats.bind_pipeline();
+        stats.draw(0..18, instances);
+        stats
+    }
+
+    #[test]
+    fn counts_draws_instances_and_binds() {
+        let mut stats = DrawStats::default();
+        stats.bind_pipeline();
+        stats.bind_descriptor_sets();
+        stats.draw(0..18, 0..64);
+        stats.draw(6..18, 10..12);
+        stats.draw(0..3, 0..1);
+        assert_eq!(
+            stats,
+            DrawStats {
+                draw_calls: 3,
+                instances: 67,
+                vertices: 18 * 64 + 12 * 2 + 3,
+                pipeline_binds: 1,
+                descriptor_binds: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn empty_draw_counts_call_only() {
+        let mut stats = DrawStats::default();
+        stats.draw(0..18, 5..5);
+        assert_eq!(stats.draw_calls, 1);
+        assert_eq!(stats.instances, 0);
+        assert_eq!(stats.vertices, 0);
+    }
+
+    #[test]
+    fn merges_secondary_buffers() {
+        let chunks = vec![chunk(0..2048), chunk(2048..4096), chunk(4096..5000)];
+        let mut stats: DrawStats = chunks.into_iter().sum();
+        assert_eq!(
+            stats,
+            DrawStats {
+                draw_calls: 6,
+                instances: 2 * 5000,
+                vertices: 2 * 18 * 5000,
+                pipeline_binds: 6,
+                descriptor_binds: 3,
+            }
+        );
+
+        let mut overlay = DrawStats::default();
+        overlay.bind_pipeline();
+        overlay.draw(0..3, 0..1);
+        stats += overlay;
+        assert_eq!(stats.draw_calls, 7);
+        assert_eq!(stats.pipeline_binds, 7);
+        assert_eq!(stats.vertices, 2 * 18 * 5000 + 3);
+
+        let nothing: DrawStats = Vec::new().into_iter().sum();
+        assert_eq!(nothing, DrawStats::default());
+    }
+}
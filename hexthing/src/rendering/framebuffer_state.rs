@@ -1,10 +1,10 @@
+use super::depth;
 use super::{
-    BackendImpl, DeviceState, FenceImpl, FramebufferImpl, ImageImpl, ImageViewImpl,
-    RenderPassState, SemaphoreImpl, SwapchainState,
+    DeviceState, FramebufferImpl, ImageImpl, ImageState, ImageViewImpl, RenderPassState,
+    SwapchainState,
 };
-use hal::{format, image, pool, Backbuffer, Device};
-use std::cell::RefCell;
-use std::rc::Rc;
+use hal::{self, format, image, Backbuffer, Device};
+use std::sync::Arc;
 
 const COLOR_RANGE: image::SubresourceRange = image::SubresourceRange {
     aspects: format::Aspects::COLOR,
@@ -12,24 +12,24 @@ const COLOR_RANGE: image::SubresourceRange = image::SubresourceRange {
     layers: 0..1,
 };
 
+/// Views and framebuffers of the swapchain images. Per frame synchronization lives in
+/// `FrameContext`.
 pub struct FramebufferState {
     framebuffers: Option<Vec<FramebufferImpl>>,
-    framebuffer_fences: Option<Vec<FenceImpl>>,
-    command_pools: Option<Vec<hal::CommandPool<BackendImpl, hal::Graphics>>>,
     frame_images: Option<Vec<(ImageImpl, ImageViewImpl)>>,
-    acquire_semaphores: Option<Vec<SemaphoreImpl>>,
-    present_semaphores: Option<Vec<SemaphoreImpl>>,
-    last_ref: usize,
-    device: Rc<RefCell<DeviceState>>,
+    /// Multisampled color and depth targets rendered alongside the swapchain images.
+    targets: Option<Vec<ImageState>>,
+    device: Arc<DeviceState>,
 }
 
 impl FramebufferState {
     pub fn new(
-        device: Rc<RefCell<DeviceState>>,
+        device: Arc<DeviceState>,
         render_pass: &RenderPassState,
         swapchain: &mut SwapchainState,
+        memory_types: &[hal::MemoryType],
     ) -> Self {
-        let (frame_images, framebuffers) = match swapchain.backbuffer.take().unwrap() {
+        let (frame_images, targets, framebuffers) = match swapchain.backbuffer.take().unwrap() {
             Backbuffer::Images(images) => {
                 let extent = image::Extent {
                     width: swapchain.extent.width as _,
@@ -40,7 +40,6 @@ impl FramebufferState {
                     .into_iter()
                     .map(|image| {
                         let rtv = device
-                            .borrow()
                             .device
                             .create_image_view(
                                 &image,
@@ -53,130 +52,89 @@ impl FramebufferState {
                         (image, rtv)
                     })
                     .collect::<Vec<_>>();
+                let kind = image::Kind::D2(extent.width, extent.height, 1, render_pass.samples);
+                let msaa_images: Vec<_> = if render_pass.samples > 1 {
+                    pairs
+                        .iter()
+                        .map(|_| {
+                            ImageState::new(
+                                Arc::clone(&device),
+                                memory_types,
+                                kind,
+                                1,
+                                swapchain.format,
+                                image::Usage::COLOR_ATTACHMENT | image::Usage::TRANSIENT_ATTACHMENT,
+                                format::Aspects::COLOR,
+                            )
+                        })
+                        .collect()
+                } else {
+                    Vec::new()
+                };
+                let depth_images: Vec<_> = match render_pass.depth_format {
+                    Some(depth_format) => pairs
+                        .iter()
+                        .map(|_| {
+                            ImageState::new(
+                                Arc::clone(&device),
+                                memory_types,
+                                kind,
+                                1,
+                                depth_format,
+                                image::Usage::DEPTH_STENCIL_ATTACHMENT,
+                                depth::aspects_of(depth_format),
+                            )
+                        })
+                        .collect(),
+                    None => Vec::new(),
+                };
                 let fbos = pairs
                     .iter()
-                    .map(|&(_, ref rtv)| {
+                    .enumerate()
+                    .map(|(i, &(_, ref rtv))| {
+                        // Attachment order has to match `RenderPassState`: the multisampled color
+                        // target (if any), the swapchain image, then depth.
+                        let mut attachments = Vec::new();
+                        if let Some(msaa_image) = msaa_images.get(i) {
+                            attachments.push(msaa_image.get_view());
+                        }
+                        attachments.push(rtv);
+                        if let Some(depth_image) = depth_images.get(i) {
+                            attachments.push(depth_image.get_view());
+                        }
                         device
-                            .borrow()
                             .device
                             .create_framebuffer(
                                 render_pass.render_pass.as_ref().unwrap(),
-                                Some(rtv),
+                                attachments,
                                 extent,
                             )
                             .unwrap()
                     })
                     .collect();
-                (pairs, fbos)
+                let targets = msaa_images.into_iter().chain(depth_images).collect();
+                (pairs, targets, fbos)
             }
-            Backbuffer::Framebuffer(fbo) => (Vec::new(), vec![fbo]),
-        };
-
-        let iter_count = if !frame_images.is_empty() {
-            frame_images.len()
-        } else {
-            1 // GL can have zero
+            Backbuffer::Framebuffer(fbo) => (Vec::new(), Vec::new(), vec![fbo]),
         };
 
-        let mut fences: Vec<FenceImpl> = vec![];
-        let mut command_pools: Vec<hal::CommandPool<BackendImpl, hal::Graphics>> = vec![];
-        let mut acquire_semaphores: Vec<SemaphoreImpl> = vec![];
-        let mut present_semaphores: Vec<SemaphoreImpl> = vec![];
-
-        for _ in 0..iter_count {
-            fences.push(device.borrow().device.create_fence(true).unwrap());
-            command_pools.push(
-                device
-                    .borrow()
-                    .device
-                    .create_command_pool_typed(
-                        &device.borrow().queues,
-                        pool::CommandPoolCreateFlags::empty(),
-                        16,
-                    )
-                    .expect("Can't create command pool"),
-            );
-
-            acquire_semaphores.push(device.borrow().device.create_semaphore().unwrap());
-            present_semaphores.push(device.borrow().device.create_semaphore().unwrap());
-        }
-
         FramebufferState {
             frame_images: Some(frame_images),
+            targets: Some(targets),
             framebuffers: Some(framebuffers),
-            framebuffer_fences: Some(fences),
-            command_pools: Some(command_pools),
-            present_semaphores: Some(present_semaphores),
-            acquire_semaphores: Some(acquire_semaphores),
             device,
-            last_ref: 0,
-        }
-    }
-
-    pub fn next_acq_pre_pair_index(&mut self) -> usize {
-        if self.last_ref >= self.acquire_semaphores.as_ref().unwrap().len() {
-            self.last_ref = 0
         }
-
-        let ret = self.last_ref;
-        self.last_ref += 1;
-        ret
     }
 
-    pub fn get_frame_data(
-        &mut self,
-        frame_id: Option<usize>,
-        sem_index: Option<usize>,
-    ) -> (
-        Option<(
-            &mut FenceImpl,
-            &mut FramebufferImpl,
-            &mut hal::CommandPool<BackendImpl, ::hal::Graphics>,
-        )>,
-        Option<(&mut SemaphoreImpl, &mut SemaphoreImpl)>,
-    ) {
-        (
-            if let Some(fid) = frame_id {
-                Some((
-                    &mut self.framebuffer_fences.as_mut().unwrap()[fid],
-                    &mut self.framebuffers.as_mut().unwrap()[fid],
-                    &mut self.command_pools.as_mut().unwrap()[fid],
-                ))
-            } else {
-                None
-            },
-            if let Some(sid) = sem_index {
-                Some((
-                    &mut self.acquire_semaphores.as_mut().unwrap()[sid],
-                    &mut self.present_semaphores.as_mut().unwrap()[sid],
-                ))
-            } else {
-                None
-            },
-        )
+    /// The framebuffer rendering to the swapchain image at `index`.
+    pub fn framebuffer(&self, index: usize) -> &FramebufferImpl {
+        &self.framebuffers.as_ref().unwrap()[index]
     }
 }
 
 impl Drop for FramebufferState {
     fn drop(&mut self) {
-        let device = &self.device.borrow().device;
-
-        for fence in self.framebuffer_fences.take().unwrap() {
-            device.wait_for_fence(&fence, !0).unwrap();
-            device.destroy_fence(fence);
-        }
-
-        for command_pool in self.command_pools.take().unwrap() {
-            device.destroy_command_pool(command_pool.into_raw());
-        }
-
-        for acquire_semaphore in self.acquire_semaphores.take().unwrap() {
-            device.destroy_semaphore(acquire_semaphore);
-        }
-
-        for present_semaphore in self.present_semaphores.take().unwrap() {
-            device.destroy_semaphore(present_semaphore);
-        }
+        let device = &self.device.device;
 
         for framebuffer in self.framebuffers.take().unwrap() {
             device.destroy_framebuffer(framebuffer);
@@ -185,5 +143,7 @@ impl Drop for FramebufferState {
         for (_, rtv) in self.frame_images.take().unwrap() {
             device.destroy_image_view(rtv);
         }
+
+        self.targets.take();
     }
 }
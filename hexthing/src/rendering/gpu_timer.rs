@@ -0,0 +1,174 @@
+use super::{read_query_results, BackendImpl, DeviceState, QueryPoolImpl, ScopeTiming};
+use hal::{command, pso, query, Device, Graphics};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Timestamp queries available to a frame, two per scope.
+const MAX_QUERIES: u32 = 64;
+
+struct FrameQueries {
+    pool: Option<QueryPoolImpl>,
+    /// Name and first query of every scope written so far.
+    scopes: Vec<(&'static str, query::Id)>,
+    /// Serial of the frame the queries were written by, `None` while there is nothing to read.
+    serial: Option<u64>,
+}
+
+/// A scope that has been begun on the GPU and still has to be ended.
+pub struct GpuScope {
+    query: query::Id,
+}
+
+/// Measures scopes of command buffers with timestamp queries.
+///
+/// There is a query pool per frame in flight. Results are read back when a frame comes around
+/// again, after `FrameContext::begin_frame` has waited for its previous submission, so reading
+/// them never stalls.
+///
+/// gfx-hal doesn't report the valid timestamp bits of a queue family, but
+/// `Limits::timestamp_compute_and_graphics` guarantees them for every graphics queue. Without it
+/// no scope is measured.
+pub struct GpuTimer {
+    frames: Vec<FrameQueries>,
+    current: usize,
+    /// Nanoseconds per timestamp tick.
+    period: f32,
+    supported: bool,
+    device: Arc<DeviceState>,
+}
+
+impl GpuTimer {
+    pub fn new(device: &Arc<DeviceState>, frames_in_flight: usize, limits: &hal::Limits) -> Self {
+        let supported = limits.timestamp_compute_and_graphics;
+        if !supported {
+            warn!("The graphics queue may not support timestamps, GPU scopes won't be measured");
+        }
+
+        let frames = (0..frames_in_flight)
+            .map(|_| FrameQueries {
+                pool: Some(
+                    device
+                        .device
+                        .create_query_pool(query::Type::Timestamp, MAX_QUERIES)
+                        .expect("Can't create query pool"),
+                ),
+                scopes: Vec::new(),
+                serial: None,
+            })
+            .collect();
+
+        GpuTimer {
+            frames,
+            current: 0,
+            period: limits.timestamp_period,
+            supported,
+            device: Arc::clone(device),
+        }
+    }
+
+    /// Switches to the queries of the frame at `frame_index`, which the GPU has to be done with,
+    /// and resets them in `cmd_buffer` for the frame with `serial`. Has to be recorded before any
+    /// scope and outside of a render pass.
+    ///
+    /// Returns the serial and scopes of the frame that last used the queries, if any.
+    pub fn begin_frame(
+        &mut self,
+        frame_index: usize,
+        serial: u64,
+        cmd_buffer: &mut command::CommandBuffer<BackendImpl, Graphics>,
+    ) -> Option<(u64, Vec<ScopeTiming>)> {
+        self.current = frame_index;
+        let results = self.read_results();
+
+        let frame = &mut self.frames[frame_index];
+        cmd_buffer.reset_query_pool(frame.pool.as_ref().unwrap(), 0..MAX_QUERIES);
+        frame.scopes.clear();
+        frame.serial = Some(serial);
+
+        results
+    }
+
+    /// Writes the timestamp starting a scope named `name`. Returns `None` once the frame is out
+    /// of queries or when timestamps aren't supported, in which case the scope isn't measured.
+    pub fn begin_scope(
+        &mut self,
+        cmd_buffer: &mut command::CommandBuffer<BackendImpl, Graphics>,
+        name: &'static str,
+    ) -> Option<GpuScope> {
+        if !self.supported {
+            return None;
+        }
+
+        let frame = &mut self.frames[self.current];
+        let query = frame.scopes.len() as query::Id * 2;
+        if query + 2 > MAX_QUERIES {
+            return None;
+        }
+
+        frame.scopes.push((name, query));
+        cmd_buffer.write_timestamp(
+            pso::PipelineStage::TOP_OF_PIPE,
+            query::Query {
+                pool: frame.pool.as_ref().unwrap(),
+                id: query,
+            },
+        );
+        Some(GpuScope { query })
+    }
+
+    pub fn end_scope(
+        &mut self,
+        cmd_buffer: &mut command::CommandBuffer<BackendImpl, Graphics>,
+        scope: Option<GpuScope>,
+    ) {
+        if let Some(scope) = scope {
+            cmd_buffer.write_timestamp(
+                pso::PipelineStage::BOTTOM_OF_PIPE,
+                query::Query {
+                    pool: self.frames[self.current].pool.as_ref().unwrap(),
+                    id: scope.query + 1,
+                },
+            );
+        }
+    }
+
+    fn read_results(&mut self) -> Option<(u64, Vec<ScopeTiming>)> {
+        let frame = &mut self.frames[self.current];
+        let serial = frame.serial.take()?;
+        if frame.scopes.is_empty() {
+            return Some((serial, Vec::new()));
+        }
+
+        let count = frame.scopes.len() as query::Id * 2;
+        let timestamps =
+            read_query_results(&self.device, frame.pool.as_ref().unwrap(), 0..count, 1)?;
+        let origin = timestamps.iter().cloned().min().unwrap();
+        let period = self.period;
+        let to_duration = |ticks: u64| Duration::from_nanos((ticks as f64 * period as f64) as u64);
+
+        let scopes = frame
+            .scopes
+            .iter()
+            .map(|&(name, query)| {
+                let begin = timestamps[query as usize];
+                let end = timestamps[query as usize + 1].max(begin);
+                ScopeTiming {
+                    name,
+                    start: to_duration(begin - origin),
+                    duration: to_duration(end - begin),
+                }
+            })
+            .collect();
+        Some((serial, scopes))
+    }
+}
+
+impl Drop for GpuTimer {
+    fn drop(&mut self) {
+        for frame in &mut self.frames {
+            self.device
+                .device
+                .destroy_query_pool(frame.pool.take().unwrap());
+        }
+    }
+}
@@ -0,0 +1,82 @@
+use super::PipelineConfig;
+use hal::pso;
+
+/// Appearance of the hex grid overlay. The outline is computed per fragment from the signed
+/// distance to the hex border, so it stays crisp regardless of zoom.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct GridStyle {
+    /// Line width in hex-local units, where the hex circumradius is 1.
+    pub line_width: f32,
+    /// Width of the antialiased falloff, in pixels.
+    pub feather: f32,
+    pub color: [f32; 4],
+}
+
+impl Default for GridStyle {
+    fn default() -> Self {
+        GridStyle {
+            line_width: 0.03,
+            feather: 1.0,
+            color: [0.0, 0.0, 0.0, 0.6],
+        }
+    }
+}
+
+/// Byte offset of the fragment stage constants, after the vertex stage's view projection matrix
+/// and layer depth.
+pub const FRAGMENT_CONSTANTS_OFFSET: u32 = 80;
+
+impl GridStyle {
+    /// Outline drawn around the hex under the cursor.
+    pub fn hover() -> Self {
+        GridStyle {
+            line_width: 0.08,
+            feather: 1.0,
+            color: [1.0, 1.0, 1.0, 0.5],
+        }
+    }
+
+    /// Outline drawn around the selected hex.
+    pub fn selection() -> Self {
+        GridStyle {
+            line_width: 0.12,
+            feather: 1.0,
+            color: [1.0, 0.85, 0.2, 1.0],
+        }
+    }
+
+    /// Fragment stage push constants, laid out as `GridConsts` in `hex_grid.frag`.
+    pub fn push_constants(&self) -> [u32; 6] {
+        [
+            self.color[0].to_bits(),
+            self.color[1].to_bits(),
+            self.color[2].to_bits(),
+            self.color[3].to_bits(),
+            self.line_width.to_bits(),
+            self.feather.to_bits(),
+        ]
+    }
+}
+
+/// The grid is drawn over the tiles at the same depth, so it tests against but never writes depth.
+pub fn pipeline_config(with_depth: bool) -> PipelineConfig {
+    let mut config = PipelineConfig::hex("src/shaders/hex_grid.vert", "src/shaders/hex_grid.frag");
+    config.push_constants = vec![
+        (pso::ShaderStageFlags::VERTEX, 0..FRAGMENT_CONSTANTS_OFFSET),
+        (
+            pso::ShaderStageFlags::FRAGMENT,
+            FRAGMENT_CONSTANTS_OFFSET..FRAGMENT_CONSTANTS_OFFSET + 24,
+        ),
+    ];
+    if with_depth {
+        config.depth_stencil = pso::DepthStencilDesc {
+            depth: pso::DepthTest::On {
+                fun: pso::Comparison::LessEqual,
+                write: false,
+            },
+            depth_bounds: false,
+            stencil: pso::StencilTest::Off,
+        };
+    }
+    config
+}
@@ -0,0 +1,350 @@
+use super::depth;
+use super::{
+    BackendImpl, BufferImpl, BufferState, DescriptorSetLayoutImpl, DeviceState, FenceImpl,
+    FramebufferImpl, ImageState, PipelineConfig, PipelineState, RenderPassState,
+};
+use definitions::{PickInstance, Vertex};
+use hal::{self, buffer, command, format, image, memory, pool, pso, queue, Device, Graphics};
+use std::mem::size_of;
+use std::sync::Arc;
+
+const ID_FORMAT: format::Format = format::Format::R32Uint;
+
+/// Id written where no pickable instance was drawn.
+const NO_ENTITY: u32 = 0;
+
+/// Renders `PickInstance` ids into an integer target and reads back the id under the cursor.
+///
+/// Only the pixel under the cursor is rasterized, by restricting the scissor to it. The readback
+/// is asynchronous: `submit` records and submits the pass with its own fence, and `poll` picks up
+/// the result on a later frame once the fence has signaled, so the frame never stalls on it.
+pub struct IdPickingState {
+    render_pass: RenderPassState,
+    pipeline: PipelineState,
+    target: ImageState,
+    depth: Option<ImageState>,
+    framebuffer: Option<FramebufferImpl>,
+    extent: image::Extent,
+    readback: BufferState,
+    command_pool: Option<hal::CommandPool<BackendImpl, Graphics>>,
+    fence: Option<FenceImpl>,
+    pending: bool,
+    device: Arc<DeviceState>,
+}
+
+impl IdPickingState {
+    pub fn new(
+        device: &Arc<DeviceState>,
+        memory_types: &[hal::MemoryType],
+        extent: image::Extent,
+        depth_format: Option<format::Format>,
+    ) -> Self {
+        let render_pass = RenderPassState::offscreen(ID_FORMAT, depth_format, Arc::clone(device));
+        let pipeline = PipelineState::new(
+            Vec::<&DescriptorSetLayoutImpl>::new(),
+            &render_pass,
+            &IdPickingState::pipeline_config(depth_format.is_some()),
+            device,
+        );
+
+        let (target, depth, framebuffer) = IdPickingState::create_targets(
+            device,
+            memory_types,
+            &render_pass,
+            extent,
+            depth_format,
+        );
+
+        let readback = BufferState::new(
+            Arc::clone(device),
+            &[NO_ENTITY],
+            buffer::Usage::TRANSFER_DST,
+            memory_types,
+        );
+
+        let (command_pool, fence) = {
+            let command_pool = device
+                .device
+                .create_command_pool_typed(
+                    &*device.queues.lock().unwrap(),
+                    pool::CommandPoolCreateFlags::empty(),
+                    1,
+                )
+                .expect("Can't create command pool");
+            (command_pool, device.device.create_fence(false).unwrap())
+        };
+
+        IdPickingState {
+            render_pass,
+            pipeline,
+            target,
+            depth,
+            framebuffer: Some(framebuffer),
+            extent,
+            readback,
+            command_pool: Some(command_pool),
+            fence: Some(fence),
+            pending: false,
+            device: Arc::clone(device),
+        }
+    }
+
+    fn create_targets(
+        device: &Arc<DeviceState>,
+        memory_types: &[hal::MemoryType],
+        render_pass: &RenderPassState,
+        extent: image::Extent,
+        depth_format: Option<format::Format>,
+    ) -> (ImageState, Option<ImageState>, FramebufferImpl) {
+        let kind = image::Kind::D2(extent.width, extent.height, 1, 1);
+        let target = ImageState::new(
+            Arc::clone(device),
+            memory_types,
+            kind,
+            1,
+            ID_FORMAT,
+            image::Usage::COLOR_ATTACHMENT | image::Usage::TRANSFER_SRC,
+            format::Aspects::COLOR,
+        );
+        let depth = depth_format.map(|depth_format| {
+            ImageState::new(
+                Arc::clone(device),
+                memory_types,
+                kind,
+                1,
+                depth_format,
+                image::Usage::DEPTH_STENCIL_ATTACHMENT,
+                depth::aspects_of(depth_format),
+            )
+        });
+
+        let framebuffer = {
+            let mut attachments = vec![target.get_view()];
+            if let Some(ref depth) = depth {
+                attachments.push(depth.get_view());
+            }
+            device
+                .device
+                .create_framebuffer(
+                    render_pass.render_pass.as_ref().unwrap(),
+                    attachments,
+                    extent,
+                )
+                .unwrap()
+        };
+
+        (target, depth, framebuffer)
+    }
+
+    /// Recreates the size dependent targets, keeping the render pass and pipeline. Waits for a
+    /// pass still in flight and drops its result.
+    pub fn resize(&mut self, extent: image::Extent, memory_types: &[hal::MemoryType]) {
+        if self.pending {
+            self.device
+                .device
+                .wait_for_fence(self.fence.as_ref().unwrap(), !0)
+                .unwrap();
+            self.pending = false;
+        }
+
+        self.device
+            .device
+            .destroy_framebuffer(self.framebuffer.take().unwrap());
+
+        let (target, depth, framebuffer) = IdPickingState::create_targets(
+            &self.device,
+            memory_types,
+            &self.render_pass,
+            extent,
+            self.render_pass.depth_format,
+        );
+        self.target = target;
+        self.depth = depth;
+        self.framebuffer = Some(framebuffer);
+        self.extent = extent;
+    }
+
+    /// Instances are drawn with the hex mesh at depths given per instance, tested like the main
+    /// pass so the topmost instance under the cursor wins.
+    fn pipeline_config(with_depth: bool) -> PipelineConfig {
+        let mut config = PipelineConfig::hex("src/shaders/pick.vert", "src/shaders/pick.frag");
+        // Integer targets can't be blended.
+        config.blend = pso::BlendState::Off;
+        config.push_constants = vec![(pso::ShaderStageFlags::VERTEX, 0..64)];
+        config.vertex_buffers[1].stride = size_of::<PickInstance>() as u32;
+        let instance_attribute = |location, format, offset| pso::AttributeDesc {
+            location,
+            binding: 1,
+            element: pso::Element { format, offset },
+        };
+        config.attributes.truncate(1);
+        config.attributes.extend(vec![
+            instance_attribute(1, format::Format::Rg32Float, 0),
+            instance_attribute(2, format::Format::R32Float, size_of::<Vertex>() as u32),
+            instance_attribute(3, format::Format::R32Float, size_of::<Vertex>() as u32 + 4),
+            instance_attribute(4, format::Format::R32Uint, size_of::<Vertex>() as u32 + 8),
+        ]);
+        if with_depth {
+            config.depth_stencil = pso::DepthStencilDesc {
+                depth: pso::DepthTest::On {
+                    fun: pso::Comparison::LessEqual,
+                    write: true,
+                },
+                depth_bounds: false,
+                stencil: pso::StencilTest::Off,
+            };
+        }
+        config
+    }
+
+    /// Returns the result of the last submitted pass once the GPU has finished it: `Some(None)`
+    /// when nothing pickable was under the cursor, `None` while no new result is available.
+    pub fn poll(&mut self) -> Option<Option<u32>> {
+        if !self.pending {
+            return None;
+        }
+
+        let signaled = self
+            .device
+            .device
+            .wait_for_fence(self.fence.as_ref().unwrap(), 0)
+            .unwrap();
+        if !signaled {
+            return None;
+        }
+
+        self.pending = false;
+        match self.readback.read_data::<u32>(1)[0] {
+            NO_ENTITY => Some(None),
+            id => Some(Some(id)),
+        }
+    }
+
+    /// Draws `instance_count` instances from `instance_buffer` and copies the id at `cursor`, in
+    /// physical pixels, into the readback buffer. Does nothing while a previous pass is still in
+    /// flight or the cursor is outside of the target.
+    pub fn submit(
+        &mut self,
+        cursor: (u32, u32),
+        vertex_buffer: &BufferImpl,
+        instance_buffer: &BufferImpl,
+        instance_count: u32,
+        view_proj: &[u32; 16],
+    ) {
+        let (x, y) = cursor;
+        if self.pending || x >= self.extent.width || y >= self.extent.height {
+            return;
+        }
+
+        let device = &self.device;
+        let fence = self.fence.as_ref().unwrap();
+        device.device.reset_fence(fence).unwrap();
+
+        let command_pool = self.command_pool.as_mut().unwrap();
+        command_pool.reset();
+
+        let submit = {
+            let mut cmd_buffer = command_pool.acquire_command_buffer(false);
+            let layout = self.pipeline.pipeline_layout.as_ref().unwrap();
+            let viewport = pso::Viewport {
+                rect: pso::Rect {
+                    x: 0,
+                    y: 0,
+                    w: self.extent.width as i16,
+                    h: self.extent.height as i16,
+                },
+                depth: 0.0..1.0,
+            };
+            let scissor = pso::Rect {
+                x: x as i16,
+                y: y as i16,
+                w: 1,
+                h: 1,
+            };
+
+            let mut clear_values = vec![command::ClearValue::Color(command::ClearColor::Uint([
+                NO_ENTITY, 0, 0, 0,
+            ]))];
+            if self.depth.is_some() {
+                clear_values.push(command::ClearValue::DepthStencil(
+                    command::ClearDepthStencil(1.0, 0),
+                ));
+            }
+
+            cmd_buffer.set_viewports(0, &[viewport]);
+            cmd_buffer.set_scissors(0, &[scissor]);
+            cmd_buffer.bind_graphics_pipeline(self.pipeline.pipeline.as_ref().unwrap());
+            cmd_buffer.bind_vertex_buffers(0, Some((vertex_buffer, 0)));
+            cmd_buffer.bind_vertex_buffers(1, Some((instance_buffer, 0)));
+            cmd_buffer.push_graphics_constants(layout, pso::ShaderStageFlags::VERTEX, 0, view_proj);
+
+            {
+                let mut encoder = cmd_buffer.begin_render_pass_inline(
+                    self.render_pass.render_pass.as_ref().unwrap(),
+                    self.framebuffer.as_ref().unwrap(),
+                    scissor,
+                    &clear_values,
+                );
+                encoder.draw(0..18, 0..instance_count);
+            }
+
+            // The render pass leaves the target in `TransferSrcOptimal`, its outgoing dependency
+            // makes the writes visible to the copy.
+            cmd_buffer.copy_image_to_buffer(
+                self.target.get_image(),
+                image::Layout::TransferSrcOptimal,
+                self.readback.get_buffer(),
+                &[command::BufferImageCopy {
+                    buffer_offset: 0,
+                    buffer_width: 0,
+                    buffer_height: 0,
+                    image_layers: image::SubresourceLayers {
+                        aspects: format::Aspects::COLOR,
+                        level: 0,
+                        layers: 0..1,
+                    },
+                    image_offset: image::Offset {
+                        x: x as i32,
+                        y: y as i32,
+                        z: 0,
+                    },
+                    image_extent: image::Extent {
+                        width: 1,
+                        height: 1,
+                        depth: 1,
+                    },
+                }],
+            );
+            cmd_buffer.pipeline_barrier(
+                pso::PipelineStage::TRANSFER..pso::PipelineStage::HOST,
+                memory::Dependencies::empty(),
+                &[memory::Barrier::Buffer {
+                    states: buffer::Access::TRANSFER_WRITE..buffer::Access::HOST_READ,
+                    target: self.readback.get_buffer(),
+                    families: None,
+                    range: None..None,
+                }],
+            );
+
+            cmd_buffer.finish()
+        };
+
+        let submission = queue::Submission::new().submit(Some(submit));
+        device.queues.lock().unwrap().queues[0].submit(submission, Some(fence));
+        self.pending = true;
+    }
+}
+
+impl Drop for IdPickingState {
+    fn drop(&mut self) {
+        let device = &self.device.device;
+        if self.pending {
+            device
+                .wait_for_fence(self.fence.as_ref().unwrap(), !0)
+                .unwrap();
+        }
+        device.destroy_fence(self.fence.take().unwrap());
+        device.destroy_command_pool(self.command_pool.take().unwrap().into_raw());
+        device.destroy_framebuffer(self.framebuffer.take().unwrap());
+    }
+}
@@ -0,0 +1,100 @@
+use super::{DeviceState, ImageImpl, ImageViewImpl, MemoryImpl};
+use hal::{self, format, image, memory, Device};
+use std::sync::Arc;
+
+/// A device local image together with its memory and a single view covering every mip level and
+/// layer. Used for render targets that live next to the swapchain images.
+pub struct ImageState {
+    memory: Option<MemoryImpl>,
+    image: Option<ImageImpl>,
+    view: Option<ImageViewImpl>,
+    device: Arc<DeviceState>,
+    pub format: format::Format,
+    pub kind: image::Kind,
+}
+
+impl ImageState {
+    pub fn new(
+        device_ptr: Arc<DeviceState>,
+        memory_types: &[hal::MemoryType],
+        kind: image::Kind,
+        mip_levels: image::Level,
+        format: format::Format,
+        usage: image::Usage,
+        aspects: format::Aspects,
+    ) -> Self {
+        let memory: MemoryImpl;
+        let image: ImageImpl;
+        let view: ImageViewImpl;
+
+        {
+            let device = &device_ptr.device;
+
+            let unbound = device
+                .create_image(
+                    kind,
+                    mip_levels,
+                    format,
+                    image::Tiling::Optimal,
+                    usage,
+                    image::ViewCapabilities::empty(),
+                )
+                .unwrap();
+            let mem_req = device.get_image_requirements(&unbound);
+
+            let device_type = memory_types
+                .iter()
+                .enumerate()
+                .position(|(id, mem_type)| {
+                    mem_req.type_mask & (1 << id) != 0
+                        && mem_type
+                            .properties
+                            .contains(memory::Properties::DEVICE_LOCAL)
+                })
+                .unwrap()
+                .into();
+
+            memory = device.allocate_memory(device_type, mem_req.size).unwrap();
+            image = device.bind_image_memory(&memory, 0, unbound).unwrap();
+            view = device
+                .create_image_view(
+                    &image,
+                    image::ViewKind::D2,
+                    format,
+                    format::Swizzle::NO,
+                    image::SubresourceRange {
+                        aspects,
+                        levels: 0..mip_levels,
+                        layers: 0..kind.num_layers(),
+                    },
+                )
+                .unwrap();
+        }
+
+        ImageState {
+            memory: Some(memory),
+            image: Some(image),
+            view: Some(view),
+            device: device_ptr,
+            format,
+            kind,
+        }
+    }
+
+    pub fn get_image(&self) -> &ImageImpl {
+        self.image.as_ref().unwrap()
+    }
+
+    pub fn get_view(&self) -> &ImageViewImpl {
+        self.view.as_ref().unwrap()
+    }
+}
+
+impl Drop for ImageState {
+    fn drop(&mut self) {
+        let device = &self.device.device;
+        device.destroy_image_view(self.view.take().unwrap());
+        device.destroy_image(self.image.take().unwrap());
+        device.free_memory(self.memory.take().unwrap());
+    }
+}
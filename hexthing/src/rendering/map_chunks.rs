@@ -0,0 +1,144 @@
+use super::grid::{self, GridStyle};
+use super::{
+    BackendImpl, BufferImpl, CountingEncoder, DescriptorSetImpl, DeviceState, DrawStats,
+    FramebufferImpl, PipelineState, RenderPassImpl, StatisticsQueries, SubpassCommands,
+};
+use hal::{self, pass, pool, pso, Device, Graphics};
+use rayon::prelude::*;
+use std::ops::Range;
+use std::sync::Arc;
+
+/// Hex instances drawn by one chunk. Maps smaller than this are recorded on a single worker.
+const CHUNK_INSTANCES: u32 = 2048;
+
+/// Everything the workers need to draw the map. Only holds shared references, which every thread
+/// may read from at once.
+pub struct MapDraw<'a> {
+    pub render_pass: &'a RenderPassImpl,
+    pub framebuffer: &'a FramebufferImpl,
+    pub viewport: &'a pso::Viewport,
+    pub main: &'a PipelineState,
+    /// The grid pipeline and style, when the outline overlay is enabled.
+    pub grid: Option<(&'a PipelineState, GridStyle)>,
+    pub descriptor_set: &'a DescriptorSetImpl,
+    pub vertex_buffer: &'a BufferImpl,
+    pub instance_buffer: &'a BufferImpl,
+    pub instance_count: u32,
+    pub push_constants: &'a [u32; 17],
+    /// Hands every chunk a pipeline statistics query, when they are enabled.
+    pub statistics: Option<&'a StatisticsQueries>,
+}
+
+/// Records the hex map into secondary command buffers, one per chunk of instances, in parallel on
+/// the rayon thread pool. The main thread then executes them in a single render pass.
+///
+/// Command pools may only be used by one thread at a time, so every chunk has its own pool, and
+/// since a pool can't be reset while the GPU still executes its buffers there is a set of pools
+/// per frame in flight.
+pub struct MapChunkRecorder {
+    pools: Vec<Vec<hal::CommandPool<BackendImpl, Graphics>>>,
+    device: Arc<DeviceState>,
+}
+
+impl MapChunkRecorder {
+    pub fn new(device: &Arc<DeviceState>, frames_in_flight: usize) -> Self {
+        MapChunkRecorder {
+            pools: (0..frames_in_flight).map(|_| Vec::new()).collect(),
+            device: Arc::clone(device),
+        }
+    }
+
+    /// Records `draw` for the frame at `frame_index`, which the GPU has to be done with. Returns
+    /// the commands of every chunk together with what they draw.
+    pub fn record(
+        &mut self,
+        frame_index: usize,
+        draw: &MapDraw,
+    ) -> (Vec<SubpassCommands>, DrawStats) {
+        let chunks: Vec<Range<u32>> = (0..draw.instance_count)
+            .step_by(CHUNK_INSTANCES as usize)
+            .map(|start| start..(start + CHUNK_INSTANCES).min(draw.instance_count))
+            .collect();
+
+        let pools = &mut self.pools[frame_index];
+        while pools.len() < chunks.len() {
+            let queues = self.device.queues.lock().unwrap();
+            pools.push(
+                self.device
+                    .device
+                    .create_command_pool_typed(&*queues, pool::CommandPoolCreateFlags::empty(), 1)
+                    .expect("Can't create command pool"),
+            );
+        }
+
+        let (commands, chunk_stats): (Vec<SubpassCommands>, Vec<DrawStats>) = pools[..chunks.len()]
+            .par_iter_mut()
+            .zip(chunks.par_iter())
+            .map(|(pool, instances)| {
+                pool.reset();
+                record_chunk(pool, draw, instances.clone())
+            })
+            .unzip();
+        (commands, chunk_stats.into_iter().sum())
+    }
+}
+
+fn record_chunk(
+    pool: &mut hal::CommandPool<BackendImpl, Graphics>,
+    draw: &MapDraw,
+    instances: Range<u32>,
+) -> (SubpassCommands, DrawStats) {
+    let subpass = pass::Subpass {
+        index: 0,
+        main_pass: draw.render_pass,
+    };
+    let mut cmd_buffer = CountingEncoder::new(
+        pool.acquire_subpass_command_buffer(subpass, Some(draw.framebuffer), false),
+        draw.statistics.and_then(|queries| queries.next_query()),
+    );
+
+    // Dynamic state isn't inherited from the primary command buffer.
+    cmd_buffer.set_viewports(0, &[draw.viewport.clone()]);
+    cmd_buffer.set_scissors(0, &[draw.viewport.rect]);
+    cmd_buffer.bind_vertex_buffers(0, Some((draw.vertex_buffer, 0)));
+    cmd_buffer.bind_vertex_buffers(1, Some((draw.instance_buffer, 0)));
+
+    let layout = draw.main.pipeline_layout.as_ref().unwrap();
+    cmd_buffer.bind_graphics_pipeline(draw.main.pipeline.as_ref().unwrap());
+    cmd_buffer.bind_graphics_descriptor_sets(layout, 0, Some(draw.descriptor_set), &[]);
+    cmd_buffer.push_graphics_constants(
+        layout,
+        pso::ShaderStageFlags::VERTEX,
+        0,
+        draw.push_constants,
+    );
+    cmd_buffer.draw(0..18, instances.clone());
+
+    if let Some((grid, style)) = draw.grid {
+        let layout = grid.pipeline_layout.as_ref().unwrap();
+        cmd_buffer.bind_graphics_pipeline(grid.pipeline.as_ref().unwrap());
+        cmd_buffer.push_graphics_constants(
+            layout,
+            pso::ShaderStageFlags::VERTEX,
+            0,
+            draw.push_constants,
+        );
+        cmd_buffer.push_graphics_constants(
+            layout,
+            pso::ShaderStageFlags::FRAGMENT,
+            grid::FRAGMENT_CONSTANTS_OFFSET,
+            &style.push_constants(),
+        );
+        cmd_buffer.draw(0..18, instances);
+    }
+
+    cmd_buffer.finish()
+}
+
+impl Drop for MapChunkRecorder {
+    fn drop(&mut self) {
+        for pool in self.pools.drain(..).flat_map(|pools| pools) {
+            self.device.device.destroy_command_pool(pool.into_raw());
+        }
+    }
+}
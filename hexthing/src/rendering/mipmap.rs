@@ -0,0 +1,197 @@
+const BYTES_PER_PIXEL: usize = 4;
+
+pub struct MipLevel {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+/// Number of levels in a full mip chain, down to and including the 1x1 level.
+pub fn mip_level_count(width: u32, height: u32) -> u8 {
+    (32 - width.max(height).max(1).leading_zeros()) as u8
+}
+
+/// Builds `levels` mip levels from sRGB encoded RGBA pixels by repeatedly applying a 2x2 box
+/// filter. The first level is the source image itself.
+pub fn generate_mip_chain(width: u32, height: u32, pixels: &[u8], levels: u8) -> Vec<MipLevel> {
+    assert_eq!(
+        pixels.len(),
+        width as usize * height as usize * BYTES_PER_PIXEL
+    );
+
+    let mut chain = vec![MipLevel {
+        width,
+        height,
+        pixels: pixels.to_vec(),
+    }];
+    for _ in 1..levels {
+        let next = downsample(chain.last().unwrap());
+        chain.push(next);
+    }
+    chain
+}
+
+/// Halves both dimensions (rounding down, but never below one texel), averaging each 2x2 block.
+/// Color channels are averaged in linear space so that the chain doesn't darken as it shrinks.
+/// When a dimension is odd the last row or column is clamped.
+pub fn downsample(level: &MipLevel) -> MipLevel {
+    let width = (level.width / 2).max(1);
+    let height = (level.height / 2).max(1);
+    let mut pixels = Vec::with_capacity(width as usize * height as usize * BYTES_PER_PIXEL);
+
+    let texel = |x: u32, y: u32, channel: usize| {
+        let x = x.min(level.width - 1) as usize;
+        let y = y.min(level.height - 1) as usize;
+        level.pixels[(y * level.width as usize + x) * BYTES_PER_PIXEL + channel]
+    };
+
+    for y in 0..height {
+        for x in 0..width {
+            let samples = [
+                (x * 2, y * 2),
+                (x * 2 + 1, y * 2),
+                (x * 2, y * 2 + 1),
+                (x * 2 + 1, y * 2 + 1),
+            ];
+            for channel in 0..BYTES_PER_PIXEL {
+                let value = if channel == 3 {
+                    let sum: u32 = samples
+                        .iter()
+                        .map(|&(sx, sy)| u32::from(texel(sx, sy, channel)))
+                        .sum();
+                    ((sum + 2) / 4) as u8
+                } else {
+                    let sum: f32 = samples
+                        .iter()
+                        .map(|&(sx, sy)| srgb_to_linear(texel(sx, sy, channel)))
+                        .sum();
+                    linear_to_srgb(sum / 4.0)
+                };
+                pixels.push(value);
+            }
+        }
+    }
+
+    MipLevel {
+        width,
+        height,
+        pixels,
+    }
+}
+
+fn srgb_to_linear(value: u8) -> f32 {
+    let c = f32::from(value) / 255.0;
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(value: f32) -> u8 {
+    let c = if value <= 0.003_130_8 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    };
+    (c * 255.0).round().max(0.0).min(255.0) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solid(width: u32, height: u32, rgba: [u8; 4]) -> Vec<u8> {
+        (0..width * height).flat_map(|_| rgba.to_vec()).collect()
+    }
+
+    #[test]
+    fn level_count_covers_the_larger_dimension() {
+        assert_eq!(mip_level_count(1, 1), 1);
+        assert_eq!(mip_level_count(64, 64), 7);
+        assert_eq!(mip_level_count(64, 1), 7);
+        assert_eq!(mip_level_count(100, 30), 7);
+        assert_eq!(mip_level_count(127, 128), 8);
+        assert_eq!(mip_level_count(129, 3), 8);
+    }
+
+    #[test]
+    fn odd_and_thin_levels_clamp_at_one() {
+        let chain = generate_mip_chain(5, 1, &solid(5, 1, [255; 4]), mip_level_count(5, 1));
+        let sizes: Vec<_> = chain
+            .iter()
+            .map(|level| (level.width, level.height))
+            .collect();
+        assert_eq!(sizes, vec![(5, 1), (2, 1), (1, 1)]);
+
+        let chain = generate_mip_chain(1, 6, &solid(1, 6, [255; 4]), mip_level_count(1, 6));
+        let sizes: Vec<_> = chain
+            .iter()
+            .map(|level| (level.width, level.height))
+            .collect();
+        assert_eq!(sizes, vec![(1, 6), (1, 3), (1, 1)]);
+        for level in &chain {
+            assert_eq!(
+                level.pixels.len(),
+                (level.width * level.height) as usize * BYTES_PER_PIXEL
+            );
+        }
+    }
+
+    #[test]
+    fn odd_sizes_clamp_the_last_row_and_column() {
+        // A 3x3 image whose last column and row are white. The only texel of the 1x1 level
+        // averages the top left 2x2 block, which is black.
+        let mut pixels = solid(3, 3, [0, 0, 0, 255]);
+        for y in 0..3 {
+            for x in 0..3 {
+                if x == 2 || y == 2 {
+                    let i = (y * 3 + x) * BYTES_PER_PIXEL;
+                    pixels[i..i + 3].copy_from_slice(&[255, 255, 255]);
+                }
+            }
+        }
+        let level = downsample(&MipLevel {
+            width: 3,
+            height: 3,
+            pixels,
+        });
+        assert_eq!((level.width, level.height), (1, 1));
+        assert_eq!(level.pixels, vec![0, 0, 0, 255]);
+    }
+
+    #[test]
+    fn colors_are_averaged_in_linear_space() {
+        // Two black and two white texels. Averaging the sRGB values would give 128, averaging
+        // the linear values and encoding the result gives 188.
+        let mut pixels = solid(2, 2, [0, 0, 0, 255]);
+        pixels[4..8].copy_from_slice(&[255, 255, 255, 255]);
+        pixels[8..12].copy_from_slice(&[255, 255, 255, 255]);
+        let level = downsample(&MipLevel {
+            width: 2,
+            height: 2,
+            pixels,
+        });
+        assert_eq!(level.pixels, vec![188, 188, 188, 255]);
+    }
+
+    #[test]
+    fn alpha_is_averaged_directly() {
+        let mut pixels = solid(2, 2, [255, 255, 255, 0]);
+        pixels[3] = 255;
+        pixels[7] = 255;
+        let level = downsample(&MipLevel {
+            width: 2,
+            height: 2,
+            pixels,
+        });
+        assert_eq!(level.pixels[3], 128);
+    }
+
+    #[test]
+    fn srgb_round_trips() {
+        for value in 0..=255u8 {
+            assert_eq!(linear_to_srgb(srgb_to_linear(value)), value);
+        }
+    }
+}
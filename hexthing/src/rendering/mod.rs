@@ -1,29 +1,75 @@
 mod adapter_state;
+mod atlas;
 mod backend_state;
 mod buffer_state;
+mod camera;
+mod debug_draw;
+mod depth;
+mod descriptor_allocator;
 mod descriptor_set;
 mod device_state;
+mod fog;
+mod frame_context;
+mod frame_stats;
 mod framebuffer_state;
+mod gpu_timer;
+mod grid;
+mod id_picking;
+mod image_state;
+mod map_chunks;
+mod mipmap;
+mod pipeline_cache;
 mod pipeline_state;
+mod profiler;
+mod query_results;
+mod registry;
 mod render_pass_state;
 mod renderer_state;
+mod statistics_queries;
 mod swapchain_state;
+mod text;
+mod text_state;
+mod texture_state;
 mod uniform;
+mod upload_arena;
 
 use hal::Backend;
 
+pub use self::atlas::{Atlas, AtlasBuilder, UvRect};
+pub use self::camera::Camera;
+pub use self::debug_draw::DebugDraw;
+pub use self::depth::{DepthMode, Layer};
+pub use self::frame_stats::{DrawStats, PipelineStatistics};
+pub use self::grid::GridStyle;
+pub use self::profiler::{
+    CpuScope, FrameTimings, Profiler, ScopeStats, ScopeTiming, Timeline, FRAME_SCOPE,
+};
 pub use self::renderer_state::RendererState;
 
-use self::adapter_state::AdapterState;
+use self::adapter_state::{AdapterRequirements, AdapterState};
 use self::backend_state::BackendState;
 use self::buffer_state::BufferState;
+use self::descriptor_allocator::{DescriptorAllocator, PoolId};
 use self::descriptor_set::{DescSet, DescSetLayout, DescSetWrite};
-use self::device_state::DeviceState;
+use self::device_state::{DeviceState, PendingUpload, UploadPools, UploadSubmission};
+use self::frame_context::FrameContext;
+use self::frame_stats::{CountingEncoder, SubpassCommands};
 use self::framebuffer_state::FramebufferState;
-use self::pipeline_state::PipelineState;
+use self::gpu_timer::GpuTimer;
+use self::id_picking::IdPickingState;
+use self::image_state::ImageState;
+use self::map_chunks::{MapChunkRecorder, MapDraw};
+use self::pipeline_cache::PipelineCache;
+use self::pipeline_state::{PipelineConfig, PipelineState};
+use self::query_results::read_query_results;
+use self::registry::{Handle, ResourceRegistry};
 use self::render_pass_state::RenderPassState;
+use self::statistics_queries::StatisticsQueries;
 use self::swapchain_state::SwapchainState;
+use self::text_state::TextState;
+use self::texture_state::TextureState;
 use self::uniform::Uniform;
+use self::upload_arena::{ArenaSlice, UploadArena};
 
 type BackendImpl = back::Backend;
 type BufferImpl = <BackendImpl as Backend>::Buffer;
@@ -42,4 +88,7 @@ type SwapchainImpl = <BackendImpl as Backend>::Swapchain;
 type RenderPassImpl = <BackendImpl as Backend>::RenderPass;
 type MemoryImpl = <BackendImpl as Backend>::Memory;
 type PhysicalDeviceImpl = <BackendImpl as Backend>::PhysicalDevice;
+type PipelineCacheImpl = <BackendImpl as Backend>::PipelineCache;
 type PipelineLayoutImpl = <BackendImpl as Backend>::PipelineLayout;
+type QueryPoolImpl = <BackendImpl as Backend>::QueryPool;
+type SamplerImpl = <BackendImpl as Backend>::Sampler;
@@ -0,0 +1,89 @@
+use hal::{AdapterInfo, Device};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use super::{DeviceImpl, PipelineCacheImpl};
+
+#[cfg(feature = "vulkan")]
+const BACKEND_NAME: &str = "vulkan";
+#[cfg(feature = "dx12")]
+const BACKEND_NAME: &str = "dx12";
+#[cfg(feature = "metal")]
+const BACKEND_NAME: &str = "metal";
+#[cfg(not(any(feature = "vulkan", feature = "dx12", feature = "metal")))]
+const BACKEND_NAME: &str = "empty";
+
+/// A pipeline cache that is seeded from and written back to a file in the user cache directory.
+///
+/// The file is keyed by backend and adapter, so switching GPUs doesn't feed one driver another
+/// driver's data. Drivers validate the header of the data they are given and ignore data from
+/// other driver versions, so a driver update only costs one cold start.
+pub struct PipelineCache {
+    pub cache: Option<PipelineCacheImpl>,
+    /// `None` when the platform has no cache directory, in which case nothing is persisted.
+    path: Option<PathBuf>,
+}
+
+impl PipelineCache {
+    pub fn new(device: &DeviceImpl, info: &AdapterInfo) -> Self {
+        let path = dirs::cache_dir().map(|dir| dir.join("hexthing").join(file_name(info)));
+        let data = path.as_ref().and_then(|path| match fs::read(path) {
+            Ok(data) => Some(data),
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => None,
+            Err(err) => {
+                warn!("Can't read pipeline cache {}: {}", path.display(), err);
+                None
+            }
+        });
+
+        let cache = device
+            .create_pipeline_cache(data.as_ref().map(Vec::as_slice))
+            .or_else(|_| device.create_pipeline_cache(None))
+            .expect("Can't create pipeline cache");
+
+        PipelineCache {
+            cache: Some(cache),
+            path,
+        }
+    }
+
+    /// Writes the current contents of the cache to disk. Failures are reported but not fatal,
+    /// the cache only saves time.
+    pub fn save(&self, device: &DeviceImpl) {
+        let path = match self.path {
+            Some(ref path) => path,
+            None => return,
+        };
+
+        let result = device
+            .get_pipeline_cache_data(self.cache.as_ref().unwrap())
+            .map_err(|err| format!("{:?}", err))
+            .and_then(|data| {
+                fs::create_dir_all(path.parent().unwrap())
+                    .and_then(|_| fs::write(path, data))
+                    .map_err(|err| err.to_string())
+            });
+        if let Err(err) = result {
+            warn!("Can't save pipeline cache {}: {}", path.display(), err);
+        }
+    }
+
+    pub fn destroy(&mut self, device: &DeviceImpl) {
+        if let Some(cache) = self.cache.take() {
+            device.destroy_pipeline_cache(cache);
+        }
+    }
+}
+
+fn file_name(info: &AdapterInfo) -> String {
+    let name: String = info
+        .name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    format!(
+        "pipelines-{}-{:04x}-{:04x}-{}.bin",
+        BACKEND_NAME, info.vendor, info.device, name
+    )
+}
@@ -1,134 +1,219 @@
 use hal::{format, pass, pso, Device};
-use std::cell::RefCell;
 use std::fs;
 use std::io::Read;
 use std::mem::size_of;
-use std::rc::Rc;
+use std::ops::Range;
+use std::sync::Arc;
 
 use super::{
     BackendImpl, DescriptorSetLayoutImpl, DeviceState, GraphicsPipelineImpl, PipelineLayoutImpl,
-    RenderPassImpl,
+    RenderPassState,
 };
-use definitions::Vertex;
+use definitions::{HexInstance, Vertex};
 
 const ENTRY_NAME: &str = "main";
 
+/// Everything that distinguishes one graphics pipeline from another. Shaders are given as paths to
+/// GLSL sources that are compiled when the pipeline is created.
+#[derive(Clone)]
+pub struct PipelineConfig {
+    pub vertex_shader: String,
+    pub fragment_shader: String,
+    pub primitive: hal::Primitive,
+    pub blend: pso::BlendState,
+    pub depth_stencil: pso::DepthStencilDesc,
+    pub push_constants: Vec<(pso::ShaderStageFlags, Range<u32>)>,
+    pub vertex_buffers: Vec<pso::VertexBufferDesc>,
+    pub attributes: Vec<pso::AttributeDesc>,
+}
+
+impl PipelineConfig {
+    /// A pipeline drawing the hex mesh once per `HexInstance`, with the camera's view projection
+    /// matrix and the layer depth pushed as vertex stage constants.
+    pub fn hex(vertex_shader: &str, fragment_shader: &str) -> Self {
+        PipelineConfig {
+            vertex_shader: vertex_shader.to_owned(),
+            fragment_shader: fragment_shader.to_owned(),
+            primitive: hal::Primitive::TriangleList,
+            blend: pso::BlendState::ALPHA,
+            depth_stencil: pso::DepthStencilDesc::default(),
+            push_constants: vec![(pso::ShaderStageFlags::VERTEX, 0..68)],
+            vertex_buffers: vec![
+                pso::VertexBufferDesc {
+                    binding: 0,
+                    stride: size_of::<Vertex>() as u32,
+                    rate: 0,
+                },
+                pso::VertexBufferDesc {
+                    binding: 1,
+                    stride: size_of::<HexInstance>() as u32,
+                    rate: 1,
+                },
+            ],
+            attributes: vec![
+                pso::AttributeDesc {
+                    location: 0,
+                    binding: 0,
+                    element: pso::Element {
+                        format: format::Format::Rg32Float,
+                        offset: 0,
+                    },
+                },
+                pso::AttributeDesc {
+                    location: 1,
+                    binding: 1,
+                    element: pso::Element {
+                        format: format::Format::Rg32Float,
+                        offset: 0,
+                    },
+                },
+                pso::AttributeDesc {
+                    location: 2,
+                    binding: 1,
+                    element: pso::Element {
+                        format: format::Format::Rgba32Float,
+                        offset: size_of::<Vertex>() as u32,
+                    },
+                },
+            ],
+        }
+    }
+}
+
+fn compile_shader(path: &str, shader_type: glsl_to_spirv::ShaderType) -> Vec<u8> {
+    let glsl = fs::read_to_string(path).unwrap();
+    glsl_to_spirv::compile(&glsl, shader_type)
+        .unwrap()
+        .bytes()
+        .map(|b| b.unwrap())
+        .collect()
+}
+
 pub struct PipelineState {
     pub pipeline: Option<GraphicsPipelineImpl>,
     pub pipeline_layout: Option<PipelineLayoutImpl>,
-    device: Rc<RefCell<DeviceState>>,
+    config: PipelineConfig,
+    /// Compiled shaders, kept so the pipeline can be rebuilt for a new render pass without going
+    /// through GLSL again.
+    vertex_spirv: Vec<u8>,
+    fragment_spirv: Vec<u8>,
+    device: Arc<DeviceState>,
 }
 
 impl PipelineState {
     pub fn new<IS>(
         desc_layouts: IS,
-        render_pass: &RenderPassImpl,
-        device_ptr: &Rc<RefCell<DeviceState>>,
+        render_pass: &RenderPassState,
+        config: &PipelineConfig,
+        device_ptr: &Arc<DeviceState>,
     ) -> Self
     where
         IS: IntoIterator,
         IS::Item: std::borrow::Borrow<DescriptorSetLayoutImpl>,
     {
-        let device = &device_ptr.borrow().device;
-        let pipeline_layout = device
-            .create_pipeline_layout(desc_layouts, &[(pso::ShaderStageFlags::VERTEX, 0..8)])
+        let pipeline_layout = device_ptr
+            .device
+            .create_pipeline_layout(desc_layouts, &config.push_constants)
             .expect("Can't create pipeline layout");
 
-        let pipeline = {
-            let vs_module = {
-                let glsl = fs::read_to_string("src/shaders/hex.vert").unwrap();
-                let spirv: Vec<u8> =
-                    glsl_to_spirv::compile(&glsl, glsl_to_spirv::ShaderType::Vertex)
-                        .unwrap()
-                        .bytes()
-                        .map(|b| b.unwrap())
-                        .collect();
-                device.create_shader_module(&spirv).unwrap()
-            };
-            let fs_module = {
-                let glsl = fs::read_to_string("src/shaders/hex.frag").unwrap();
-                let spirv: Vec<u8> =
-                    glsl_to_spirv::compile(&glsl, glsl_to_spirv::ShaderType::Fragment)
-                        .unwrap()
-                        .bytes()
-                        .map(|b| b.unwrap())
-                        .collect();
-                device.create_shader_module(&spirv).unwrap()
-            };
+        let mut state = PipelineState {
+            pipeline: None,
+            pipeline_layout: Some(pipeline_layout),
+            config: config.clone(),
+            vertex_spirv: compile_shader(&config.vertex_shader, glsl_to_spirv::ShaderType::Vertex),
+            fragment_spirv: compile_shader(
+                &config.fragment_shader,
+                glsl_to_spirv::ShaderType::Fragment,
+            ),
+            device: Arc::clone(&device_ptr),
+        };
+        state.rebuild(render_pass);
+        state
+    }
 
-            let pipeline = {
-                let (vs_entry, fs_entry) = (
-                    pso::EntryPoint::<BackendImpl> {
-                        entry: ENTRY_NAME,
-                        module: &vs_module,
-                        specialization: pso::Specialization::default(),
-                    },
-                    pso::EntryPoint::<BackendImpl> {
-                        entry: ENTRY_NAME,
-                        module: &fs_module,
-                        specialization: pso::Specialization::default(),
-                    },
-                );
-
-                let shader_entries = pso::GraphicsShaderSet {
-                    vertex: vs_entry,
-                    hull: None,
-                    domain: None,
-                    geometry: None,
-                    fragment: Some(fs_entry),
-                };
-
-                let subpass = pass::Subpass {
-                    index: 0,
-                    main_pass: render_pass,
-                };
-
-                let mut pipeline_desc = pso::GraphicsPipelineDesc::new(
-                    shader_entries,
-                    hal::Primitive::TriangleList,
-                    pso::Rasterizer::FILL,
-                    &pipeline_layout,
-                    subpass,
-                );
-                pipeline_desc.blender.targets.push(pso::ColorBlendDesc(
-                    pso::ColorMask::ALL,
-                    pso::BlendState::ALPHA,
-                ));
-                pipeline_desc.vertex_buffers.push(pso::VertexBufferDesc {
-                    binding: 0,
-                    stride: size_of::<Vertex>() as u32,
-                    rate: 0,
-                });
+    /// Recreates the pipeline for `render_pass`, keeping the layout and the compiled shaders.
+    pub fn rebuild(&mut self, render_pass: &RenderPassState) {
+        let device_state = &self.device;
+        let device = &device_state.device;
+        if let Some(pipeline) = self.pipeline.take() {
+            device.destroy_graphics_pipeline(pipeline);
+        }
 
-                pipeline_desc.attributes.push(pso::AttributeDesc {
-                    location: 0,
-                    binding: 0,
-                    element: pso::Element {
-                        format: format::Format::Rg32Float,
-                        offset: 0,
-                    },
-                });
+        let config = &self.config;
+        let vs_module = device.create_shader_module(&self.vertex_spirv).unwrap();
+        let fs_module = device.create_shader_module(&self.fragment_spirv).unwrap();
+
+        let pipeline = {
+            let (vs_entry, fs_entry) = (
+                pso::EntryPoint::<BackendImpl> {
+                    entry: ENTRY_NAME,
+                    module: &vs_module,
+                    specialization: pso::Specialization::default(),
+                },
+                pso::EntryPoint::<BackendImpl> {
+                    entry: ENTRY_NAME,
+                    module: &fs_module,
+                    specialization: pso::Specialization::default(),
+                },
+            );
 
-                device.create_graphics_pipeline(&pipeline_desc, None)
+            let shader_entries = pso::GraphicsShaderSet {
+                vertex: vs_entry,
+                hull: None,
+                domain: None,
+                geometry: None,
+                fragment: Some(fs_entry),
             };
 
-            device.destroy_shader_module(vs_module);
-            device.destroy_shader_module(fs_module);
+            let subpass = pass::Subpass {
+                index: 0,
+                main_pass: render_pass.render_pass.as_ref().unwrap(),
+            };
+
+            let mut pipeline_desc = pso::GraphicsPipelineDesc::new(
+                shader_entries,
+                config.primitive,
+                pso::Rasterizer::FILL,
+                self.pipeline_layout.as_ref().unwrap(),
+                subpass,
+            );
+            pipeline_desc
+                .blender
+                .targets
+                .push(pso::ColorBlendDesc(pso::ColorMask::ALL, config.blend));
+            pipeline_desc.depth_stencil = config.depth_stencil;
+            if render_pass.samples > 1 {
+                pipeline_desc.multisampling = Some(pso::Multisampling {
+                    rasterization_samples: render_pass.samples,
+                    sample_shading: None,
+                    sample_mask: !0,
+                    alpha_coverage: false,
+                    alpha_to_one: false,
+                });
+            }
+            pipeline_desc
+                .vertex_buffers
+                .extend(config.vertex_buffers.iter().cloned());
+            pipeline_desc
+                .attributes
+                .extend(config.attributes.iter().cloned());
 
-            pipeline.unwrap()
+            device.create_graphics_pipeline(
+                &pipeline_desc,
+                device_state.pipeline_cache.cache.as_ref(),
+            )
         };
 
-        PipelineState {
-            pipeline: Some(pipeline),
-            pipeline_layout: Some(pipeline_layout),
-            device: Rc::clone(&device_ptr),
-        }
+        device.destroy_shader_module(vs_module);
+        device.destroy_shader_module(fs_module);
+
+        self.pipeline = Some(pipeline.unwrap());
     }
 }
 
 impl Drop for PipelineState {
     fn drop(&mut self) {
-        let device = &self.device.borrow().device;
+        let device = &self.device.device;
         device.destroy_graphics_pipeline(self.pipeline.take().unwrap());
         device.destroy_pipeline_layout(self.pipeline_layout.take().unwrap());
     }
@@ -0,0 +1,459 @@
+use super::debug_draw::DebugVertex;
+use serde_json;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// Frames kept for statistics, the overlay graph and trace export.
+const HISTORY_FRAMES: usize = 240;
+
+/// Frame time at the top of the overlay graph, in milliseconds.
+const GRAPH_MAX_MS: f32 = 33.3;
+
+const CPU_COLOR: [f32; 4] = [0.2, 0.8, 1.0, 1.0];
+const GPU_COLOR: [f32; 4] = [1.0, 0.6, 0.1, 1.0];
+const GRID_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 0.3];
+
+/// Name of the scope spanning a whole frame, on both timelines.
+pub const FRAME_SCOPE: &str = "frame";
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Timeline {
+    Cpu,
+    Gpu,
+}
+
+/// A measured scope, with `start` relative to the start of its frame on the same timeline.
+#[derive(Debug, Clone)]
+pub struct ScopeTiming {
+    pub name: &'static str,
+    pub start: Duration,
+    pub duration: Duration,
+}
+
+/// Everything measured during one frame. The GPU scopes arrive a few frames late and stay empty
+/// until then.
+#[derive(Debug, Clone)]
+pub struct FrameTimings {
+    pub serial: u64,
+    pub start: Instant,
+    pub cpu: Vec<ScopeTiming>,
+    pub gpu: Vec<ScopeTiming>,
+}
+
+/// Rolling statistics of one scope over the frames in the history that measured it.
+#[derive(Debug, Clone)]
+pub struct ScopeStats {
+    pub name: &'static str,
+    pub timeline: Timeline,
+    pub average: Duration,
+    pub min: Duration,
+    pub max: Duration,
+    /// 95th percentile, which unlike `max` isn't thrown off by a single hitch.
+    pub p95: Duration,
+    pub samples: usize,
+}
+
+/// A CPU scope that has been begun and still has to be passed to `Profiler::end_cpu`.
+pub struct CpuScope {
+    name: &'static str,
+    start: Instant,
+}
+
+#[derive(Serialize)]
+struct TraceEvent {
+    name: &'static str,
+    cat: &'static str,
+    ph: &'static str,
+    /// Microseconds since the first frame in the trace.
+    ts: f64,
+    dur: f64,
+    pid: u32,
+    tid: u32,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Trace {
+    trace_events: Vec<TraceEvent>,
+}
+
+/// Collects CPU and GPU scope timings per frame and keeps the last `HISTORY_FRAMES` of them.
+///
+/// CPU scopes are timed with `Instant`. GPU scopes are measured by `GpuTimer` and attached to the
+/// frame that recorded them once their results are read back.
+pub struct Profiler {
+    frames: VecDeque<FrameTimings>,
+    frame_start: Option<Instant>,
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Profiler {
+            frames: VecDeque::with_capacity(HISTORY_FRAMES),
+            frame_start: None,
+        }
+    }
+}
+
+impl Profiler {
+    /// Starts the frame with `serial`. Starting the same serial again, because the previous
+    /// attempt was never submitted, drops what was measured for it so far.
+    pub fn begin_frame(&mut self, serial: u64) {
+        if self.frames.back().map(|frame| frame.serial) == Some(serial) {
+            self.frames.pop_back();
+        }
+        if self.frames.len() == HISTORY_FRAMES {
+            self.frames.pop_front();
+        }
+
+        let start = Instant::now();
+        self.frame_start = Some(start);
+        self.frames.push_back(FrameTimings {
+            serial,
+            start,
+            cpu: Vec::new(),
+            gpu: Vec::new(),
+        });
+    }
+
+    /// Ends the current frame, recording its total CPU time as the `FRAME_SCOPE` scope.
+    pub fn end_frame(&mut self) {
+        if let Some(start) = self.frame_start.take() {
+            self.end_cpu(CpuScope {
+                name: FRAME_SCOPE,
+                start,
+            });
+        }
+    }
+
+    pub fn begin_cpu(&self, name: &'static str) -> CpuScope {
+        CpuScope {
+            name,
+            start: Instant::now(),
+        }
+    }
+
+    pub fn end_cpu(&mut self, scope: CpuScope) {
+        let end = Instant::now();
+        if let Some(frame) = self.frames.back_mut() {
+            frame.cpu.push(ScopeTiming {
+                name: scope.name,
+                start: scope.start.max(frame.start) - frame.start,
+                duration: end - scope.start,
+            });
+        }
+    }
+
+    /// Attaches GPU scopes read back for the frame with `serial`. Dropped if the frame has
+    /// already left the history.
+    pub fn add_gpu(&mut self, serial: u64, scopes: Vec<ScopeTiming>) {
+        if let Some(frame) = self.frames.iter_mut().find(|frame| frame.serial == serial) {
+            frame.gpu = scopes;
+        }
+    }
+
+    /// The frames in the history, oldest first.
+    pub fn frames(&self) -> impl Iterator<Item = &FrameTimings> {
+        self.frames.iter()
+    }
+
+    /// Statistics of every scope in the history, CPU scopes first, each timeline in order of
+    /// first appearance.
+    pub fn stats(&self) -> Vec<ScopeStats> {
+        let mut samples: Vec<(&'static str, Timeline, Vec<Duration>)> = Vec::new();
+        for frame in &self.frames {
+            let scopes = frame
+                .cpu
+                .iter()
+                .map(|scope| (Timeline::Cpu, scope))
+                .chain(frame.gpu.iter().map(|scope| (Timeline::Gpu, scope)));
+            for (timeline, scope) in scopes {
+                match samples
+                    .iter()
+                    .position(|&(name, t, _)| t == timeline && name == scope.name)
+                {
+                    Some(index) => samples[index].2.push(scope.duration),
+                    None => samples.push((scope.name, timeline, vec![scope.duration])),
+                }
+            }
+        }
+
+        let mut stats: Vec<ScopeStats> = samples
+            .into_iter()
+            .map(|(name, timeline, durations)| scope_stats(name, timeline, durations))
+            .collect();
+        stats.sort_by_key(|s| s.timeline == Timeline::Gpu);
+        stats
+    }
+
+    /// Average of the `FRAME_SCOPE` scope on `timeline`, `None` before anything was measured.
+    pub fn average_frame_time(&self, timeline: Timeline) -> Option<Duration> {
+        self.stats()
+            .into_iter()
+            .find(|s| s.timeline == timeline && s.name == FRAME_SCOPE)
+            .map(|s| s.average)
+    }
+
+    /// Line list graphing the CPU and GPU frame times of the history, in a `size` pixel box with
+    /// its top left corner at `origin`. Lines mark 60 and 30 frames per second.
+    pub fn graph_vertices(&self, origin: [f32; 2], size: [f32; 2]) -> Vec<DebugVertex> {
+        let [x, y] = origin;
+        let [width, height] = size;
+        let to_y = |ms: f32| y + height * (1.0 - (ms / GRAPH_MAX_MS).min(1.0));
+        let step = width / (HISTORY_FRAMES - 1) as f32;
+
+        let mut vertices = Vec::new();
+        {
+            let mut line = |from: [f32; 2], to: [f32; 2], color| {
+                vertices.push(DebugVertex {
+                    position: from,
+                    color,
+                });
+                vertices.push(DebugVertex {
+                    position: to,
+                    color,
+                });
+            };
+
+            for &ms in &[16.7, GRAPH_MAX_MS] {
+                line([x, to_y(ms)], [x + width, to_y(ms)], GRID_COLOR);
+            }
+
+            let frame_ms = |scopes: &[ScopeTiming]| {
+                scopes
+                    .iter()
+                    .find(|scope| scope.name == FRAME_SCOPE)
+                    .map(|scope| duration_as_ms(scope.duration))
+            };
+            // The newest frame sits at the right edge.
+            let first = HISTORY_FRAMES - self.frames.len();
+            for &(timeline, color) in &[(Timeline::Cpu, CPU_COLOR), (Timeline::Gpu, GPU_COLOR)] {
+                let mut previous: Option<[f32; 2]> = None;
+                for (i, frame) in self.frames.iter().enumerate() {
+                    let scopes = match timeline {
+                        Timeline::Cpu => &frame.cpu,
+                        Timeline::Gpu => &frame.gpu,
+                    };
+                    let point =
+                        frame_ms(scopes).map(|ms| [x + (first + i) as f32 * step, to_y(ms)]);
+                    if let (Some(from), Some(to)) = (previous, point) {
+                        line(from, to, color);
+                    }
+                    previous = point;
+                }
+            }
+        }
+
+        vertices
+    }
+
+    /// Writes the history in the Chrome trace event format, for chrome://tracing and compatible
+    /// viewers. CPU and GPU scopes go to separate threads.
+    ///
+    /// GPU timestamps have no common clock with `Instant`, so every frame's GPU scopes are placed
+    /// relative to the start of the frame on the CPU: durations and offsets within a frame are
+    /// exact, the offset between the two timelines is not.
+    pub fn write_chrome_trace<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        let path = path.as_ref();
+        let file = File::create(path)
+            .map_err(|err| format!("can't create {}: {}", path.display(), err))?;
+        serde_json::to_writer(BufWriter::new(file), &self.chrome_trace())
+            .map_err(|err| format!("can't write {}: {}", path.display(), err))
+    }
+
+    fn chrome_trace(&self) -> Trace {
+        let origin = match self.frames.front() {
+            Some(frame) => frame.start,
+            None => Instant::now(),
+        };
+
+        let mut trace_events = Vec::new();
+        for frame in &self.frames {
+            let frame_start = frame.start - origin;
+            let scopes = frame
+                .cpu
+                .iter()
+                .map(|scope| (Timeline::Cpu, scope))
+                .chain(frame.gpu.iter().map(|scope| (Timeline::Gpu, scope)));
+            for (timeline, scope) in scopes {
+                let (cat, tid) = match timeline {
+                    Timeline::Cpu => ("cpu", 1),
+                    Timeline::Gpu => ("gpu", 2),
+                };
+                trace_events.push(TraceEvent {
+                    name: scope.name,
+                    cat,
+                    ph: "X",
+                    ts: duration_as_us(frame_start + scope.start),
+                    dur: duration_as_us(scope.duration),
+                    pid: 1,
+                    tid,
+                });
+            }
+        }
+
+        Trace { trace_events }
+    }
+}
+
+/// Statistics of the non-empty `durations` measured for a scope.
+fn scope_stats(name: &'static str, timeline: Timeline, mut durations: Vec<Duration>) -> ScopeStats {
+    durations.sort();
+    let total = durations
+        .iter()
+        .fold(Duration::from_secs(0), |total, &duration| total + duration);
+    ScopeStats {
+        name,
+        timeline,
+        average: total / durations.len() as u32,
+        min: durations[0],
+        max: durations[durations.len() - 1],
+        p95: percentile(&durations, 95),
+        samples: durations.len(),
+    }
+}
+
+/// The `percent` percentile of the sorted, non-empty `durations` by nearest rank: the smallest
+/// duration that at least `percent` percent of them don't exceed.
+fn percentile(durations: &[Duration], percent: usize) -> Duration {
+    let rank = (durations.len() * percent + 99) / 100;
+    durations[rank.max(1) - 1]
+}
+
+pub fn duration_as_ms(duration: Duration) -> f32 {
+    duration.as_secs() as f32 * 1000.0 + duration.subsec_nanos() as f32 / 1_000_000.0
+}
+
+fn duration_as_us(duration: Duration) -> f64 {
+    duration.as_secs() as f64 * 1_000_000.0 + f64::from(duration.subsec_nanos()) / 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    fn scope(name: &'static str, start: u64, duration: u64) -> ScopeTiming {
+        ScopeTiming {
+            name,
+            start: ms(start),
+            duration: ms(duration),
+        }
+    }
+
+    /// A profiler with frames 20ms apart, measuring `cpu` and `gpu` in each.
+    fn profiler(frames: Vec<(Vec<ScopeTiming>, Vec<ScopeTiming>)>) -> Profiler {
+        let origin = Instant::now();
+        let mut profiler = Profiler::default();
+        for (index, (cpu, gpu)) in frames.into_iter().enumerate() {
+            profiler.frames.push_back(FrameTimings {
+                serial: index as u64 + 1,
+                start: origin + ms(20 * index as u64),
+                cpu,
+                gpu,
+            });
+        }
+        profiler
+    }
+
+    #[test]
+    fn stats_per_scope_and_timeline() {
+        let profiler = profiler(vec![
+            (
+                vec![scope("update", 0, 2), scope(FRAME_SCOPE, 0, 10)],
+                vec![scope(FRAME_SCOPE, 0, 6)],
+            ),
+            (
+                vec![scope("update", 0, 4), scope(FRAME_SCOPE, 0, 14)],
+                vec![],
+            ),
+            (
+                vec![scope(FRAME_SCOPE, 0, 12)],
+                vec![scope(FRAME_SCOPE, 0, 8)],
+            ),
+        ]);
+
+        let stats = profiler.stats();
+        let summary: Vec<_> = stats
+            .iter()
+            .map(|s| (s.timeline, s.name, s.samples, s.min, s.average, s.max))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (Timeline::Cpu, "update", 2, ms(2), ms(3), ms(4)),
+                (Timeline::Cpu, FRAME_SCOPE, 3, ms(10), ms(12), ms(14)),
+                (Timeline::Gpu, FRAME_SCOPE, 2, ms(6), ms(7), ms(8)),
+            ]
+        );
+        assert_eq!(profiler.average_frame_time(Timeline::Gpu), Some(ms(7)));
+        assert_eq!(Profiler::default().average_frame_time(Timeline::Cpu), None);
+    }
+
+    #[test]
+    fn p95_ignores_single_hitch() {
+        // 19 frames of 10ms and one of 100ms.
+        let mut durations = vec![ms(10); 19];
+        durations.push(ms(100));
+        let stats = scope_stats(FRAME_SCOPE, Timeline::Cpu, durations);
+        assert_eq!(stats.p95, ms(10));
+        assert_eq!(stats.max, ms(100));
+        assert_eq!(stats.average, Duration::from_micros(14500));
+    }
+
+    #[test]
+    fn percentile_by_nearest_rank() {
+        let durations: Vec<_> = (1..=10).map(ms).collect();
+        assert_eq!(percentile(&durations, 95), ms(10));
+        assert_eq!(percentile(&durations, 90), ms(9));
+        assert_eq!(percentile(&durations, 50), ms(5));
+        assert_eq!(percentile(&durations, 0), ms(1));
+        assert_eq!(percentile(&[ms(3)], 95), ms(3));
+    }
+
+    #[test]
+    fn chrome_trace_shape() {
+        let profiler = profiler(vec![
+            (
+                vec![scope(FRAME_SCOPE, 0, 10), scope("record", 2, 3)],
+                vec![scope(FRAME_SCOPE, 1, 6)],
+            ),
+            (vec![scope(FRAME_SCOPE, 0, 12)], vec![]),
+        ]);
+
+        let trace = serde_json::to_value(&profiler.chrome_trace()).unwrap();
+        let events = trace["traceEvents"].as_array().unwrap();
+        assert_eq!(events.len(), 4);
+        for event in events {
+            let mut keys: Vec<_> = event.as_object().unwrap().keys().collect();
+            keys.sort();
+            assert_eq!(keys, ["cat", "dur", "name", "ph", "pid", "tid", "ts"]);
+            assert_eq!(event["ph"], "X");
+            assert_eq!(event["pid"], 1);
+        }
+
+        // Timestamps are in microseconds since the first frame, offset by the scope's start.
+        let record = &events[1];
+        assert_eq!(record["name"], "record");
+        assert_eq!(record["cat"], "cpu");
+        assert_eq!(record["tid"], 1);
+        assert_eq!(record["ts"], 2000.0);
+        assert_eq!(record["dur"], 3000.0);
+
+        let gpu = &events[2];
+        assert_eq!(gpu["name"], FRAME_SCOPE);
+        assert_eq!(gpu["cat"], "gpu");
+        assert_eq!(gpu["tid"], 2);
+        assert_eq!(gpu["ts"], 1000.0);
+
+        let second_frame = &events[3];
+        assert_eq!(second_frame["ts"], 20000.0);
+        assert_eq!(second_frame["dur"], 12000.0);
+    }
+}
@@ -0,0 +1,39 @@
+use super::{DeviceState, QueryPoolImpl};
+use hal::{query, Device};
+use std::mem::size_of;
+use std::ops::Range;
+use std::slice;
+
+/// Reads `values_per_query` 64 bit results of every query in `queries` without waiting for them.
+/// Returns `None` while any of them is unavailable.
+pub fn read_query_results(
+    device: &DeviceState,
+    pool: &QueryPoolImpl,
+    queries: Range<query::Id>,
+    values_per_query: usize,
+) -> Option<Vec<u64>> {
+    let count = (queries.end - queries.start) as usize * values_per_query;
+    let mut values = vec![0u64; count];
+    let available = {
+        // Results are written in the host's byte order, so they go straight into the `u64`s.
+        let data = unsafe {
+            slice::from_raw_parts_mut(values.as_mut_ptr() as *mut u8, count * size_of::<u64>())
+        };
+        device
+            .device
+            .get_query_pool_results(
+                pool,
+                queries,
+                data,
+                (values_per_query * size_of::<u64>()) as hal::buffer::Offset,
+                query::ResultFlags::BITS_64,
+            )
+            .unwrap_or(false)
+    };
+
+    if available {
+        Some(values)
+    } else {
+        None
+    }
+}
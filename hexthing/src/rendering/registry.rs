@@ -0,0 +1,330 @@
+use super::{BufferState, DescSet, PipelineState, TextureState};
+use std::collections::VecDeque;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+use std::ops::Index;
+
+/// Refers to a resource in a `ResourceRegistry`. Handles stay cheap to copy and are never reused:
+/// once the resource is destroyed, lookups with the old handle fail even if its slot has been
+/// filled again.
+pub struct Handle<T> {
+    index: u32,
+    generation: u32,
+    marker: PhantomData<fn() -> T>,
+}
+
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Handle<T> {}
+
+impl<T> PartialEq for Handle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.index == other.index && self.generation == other.generation
+    }
+}
+
+impl<T> Eq for Handle<T> {}
+
+impl<T> Hash for Handle<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.index.hash(state);
+        self.generation.hash(state);
+    }
+}
+
+impl<T> fmt::Debug for Handle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Handle({}v{})", self.index, self.generation)
+    }
+}
+
+struct Slot<T> {
+    generation: u32,
+    value: Option<T>,
+}
+
+/// Slots of one resource type, reused through a free list.
+pub struct Pool<T> {
+    slots: Vec<Slot<T>>,
+    free: Vec<u32>,
+}
+
+impl<T> Default for Pool<T> {
+    fn default() -> Self {
+        Pool {
+            slots: Vec::new(),
+            free: Vec::new(),
+        }
+    }
+}
+
+impl<T> Pool<T> {
+    fn insert(&mut self, value: T) -> Handle<T> {
+        let index = match self.free.pop() {
+            Some(index) => {
+                self.slots[index as usize].value = Some(value);
+                index
+            }
+            None => {
+                self.slots.push(Slot {
+                    generation: 0,
+                    value: Some(value),
+                });
+                self.slots.len() as u32 - 1
+            }
+        };
+        Handle {
+            index,
+            generation: self.slots[index as usize].generation,
+            marker: PhantomData,
+        }
+    }
+
+    fn get(&self, handle: Handle<T>) -> Option<&T> {
+        self.slots
+            .get(handle.index as usize)
+            .filter(|slot| slot.generation == handle.generation)
+            .and_then(|slot| slot.value.as_ref())
+    }
+
+    fn get_mut(&mut self, handle: Handle<T>) -> Option<&mut T> {
+        self.slots
+            .get_mut(handle.index as usize)
+            .filter(|slot| slot.generation == handle.generation)
+            .and_then(|slot| slot.value.as_mut())
+    }
+
+    fn remove(&mut self, handle: Handle<T>) -> Option<T> {
+        let value = {
+            let slot = self.slots.get_mut(handle.index as usize)?;
+            if slot.generation != handle.generation {
+                return None;
+            }
+            let value = slot.value.take()?;
+            slot.generation = slot.generation.wrapping_add(1);
+            value
+        };
+        self.free.push(handle.index);
+        Some(value)
+    }
+}
+
+/// A resource waiting for the GPU to finish with it.
+pub enum Retired {
+    Buffer(BufferState),
+    Texture(TextureState),
+    Pipeline(PipelineState),
+    DescSet(DescSet),
+}
+
+/// Values kept until the frame they were retired at has completed, in the order they were retired
+/// in. Serials only ever increase, so the oldest retirement is always at the front.
+struct RetireQueue<T> {
+    queue: VecDeque<(u64, T)>,
+}
+
+impl<T> Default for RetireQueue<T> {
+    fn default() -> Self {
+        RetireQueue {
+            queue: VecDeque::new(),
+        }
+    }
+}
+
+impl<T> RetireQueue<T> {
+    fn push(&mut self, serial: u64, value: T) {
+        self.queue.push_back((serial, value));
+    }
+
+    /// Drops every value retired at `completed_serial` or earlier.
+    fn collect(&mut self, completed_serial: u64) {
+        while self
+            .queue
+            .front()
+            .map_or(false, |&(serial, _)| serial <= completed_serial)
+        {
+            self.queue.pop_front();
+        }
+    }
+}
+
+/// Types that can be kept in a `ResourceRegistry`.
+pub trait Resource: Sized {
+    fn pool(registry: &ResourceRegistry) -> &Pool<Self>;
+    fn pool_mut(registry: &mut ResourceRegistry) -> &mut Pool<Self>;
+    fn retired(self) -> Retired;
+}
+
+macro_rules! resources {
+    ($($ty:ident => $pool:ident, $variant:ident;)*) => {
+        $(
+            impl Resource for $ty {
+                fn pool(registry: &ResourceRegistry) -> &Pool<Self> {
+                    &registry.$pool
+                }
+
+                fn pool_mut(registry: &mut ResourceRegistry) -> &mut Pool<Self> {
+                    &mut registry.$pool
+                }
+
+                fn retired(self) -> Retired {
+                    Retired::$variant(self)
+                }
+            }
+        )*
+    };
+}
+
+resources! {
+    BufferState => buffers, Buffer;
+    TextureState => textures, Texture;
+    PipelineState => pipelines, Pipeline;
+    DescSet => desc_sets, DescSet;
+}
+
+/// Owns GPU resources that are referred to by handle, and delays destroying them until the GPU is
+/// done with every frame that may use them.
+///
+/// Frames are identified by the serials handed out by `FrameContext`. A resource destroyed while
+/// recording frame `n` is kept alive until `collect` is called with a completed serial of at least
+/// `n`.
+#[derive(Default)]
+pub struct ResourceRegistry {
+    buffers: Pool<BufferState>,
+    textures: Pool<TextureState>,
+    pipelines: Pool<PipelineState>,
+    desc_sets: Pool<DescSet>,
+    retired: RetireQueue<Retired>,
+}
+
+impl ResourceRegistry {
+    pub fn insert<T: Resource>(&mut self, resource: T) -> Handle<T> {
+        T::pool_mut(self).insert(resource)
+    }
+
+    pub fn get<T: Resource>(&self, handle: Handle<T>) -> Option<&T> {
+        T::pool(self).get(handle)
+    }
+
+    pub fn get_mut<T: Resource>(&mut self, handle: Handle<T>) -> Option<&mut T> {
+        T::pool_mut(self).get_mut(handle)
+    }
+
+    /// Invalidates `handle` and destroys the resource once frame `serial` has completed. Does
+    /// nothing for handles that are already invalid.
+    pub fn destroy<T: Resource>(&mut self, handle: Handle<T>, serial: u64) {
+        if let Some(resource) = T::pool_mut(self).remove(handle) {
+            self.retire(resource, serial);
+        }
+    }
+
+    /// Destroys a resource that isn't in the registry once frame `serial` has completed.
+    pub fn retire<T: Resource>(&mut self, resource: T, serial: u64) {
+        self.retired.push(serial, resource.retired());
+    }
+
+    /// Destroys retired resources whose frames are no longer in flight.
+    pub fn collect(&mut self, completed_serial: u64) {
+        self.retired.collect(completed_serial);
+    }
+}
+
+impl<T: Resource> Index<Handle<T>> for ResourceRegistry {
+    type Output = T;
+
+    fn index(&self, handle: Handle<T>) -> &T {
+        self.get(handle).expect("Stale resource handle")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    #[test]
+    fn handle_goes_stale_after_remove() {
+        let mut pool = Pool::default();
+        let handle = pool.insert(7);
+        assert_eq!(pool.get(handle), Some(&7));
+
+        *pool.get_mut(handle).unwrap() = 8;
+        assert_eq!(pool.remove(handle), Some(8));
+        assert_eq!(pool.get(handle), None);
+        assert_eq!(pool.get_mut(handle), None);
+        // Removing twice does nothing.
+        assert_eq!(pool.remove(handle), None);
+    }
+
+    #[test]
+    fn reused_slot_gets_new_generation() {
+        let mut pool = Pool::default();
+        let old = pool.insert("old");
+        pool.remove(old);
+
+        let new = pool.insert("new");
+        assert_eq!(new.index, old.index);
+        assert_ne!(new.generation, old.generation);
+        assert_ne!(new, old);
+        assert_eq!(pool.get(new), Some(&"new"));
+        assert_eq!(pool.get(old), None);
+        // The stale handle can't remove what now lives in its slot.
+        assert_eq!(pool.remove(old), None);
+        assert_eq!(pool.get(new), Some(&"new"));
+    }
+
+    #[test]
+    fn slots_are_reused_before_growing() {
+        let mut pool = Pool::default();
+        let a = pool.insert(1);
+        let b = pool.insert(2);
+        pool.remove(a);
+        let c = pool.insert(3);
+        assert_eq!(c.index, a.index);
+        assert_ne!(c.index, b.index);
+        assert_eq!(pool.slots.len(), 2);
+    }
+
+    /// Counts drops in a counter shared between instances.
+    struct DropCounter(Rc<Cell<u32>>);
+
+    impl Drop for DropCounter {
+        fn drop(&mut self) {
+            self.0.set(self.0.get() + 1);
+        }
+    }
+
+    #[test]
+    fn collect_frees_only_completed_retirements() {
+        let dropped = Rc::new(Cell::new(0));
+        let mut queue = RetireQueue::default();
+        queue.push(1, DropCounter(Rc::clone(&dropped)));
+        queue.push(2, DropCounter(Rc::clone(&dropped)));
+        queue.push(2, DropCounter(Rc::clone(&dropped)));
+        queue.push(4, DropCounter(Rc::clone(&dropped)));
+
+        queue.collect(0);
+        assert_eq!(dropped.get(), 0);
+
+        queue.collect(1);
+        assert_eq!(dropped.get(), 1);
+
+        // Everything retired at a serial is freed together, later serials stay.
+        queue.collect(3);
+        assert_eq!(dropped.get(), 3);
+
+        // Collecting again with an older serial frees nothing more.
+        queue.collect(2);
+        assert_eq!(dropped.get(), 3);
+
+        queue.collect(4);
+        assert_eq!(dropped.get(), 4);
+        assert!(queue.queue.is_empty());
+    }
+}
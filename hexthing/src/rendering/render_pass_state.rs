@@ -1,61 +1,196 @@
 use super::{DeviceState, RenderPassImpl, SwapchainState};
-use hal::{image, pass, pso, Device};
-use std::cell::RefCell;
-use std::rc::Rc;
+use hal::{format, image, pass, pso, Device};
+use std::sync::Arc;
 
 pub struct RenderPassState {
     pub render_pass: Option<RenderPassImpl>,
-    device: Rc<RefCell<DeviceState>>,
+    pub color_format: format::Format,
+    pub depth_format: Option<format::Format>,
+    pub samples: image::NumSamples,
+    device: Arc<DeviceState>,
 }
 
 impl RenderPassState {
-    pub fn new(swapchain: &SwapchainState, device: Rc<RefCell<DeviceState>>) -> Self {
+    pub fn new(
+        swapchain: &SwapchainState,
+        depth_format: Option<format::Format>,
+        samples: image::NumSamples,
+        device: Arc<DeviceState>,
+    ) -> Self {
         let render_pass = {
-            let attachment = pass::Attachment {
+            let mut attachments = Vec::new();
+
+            // When multisampling, rendering goes to a transient multisampled target that is
+            // resolved into the swapchain image at the end of the subpass.
+            if samples > 1 {
+                attachments.push(pass::Attachment {
+                    format: Some(swapchain.format),
+                    samples,
+                    ops: pass::AttachmentOps::new(
+                        pass::AttachmentLoadOp::Clear,
+                        pass::AttachmentStoreOp::DontCare,
+                    ),
+                    stencil_ops: pass::AttachmentOps::DONT_CARE,
+                    layouts: image::Layout::Undefined..image::Layout::ColorAttachmentOptimal,
+                });
+            }
+
+            attachments.push(pass::Attachment {
                 format: Some(swapchain.format),
                 samples: 1,
                 ops: pass::AttachmentOps::new(
-                    pass::AttachmentLoadOp::Clear,
+                    if samples > 1 {
+                        pass::AttachmentLoadOp::DontCare
+                    } else {
+                        pass::AttachmentLoadOp::Clear
+                    },
                     pass::AttachmentStoreOp::Store,
                 ),
                 stencil_ops: pass::AttachmentOps::DONT_CARE,
                 layouts: image::Layout::Undefined..image::Layout::Present,
-            };
+            });
+
+            if let Some(depth_format) = depth_format {
+                attachments.push(pass::Attachment {
+                    format: Some(depth_format),
+                    samples,
+                    ops: pass::AttachmentOps::new(
+                        pass::AttachmentLoadOp::Clear,
+                        pass::AttachmentStoreOp::DontCare,
+                    ),
+                    stencil_ops: pass::AttachmentOps::new(
+                        pass::AttachmentLoadOp::Clear,
+                        pass::AttachmentStoreOp::DontCare,
+                    ),
+                    layouts: image::Layout::Undefined..image::Layout::DepthStencilAttachmentOptimal,
+                });
+            }
 
+            let (colors, resolves): (&[pass::AttachmentRef], &[pass::AttachmentRef]) =
+                if samples > 1 {
+                    (
+                        &[(0, image::Layout::ColorAttachmentOptimal)],
+                        &[(1, image::Layout::ColorAttachmentOptimal)],
+                    )
+                } else {
+                    (&[(0, image::Layout::ColorAttachmentOptimal)], &[])
+                };
+            let depth_ref = (
+                attachments.len() - 1,
+                image::Layout::DepthStencilAttachmentOptimal,
+            );
             let subpass = pass::SubpassDesc {
-                colors: &[(0, image::Layout::ColorAttachmentOptimal)],
-                depth_stencil: None,
+                colors,
+                depth_stencil: depth_format.map(|_| &depth_ref),
                 inputs: &[],
-                resolves: &[],
+                resolves,
                 preserves: &[],
             };
 
             let dependency = pass::SubpassDependency {
                 passes: pass::SubpassRef::External..pass::SubpassRef::Pass(0),
-                stages: pso::PipelineStage::COLOR_ATTACHMENT_OUTPUT
-                    ..pso::PipelineStage::COLOR_ATTACHMENT_OUTPUT,
+                stages: (pso::PipelineStage::COLOR_ATTACHMENT_OUTPUT
+                    | pso::PipelineStage::EARLY_FRAGMENT_TESTS)
+                    ..(pso::PipelineStage::COLOR_ATTACHMENT_OUTPUT
+                        | pso::PipelineStage::EARLY_FRAGMENT_TESTS),
                 accesses: image::Access::empty()
                     ..(image::Access::COLOR_ATTACHMENT_READ
-                        | image::Access::COLOR_ATTACHMENT_WRITE),
+                        | image::Access::COLOR_ATTACHMENT_WRITE
+                        | image::Access::DEPTH_STENCIL_ATTACHMENT_READ
+                        | image::Access::DEPTH_STENCIL_ATTACHMENT_WRITE),
             };
 
             device
-                .borrow()
                 .device
-                .create_render_pass(&[attachment], &[subpass], &[dependency])
+                .create_render_pass(&attachments, &[subpass], &[dependency])
                 .ok()
         };
 
         RenderPassState {
             render_pass,
+            color_format: swapchain.format,
+            depth_format,
+            samples,
             device,
         }
     }
+
+    /// A single sampled pass rendering into an offscreen `color_format` target, which is left in
+    /// `TransferSrcOptimal` so it can be copied from once the pass ends.
+    pub fn offscreen(
+        color_format: format::Format,
+        depth_format: Option<format::Format>,
+        device: Arc<DeviceState>,
+    ) -> Self {
+        let render_pass = {
+            let mut attachments = vec![pass::Attachment {
+                format: Some(color_format),
+                samples: 1,
+                ops: pass::AttachmentOps::new(
+                    pass::AttachmentLoadOp::Clear,
+                    pass::AttachmentStoreOp::Store,
+                ),
+                stencil_ops: pass::AttachmentOps::DONT_CARE,
+                layouts: image::Layout::Undefined..image::Layout::TransferSrcOptimal,
+            }];
+            if let Some(depth_format) = depth_format {
+                attachments.push(pass::Attachment {
+                    format: Some(depth_format),
+                    samples: 1,
+                    ops: pass::AttachmentOps::new(
+                        pass::AttachmentLoadOp::Clear,
+                        pass::AttachmentStoreOp::DontCare,
+                    ),
+                    stencil_ops: pass::AttachmentOps::DONT_CARE,
+                    layouts: image::Layout::Undefined..image::Layout::DepthStencilAttachmentOptimal,
+                });
+            }
+
+            let depth_ref = (1, image::Layout::DepthStencilAttachmentOptimal);
+            let subpass = pass::SubpassDesc {
+                colors: &[(0, image::Layout::ColorAttachmentOptimal)],
+                depth_stencil: depth_format.map(|_| &depth_ref),
+                inputs: &[],
+                resolves: &[],
+                preserves: &[],
+            };
+
+            // The target is copied from right after the pass.
+            let dependency = pass::SubpassDependency {
+                passes: pass::SubpassRef::Pass(0)..pass::SubpassRef::External,
+                stages: pso::PipelineStage::COLOR_ATTACHMENT_OUTPUT..pso::PipelineStage::TRANSFER,
+                accesses: image::Access::COLOR_ATTACHMENT_WRITE..image::Access::TRANSFER_READ,
+            };
+
+            device
+                .device
+                .create_render_pass(&attachments, &[subpass], &[dependency])
+                .ok()
+        };
+
+        RenderPassState {
+            render_pass,
+            color_format,
+            depth_format,
+            samples: 1,
+            device,
+        }
+    }
+
+    /// Number of attachments preceding the depth attachment, i.e. the number of color clear
+    /// values a framebuffer of this render pass expects.
+    pub fn color_attachment_count(&self) -> usize {
+        if self.samples > 1 {
+            2
+        } else {
+            1
+        }
+    }
 }
 
 impl Drop for RenderPassState {
     fn drop(&mut self) {
-        let device = &self.device.borrow().device;
+        let device = &self.device.device;
         device.destroy_render_pass(self.render_pass.take().unwrap());
     }
 }
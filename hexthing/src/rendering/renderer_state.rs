@@ -1,139 +1,455 @@
+use super::atlas::Atlas;
+use super::debug_draw::{self, DebugDraw, DebugVertex};
+use super::depth::{self, DepthMode, Layer};
+use super::fog;
+use super::grid::{self, GridStyle};
+use super::profiler::duration_as_ms;
 use super::{
-    BackendState, BufferState, DescSetLayout, DescriptorPoolImpl, DeviceState, FramebufferState,
-    PipelineState, RenderPassState, SwapchainState, Uniform,
+    AdapterRequirements, BackendState, BufferState, Camera, CountingEncoder, DescSetLayout,
+    DescriptorSetLayoutImpl, DeviceState, DrawStats, FrameContext, FramebufferState, GpuTimer,
+    Handle, IdPickingState, MapChunkRecorder, MapDraw, PipelineConfig, PipelineState,
+    PipelineStatistics, Profiler, RenderPassState, ResourceRegistry, StatisticsQueries,
+    SwapchainState, TextState, TextureState, Timeline, Uniform, FRAME_SCOPE,
 };
-use definitions::Vertex;
+use app::{App, Context, Control, FixedTimestep};
+use config::{Config, PresentMode};
+use definitions::{HexInstance, PickInstance, Vertex};
 use fnv::FnvHashMap;
-use hal::{self, buffer, command, pso, queue, window, Device, Swapchain};
-use std::cell::RefCell;
-use std::rc::Rc;
+use hal::{self, buffer, command, image, pass, pso, window, Device, Swapchain};
+use nalgebra::Vector2;
+use picking::HexPicker;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
 
 pub struct RendererState {
-    uniform_desc_pool: Option<DescriptorPoolImpl>,
     swapchain: Option<SwapchainState>,
-    device: Rc<RefCell<DeviceState>>,
+    device: Arc<DeviceState>,
     backend: BackendState,
     vertex_buffer: BufferState,
+    instance_buffer: Option<Handle<BufferState>>,
+    instance_count: u32,
     render_pass: RenderPassState,
     uniform: Uniform,
-    pipelines: FnvHashMap<String, PipelineState>,
-    framebuffer: FramebufferState,
+    atlas_texture: Handle<TextureState>,
+    pipelines: FnvHashMap<String, Handle<PipelineState>>,
+    /// Owns the resources above that are referred to by handle.
+    resources: ResourceRegistry,
+    /// Always `Some` outside of `recreate_swapchain`, which has to release the swapchain image
+    /// views before handing the old swapchain over.
+    framebuffer: Option<FramebufferState>,
+    frames: FrameContext,
+    map_chunks: MapChunkRecorder,
+    gpu_timer: GpuTimer,
+    profiler: Profiler,
+    /// Whether recent frame times are graphed in the top right corner.
+    profiler_overlay: bool,
+    /// `None` unless enabled in the configuration and supported by the adapter.
+    statistics_queries: Option<StatisticsQueries>,
+    /// Counted while recording the most recent frame.
+    draw_stats: DrawStats,
+    /// Read back for an earlier frame, see `pipeline_statistics`.
+    pipeline_statistics: Option<PipelineStatistics>,
+    last_stats_log: Instant,
     viewport: pso::Viewport,
+    clear_color: [f32; 4],
+    present_modes: Vec<PresentMode>,
+    /// Size of the window in physical pixels, as of the last resize.
+    window_extent: window::Extent2D,
+    grid_style: Option<GridStyle>,
+    text: Option<TextState>,
+    /// Cleared once the frame it was drawn for has been uploaded.
+    debug_draw: DebugDraw,
+    camera: Camera,
+    hover_style: Option<GridStyle>,
+    selection_style: Option<GridStyle>,
+    id_picking: Option<IdPickingState>,
+    pick_buffer: Option<Handle<BufferState>>,
+    pick_count: u32,
+}
+
+/// How often the frame counters are logged.
+const STATS_LOG_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Color of the fog of war drawn over everything that isn't visible.
+const FOG_COLOR: [f32; 4] = [0.05, 0.05, 0.08, 0.7];
+
+/// Size of the profiler overlay graph in pixels.
+const PROFILER_GRAPH_SIZE: [f32; 2] = [240.0, 80.0];
+
+/// Vertex stage constants of the debug pipeline drawing in pixels, with the origin at the top left
+/// corner of the viewport.
+fn screen_push_constants(viewport: &pso::Viewport) -> [u32; 16] {
+    let (width, height) = (f32::from(viewport.rect.w), f32::from(viewport.rect.h));
+    #[rustfmt::skip]
+    let matrix = [
+        2.0 / width, 0.0, 0.0, 0.0,
+        0.0, 2.0 / height, 0.0, 0.0,
+        0.0, 0.0, 1.0, 0.0,
+        -1.0, -1.0, 0.0, 1.0,
+    ];
+    let mut constants = [0u32; 16];
+    for (constant, value) in constants.iter_mut().zip(matrix.iter()) {
+        *constant = value.to_bits();
+    }
+    constants
+}
+
+/// Vertex stage constants of the hex pipelines: the camera's view projection matrix followed by
+/// the layer depth.
+fn world_push_constants(camera: &Camera, layer: Layer) -> [u32; 17] {
+    let mut constants = [0u32; 17];
+    constants[..16].copy_from_slice(&camera.push_constants());
+    constants[16] = layer.depth().to_bits();
+    constants
 }
 
 impl RendererState {
-    pub fn new(dims: window::Extent2D, quad: &[Vertex]) -> Self {
-        let mut backend = BackendState::new(dims);
-        let device = Rc::new(RefCell::new(DeviceState::new(
+    pub fn new(
+        config: &Config,
+        quad: &[Vertex],
+        atlas: &Atlas,
+        depth_mode: DepthMode,
+    ) -> Result<Self, String> {
+        let requirements = AdapterRequirements {
+            min_texture_size: atlas.width.max(atlas.height) as usize,
+            ..AdapterRequirements::default()
+        };
+        let mut backend = BackendState::new(
+            &config.window,
+            &requirements,
+            config.renderer.adapter.as_ref(),
+        )?;
+        let device = Arc::new(DeviceState::new(
             backend.adapter.adapter.take().unwrap(),
             &backend.surface,
-        )));
+        ));
 
         let uniform_desc = DescSetLayout::new(
-            Rc::clone(&device),
-            vec![pso::DescriptorSetLayoutBinding {
-                binding: 0,
-                ty: pso::DescriptorType::UniformBuffer,
-                count: 1,
-                stage_flags: pso::ShaderStageFlags::FRAGMENT,
-                immutable_samplers: false,
-            }],
-        );
-
-        let mut uniform_desc_pool = device
-            .borrow()
-            .device
-            .create_descriptor_pool(
-                1, // # of sets
-                &[pso::DescriptorRangeDesc {
+            Arc::clone(&device),
+            vec![
+                pso::DescriptorSetLayoutBinding {
+                    binding: 0,
                     ty: pso::DescriptorType::UniformBuffer,
                     count: 1,
-                }],
-            )
-            .ok();
+                    stage_flags: pso::ShaderStageFlags::FRAGMENT,
+                    immutable_samplers: false,
+                },
+                TextureState::layout_binding(1),
+            ],
+        );
 
-        let uniform_desc = uniform_desc.create_desc_set(uniform_desc_pool.as_mut().unwrap());
+        let uniform_desc = uniform_desc.create_desc_set();
 
         println!("Memory types: {:?}", backend.adapter.memory_types);
 
-        let vertex_buffer = BufferState::new::<Vertex>(
-            Rc::clone(&device),
+        // Uploads made during initialization are submitted with the first frame.
+        let mut frames = FrameContext::new(
+            &device,
+            &backend.adapter.memory_types,
+            config.renderer.frames_in_flight,
+        );
+        let mut resources = ResourceRegistry::default();
+
+        let (vertex_buffer, upload) = BufferState::device_local::<Vertex>(
+            Arc::clone(&device),
+            frames.upload_pools(),
             &quad,
             buffer::Usage::VERTEX,
             &backend.adapter.memory_types,
         );
+        frames.add_upload(upload, &mut resources);
 
-        let uniform = Uniform::new(
+        let mut uniform = Uniform::new(
             &device,
             &backend.adapter.memory_types,
-            &[1.0f32, 0.0f32, 0.0f32, 1.0f32],
+            &[1.0f32, 1.0f32, 1.0f32, 1.0f32],
             uniform_desc,
             0,
         );
 
-        let mut swapchain = Some(SwapchainState::new(&mut backend, Rc::clone(&device)));
+        let (atlas_texture, upload) = TextureState::new(
+            Arc::clone(&device),
+            frames.upload_pools(),
+            &backend.adapter.memory_types,
+            &backend.adapter.limits,
+            atlas.width,
+            atlas.height,
+            &atlas.pixels,
+            atlas.mip_levels,
+            image::Filter::Linear,
+        );
+        frames.add_upload(upload, &mut resources);
+        uniform
+            .desc
+            .as_mut()
+            .unwrap()
+            .write_to_state(vec![atlas_texture.desc_write(1)], &device.device);
 
-        let render_pass = RenderPassState::new(swapchain.as_ref().unwrap(), Rc::clone(&device));
+        let present_modes = config.renderer.present_modes.clone();
+        let window_extent = backend.window_extent();
+        let mut swapchain = Some(SwapchainState::new(
+            &mut backend,
+            Arc::clone(&device),
+            &present_modes,
+            window_extent,
+            None,
+        ));
+
+        let depth_format = device.find_depth_format(depth_mode);
+        if depth_mode != DepthMode::None && depth_format.is_none() {
+            println!(
+                "No supported format for {:?}, rendering without depth",
+                depth_mode
+            );
+        }
+
+        let samples = backend
+            .adapter
+            .supported_samples(config.renderer.msaa_samples);
+        if samples > 1 {
+            println!("MSAA: {}x", samples);
+        }
+
+        let render_pass = RenderPassState::new(
+            swapchain.as_ref().unwrap(),
+            depth_format,
+            samples,
+            Arc::clone(&device),
+        );
 
-        let framebuffer = FramebufferState::new(
-            Rc::clone(&device),
+        let framebuffer = Some(FramebufferState::new(
+            Arc::clone(&device),
             &render_pass,
             swapchain.as_mut().unwrap(),
-        );
+            &backend.adapter.memory_types,
+        ));
 
-        let pipeline = PipelineState::new(
-            vec![uniform.get_layout()],
-            render_pass.render_pass.as_ref().unwrap(),
-            &device,
-        );
+        let atlas_texture = resources.insert(atlas_texture);
+        let pipelines =
+            RendererState::create_pipelines(&uniform, None, &render_pass, &device, &mut resources);
 
         let viewport = RendererState::create_viewport(swapchain.as_ref().unwrap());
-        let mut pipelines = FnvHashMap::default();
-        pipelines.insert("main".to_owned(), pipeline);
 
-        RendererState {
+        let mut camera = Camera::new(Vector2::new(0.0, 0.0), 4.0);
+        camera.set_aspect(f32::from(viewport.rect.w) / f32::from(viewport.rect.h));
+
+        let map_chunks = MapChunkRecorder::new(&device, config.renderer.frames_in_flight);
+        let gpu_timer = GpuTimer::new(
+            &device,
+            config.renderer.frames_in_flight,
+            &backend.adapter.limits,
+        );
+        let statistics_queries = if config.renderer.pipeline_statistics {
+            let queries = StatisticsQueries::new(&device, config.renderer.frames_in_flight);
+            if queries.is_none() {
+                warn!("Pipeline statistics queries aren't supported by the adapter");
+            }
+            queries
+        } else {
+            None
+        };
+
+        Ok(RendererState {
             backend,
             device,
-            uniform_desc_pool,
             vertex_buffer,
+            instance_buffer: None,
+            instance_count: 0,
             uniform,
+            atlas_texture,
             render_pass,
             pipelines,
+            resources,
             swapchain,
             framebuffer,
+            frames,
+            map_chunks,
+            gpu_timer,
+            profiler: Profiler::default(),
+            profiler_overlay: config.renderer.profiler_overlay,
+            statistics_queries,
+            draw_stats: DrawStats::default(),
+            pipeline_statistics: None,
+            last_stats_log: Instant::now(),
             viewport,
-        }
+            clear_color: config.renderer.clear_color,
+            present_modes,
+            window_extent,
+            grid_style: None,
+            text: None,
+            debug_draw: DebugDraw::default(),
+            camera,
+            hover_style: Some(GridStyle::hover()),
+            selection_style: Some(GridStyle::selection()),
+            id_picking: None,
+            pick_buffer: None,
+            pick_count: 0,
+        })
     }
 
     fn recreate_swapchain(&mut self) {
-        self.device.borrow().device.wait_idle().unwrap();
+        // There's nothing to create while minimized, a resize follows once the window is restored.
+        if self.window_extent.width == 0 || self.window_extent.height == 0 {
+            return;
+        }
 
-        self.swapchain.take().unwrap();
+        self.device.device.wait_idle().unwrap();
 
+        // Framebuffers reference the old swapchain's images, which `create_swapchain` destroys
+        // along with the old swapchain.
+        self.framebuffer.take();
+        let old_swapchain = self.swapchain.take();
         self.swapchain = Some(SwapchainState::new(
             &mut self.backend,
-            Rc::clone(&self.device),
+            Arc::clone(&self.device),
+            &self.present_modes,
+            self.window_extent,
+            old_swapchain,
         ));
 
-        self.render_pass =
-            RenderPassState::new(self.swapchain.as_ref().unwrap(), Rc::clone(&self.device));
+        // The render pass, and the pipelines built against it, only depend on the surface format.
+        // Viewport and scissor are dynamic state, so pipelines survive size changes.
+        let format = self.swapchain.as_ref().unwrap().format;
+        if format != self.render_pass.color_format {
+            self.render_pass = RenderPassState::new(
+                self.swapchain.as_ref().unwrap(),
+                self.render_pass.depth_format,
+                self.render_pass.samples,
+                Arc::clone(&self.device),
+            );
+            for &pipeline in self.pipelines.values() {
+                self.resources
+                    .get_mut(pipeline)
+                    .unwrap()
+                    .rebuild(&self.render_pass);
+            }
+        }
 
-        self.framebuffer = FramebufferState::new(
-            Rc::clone(&self.device),
+        self.framebuffer = Some(FramebufferState::new(
+            Arc::clone(&self.device),
             &self.render_pass,
             self.swapchain.as_mut().unwrap(),
-        );
+            &self.backend.adapter.memory_types,
+        ));
 
-        let pipeline = PipelineState::new(
-            vec![self.uniform.get_layout()],
-            self.render_pass.render_pass.as_ref().unwrap(),
+        self.viewport = RendererState::create_viewport(self.swapchain.as_ref().unwrap());
+        self.camera
+            .set_aspect(f32::from(self.viewport.rect.w) / f32::from(self.viewport.rect.h));
+
+        let extent = self.surface_extent();
+        if let Some(ref mut id_picking) = self.id_picking {
+            id_picking.resize(extent, &self.backend.adapter.memory_types);
+        }
+    }
+
+    fn surface_extent(&self) -> image::Extent {
+        image::Extent {
+            width: self.viewport.rect.w as u32,
+            height: self.viewport.rect.h as u32,
+            depth: 1,
+        }
+    }
+
+    fn create_id_picking(&self) -> IdPickingState {
+        IdPickingState::new(
             &self.device,
+            &self.backend.adapter.memory_types,
+            self.surface_extent(),
+            self.render_pass.depth_format,
+        )
+    }
+
+    fn create_pipelines(
+        uniform: &Uniform,
+        text: Option<&TextState>,
+        render_pass: &RenderPassState,
+        device: &Arc<DeviceState>,
+        resources: &mut ResourceRegistry,
+    ) -> FnvHashMap<String, Handle<PipelineState>> {
+        let with_depth = render_pass.depth_format.is_some();
+        let with_stencil = render_pass.depth_format.map_or(false, depth::has_stencil);
+
+        // Every tile drawn is visible for now, so the terrain marks all of them and fog only
+        // covers what lies outside the map.
+        let mut main_config = PipelineConfig::hex("src/shaders/hex.vert", "src/shaders/hex.frag");
+        if with_stencil {
+            main_config.depth_stencil = depth::stencil_write(depth::VISIBLE);
+        } else if with_depth {
+            main_config.depth_stencil = depth::depth_test();
+        }
+
+        let mut pipelines = FnvHashMap::default();
+        pipelines.insert(
+            "main".to_owned(),
+            PipelineState::new(
+                vec![uniform.get_layout()],
+                render_pass,
+                &main_config,
+                device,
+            ),
+        );
+        pipelines.insert(
+            "grid".to_owned(),
+            PipelineState::new(
+                vec![uniform.get_layout()],
+                render_pass,
+                &grid::pipeline_config(with_depth),
+                device,
+            ),
         );
+        pipelines.insert(
+            "debug".to_owned(),
+            PipelineState::new(
+                Vec::<&DescriptorSetLayoutImpl>::new(),
+                render_pass,
+                &debug_draw::pipeline_config(),
+                device,
+            ),
+        );
+        if with_stencil {
+            pipelines.insert(
+                "fog".to_owned(),
+                PipelineState::new(
+                    Vec::<&DescriptorSetLayoutImpl>::new(),
+                    render_pass,
+                    &fog::pipeline_config(),
+                    device,
+                ),
+            );
+        }
+        if let Some(text) = text {
+            pipelines.insert(
+                "text".to_owned(),
+                PipelineState::new(
+                    vec![text.get_layout()],
+                    render_pass,
+                    &TextState::pipeline_config(with_depth),
+                    device,
+                ),
+            );
+        }
+        // Written now as well as on exit, so a crash doesn't lose what was just compiled.
+        device.pipeline_cache.save(&device.device);
 
-        self.pipelines.clear();
-        self.pipelines.insert("main".to_owned(), pipeline);
+        pipelines
+            .into_iter()
+            .map(|(name, pipeline)| (name, resources.insert(pipeline)))
+            .collect()
+    }
 
-        self.viewport = RendererState::create_viewport(self.swapchain.as_ref().unwrap());
+    fn clear_values(&self) -> Vec<command::ClearValue> {
+        let mut clear_values =
+            vec![
+                command::ClearValue::Color(command::ClearColor::Float(self.clear_color));
+                self.render_pass.color_attachment_count()
+            ];
+        if self.render_pass.depth_format.is_some() {
+            clear_values.push(command::ClearValue::DepthStencil(
+                command::ClearDepthStencil(1.0, 0),
+            ));
+        }
+        clear_values
     }
 
     fn create_viewport(swapchain: &SwapchainState) -> pso::Viewport {
@@ -148,36 +464,330 @@ impl RendererState {
         }
     }
 
-    pub fn mainloop(&mut self) {
+    /// Size of the surface in physical pixels.
+    fn surface_size(&self) -> Vector2<f32> {
+        Vector2::new(
+            f32::from(self.viewport.rect.w),
+            f32::from(self.viewport.rect.h),
+        )
+    }
+
+    /// The hexes to outline this frame and the style of each outline. The selection is drawn
+    /// first so hovering over the selected hex leaves it highlighted as selected.
+    fn highlights(&self, picking: &HexPicker) -> Vec<(HexInstance, GridStyle)> {
+        let mut highlights = Vec::new();
+        if let (Some(selected), Some(style)) = (picking.selected, self.selection_style) {
+            highlights.push((selected, style));
+        }
+        if let (Some(hovered), Some(style)) = (picking.hovered, self.hover_style) {
+            if picking.selected != Some(hovered) {
+                highlights.push((hovered, style));
+            }
+        }
+        highlights
+            .into_iter()
+            .map(|(coord, style)| {
+                let instance = HexInstance {
+                    position: coord.to_world(),
+                    uv_rect: [0.0; 4],
+                };
+                (instance, style)
+            })
+            .collect()
+    }
+
+    pub fn camera(&self) -> &Camera {
+        &self.camera
+    }
+
+    /// The camera's aspect ratio is kept in sync with the surface, everything else is up to the
+    /// caller.
+    pub fn camera_mut(&mut self) -> &mut Camera {
+        &mut self.camera
+    }
+
+    /// Primitives drawn on top of the next frame, in world space. Whatever `App::render` adds
+    /// here is drawn once.
+    pub fn debug_draw_mut(&mut self) -> &mut DebugDraw {
+        &mut self.debug_draw
+    }
+
+    pub fn set_hex_instances(&mut self, instances: &[HexInstance]) {
+        // The previous buffer may still be read by frames in flight.
+        if let Some(instance_buffer) = self.instance_buffer.take() {
+            self.resources
+                .destroy(instance_buffer, self.frames.serial());
+        }
+        self.instance_count = instances.len() as u32;
+        if !instances.is_empty() {
+            let (instance_buffer, upload) = BufferState::device_local(
+                Arc::clone(&self.device),
+                self.frames.upload_pools(),
+                instances,
+                buffer::Usage::VERTEX,
+                &self.backend.adapter.memory_types,
+            );
+            self.frames.add_upload(upload, &mut self.resources);
+            self.instance_buffer = Some(self.resources.insert(instance_buffer));
+        }
+    }
+
+    /// Sets what the ID buffer picking pass draws. The pass only runs while there are instances,
+    /// an empty slice disables it again.
+    pub fn set_pick_instances(&mut self, instances: &[PickInstance]) {
+        // The previous buffer may still be read by picks in flight, which are submitted as part
+        // of a frame.
+        if let Some(pick_buffer) = self.pick_buffer.take() {
+            self.resources.destroy(pick_buffer, self.frames.serial());
+        }
+        self.pick_count = instances.len() as u32;
+        if instances.is_empty() {
+            self.id_picking = None;
+            return;
+        }
+
+        let (pick_buffer, upload) = BufferState::device_local(
+            Arc::clone(&self.device),
+            self.frames.upload_pools(),
+            instances,
+            buffer::Usage::VERTEX,
+            &self.backend.adapter.memory_types,
+        );
+        self.frames.add_upload(upload, &mut self.resources);
+        self.pick_buffer = Some(self.resources.insert(pick_buffer));
+        if self.id_picking.is_none() {
+            self.id_picking = Some(self.create_id_picking());
+        }
+    }
+
+    /// Enables the hex outline overlay with the given style, or disables it when `None`.
+    pub fn set_grid_style(&mut self, style: Option<GridStyle>) {
+        self.grid_style = style;
+    }
+
+    /// Sets the outlines drawn around the hovered and the selected hex, `None` disables either.
+    pub fn set_highlight_styles(&mut self, hover: Option<GridStyle>, selection: Option<GridStyle>) {
+        self.hover_style = hover;
+        self.selection_style = selection;
+    }
+
+    /// Rasterizes a TrueType/OpenType font at `pixel_height` and enables `draw_text`.
+    pub fn load_font(&mut self, font_data: Vec<u8>, pixel_height: f32) -> Result<(), String> {
+        // The glyph atlas of a previous font may still be sampled by frames in flight.
+        if self.text.is_some() {
+            self.device.device.wait_idle().unwrap();
+        }
+
+        let (text, upload) = TextState::new(
+            &self.device,
+            self.frames.upload_pools(),
+            &self.backend.adapter.memory_types,
+            &self.backend.adapter.limits,
+            font_data,
+            pixel_height,
+        )?;
+        self.frames.add_upload(upload, &mut self.resources);
+
+        let pipeline = PipelineState::new(
+            vec![text.get_layout()],
+            &self.render_pass,
+            &TextState::pipeline_config(self.render_pass.depth_format.is_some()),
+            &self.device,
+        );
+        let pipeline = self.resources.insert(pipeline);
+        if let Some(old) = self.pipelines.insert("text".to_owned(), pipeline) {
+            self.resources.destroy(old, self.frames.serial());
+        }
+        self.text = Some(text);
+        Ok(())
+    }
+
+    /// Queues `text` to be drawn this frame with its top left corner at `origin`, in pixels from
+    /// the top left corner of the window. Does nothing until a font has been loaded.
+    pub fn draw_text(&mut self, text: &str, origin: [f32; 2], color: [f32; 4]) {
+        if let Some(ref mut text_state) = self.text {
+            text_state.queue(text, origin, color, None);
+        }
+    }
+
+    /// Present modes in order of preference, see `RendererConfig::present_modes`. Recreates the
+    /// swapchain when the preference changes.
+    pub fn set_present_modes(&mut self, present_modes: &[PresentMode]) {
+        if self.present_modes != present_modes {
+            self.present_modes = present_modes.to_vec();
+            self.recreate_swapchain();
+        }
+    }
+
+    /// The present mode the swapchain was created with.
+    pub fn present_mode(&self) -> PresentMode {
+        self.swapchain.as_ref().unwrap().present_mode
+    }
+
+    pub fn set_clear_color(&mut self, color: [f32; 4]) {
+        self.clear_color = color;
+    }
+
+    /// CPU and GPU timings of recent frames.
+    pub fn profiler(&self) -> &Profiler {
+        &self.profiler
+    }
+
+    /// For timing scopes of the application with `Profiler::begin_cpu` and `Profiler::end_cpu`.
+    pub fn profiler_mut(&mut self) -> &mut Profiler {
+        &mut self.profiler
+    }
+
+    /// Shows or hides the frame time graph. Its labels need a font to have been loaded.
+    pub fn set_profiler_overlay(&mut self, enabled: bool) {
+        self.profiler_overlay = enabled;
+    }
+
+    pub fn profiler_overlay(&self) -> bool {
+        self.profiler_overlay
+    }
+
+    /// Draw calls, instances, vertices and binds recorded for the most recent frame.
+    pub fn draw_stats(&self) -> DrawStats {
+        self.draw_stats
+    }
+
+    /// Pipeline statistics of the main render pass, as of the most recent frame the GPU has
+    /// finished. `None` unless `RendererConfig::pipeline_statistics` is set and the adapter
+    /// supports it.
+    pub fn pipeline_statistics(&self) -> Option<PipelineStatistics> {
+        self.pipeline_statistics
+    }
+
+    /// Logs the frame counters at debug level, at most every `STATS_LOG_INTERVAL`.
+    fn log_frame_stats(&mut self) {
+        let now = Instant::now();
+        if now - self.last_stats_log < STATS_LOG_INTERVAL {
+            return;
+        }
+        self.last_stats_log = now;
+
+        let stats = self.draw_stats;
+        debug!(
+            "{} draw calls, {} instances, {} vertices, {} pipeline binds, {} descriptor binds",
+            stats.draw_calls,
+            stats.instances,
+            stats.vertices,
+            stats.pipeline_binds,
+            stats.descriptor_binds
+        );
+        if let Some(statistics) = self.pipeline_statistics {
+            debug!(
+                "{} input vertices, {} input primitives, {} vertex shader invocations, {} \
+                 clipped primitives, {} fragment shader invocations",
+                statistics.input_vertices,
+                statistics.input_primitives,
+                statistics.vertex_shader_invocations,
+                statistics.clipped_primitives,
+                statistics.fragment_shader_invocations
+            );
+        }
+    }
+
+    /// Queues the labels of the profiler overlay and returns its graph as a line list in pixels.
+    fn queue_profiler_overlay(&mut self) -> Vec<DebugVertex> {
+        let origin = [
+            f32::from(self.viewport.rect.w) - PROFILER_GRAPH_SIZE[0] - 8.0,
+            8.0,
+        ];
+        let label = {
+            let average = |timeline| match self.profiler.average_frame_time(timeline) {
+                Some(time) => format!("{:.2} ms", duration_as_ms(time)),
+                None => "-".to_owned(),
+            };
+            format!(
+                "CPU {}  GPU {}",
+                average(Timeline::Cpu),
+                average(Timeline::Gpu)
+            )
+        };
+        self.draw_text(
+            &label,
+            [origin[0], origin[1] + PROFILER_GRAPH_SIZE[1] + 4.0],
+            [1.0, 1.0, 1.0, 1.0],
+        );
+        self.profiler.graph_vertices(origin, PROFILER_GRAPH_SIZE)
+    }
+
+    pub fn mainloop<A: App>(&mut self, app: &mut A, ticks_per_second: u32) {
         let mut running = true;
         let mut recreate_swapchain = false;
+        let mut timestep = FixedTimestep::new(ticks_per_second);
+        let mut context = Context::default();
 
-        let cr = 0.0;
-        let cg = 0.0;
-        let cb = 0.0;
+        app.init(self, &mut context);
 
         while running {
-            {
-                self.backend.events_loop.poll_events(|event| {
-                    if let winit::Event::WindowEvent { event, .. } = event {
-                        #[allow(unused_variables)]
-                        match event {
-                            winit::WindowEvent::KeyboardInput {
-                                input:
-                                    winit::KeyboardInput {
-                                        virtual_keycode: Some(winit::VirtualKeyCode::Escape),
-                                        ..
-                                    },
-                                ..
-                            }
-                            | winit::WindowEvent::CloseRequested => running = false,
-                            winit::WindowEvent::Resized(dims) => {
-                                recreate_swapchain = true;
-                            }
-                            _ => (),
-                        }
+            self.profiler.begin_frame(self.frames.serial());
+            let events_scope = self.profiler.begin_cpu("events");
+
+            // Events are collected first so handling them can use the rest of the renderer.
+            let mut events = Vec::new();
+            self.backend.events_loop.poll_events(|event| {
+                if let winit::Event::WindowEvent { event, .. } = event {
+                    events.push(event);
+                }
+            });
+
+            let mut hidpi_factor = self.backend.window.get_hidpi_factor();
+            for event in &events {
+                match *event {
+                    winit::WindowEvent::CloseRequested => running = false,
+                    winit::WindowEvent::Resized(size) => {
+                        self.window_extent = self.backend.physical_extent(size);
+                        recreate_swapchain = true;
+                    }
+                    winit::WindowEvent::HiDpiFactorChanged(factor) => {
+                        hidpi_factor = factor;
+                        self.window_extent = self.backend.window_extent();
+                        recreate_swapchain = true;
+                    }
+                    _ => (),
+                }
+
+                context.input.handle_event(event);
+                context.picking.handle_event(event, hidpi_factor);
+                context.picking.update(&self.camera, self.surface_size());
+
+                if app.handle_event(event) == Control::Exit {
+                    running = false;
+                }
+            }
+
+            match self.id_picking {
+                Some(ref mut id_picking) => {
+                    if let Some(entity) = id_picking.poll() {
+                        context.picked_entity = entity;
                     }
-                });
+                }
+                None => context.picked_entity = None,
+            }
+            self.profiler.end_cpu(events_scope);
+
+            let update_scope = self.profiler.begin_cpu("update");
+            timestep.begin_frame();
+            while running && timestep.tick() {
+                if app.update(&mut context, timestep.dt()) == Control::Exit {
+                    running = false;
+                }
+                context.input.end_update();
+            }
+            self.profiler.end_cpu(update_scope);
+
+            if !running {
+                break;
+            }
+
+            // A minimized window has no area to present to. Keep updating at a relaxed pace and
+            // recreate the swapchain once the window is restored.
+            if self.window_extent.width == 0 || self.window_extent.height == 0 {
+                thread::sleep(Duration::from_millis(16));
+                continue;
             }
 
             if recreate_swapchain {
@@ -185,103 +795,291 @@ impl RendererState {
                 recreate_swapchain = false;
             }
 
-            let sem_index = self.framebuffer.next_acq_pre_pair_index();
+            let render_scope = self.profiler.begin_cpu("render");
+            app.render(self, timestep.alpha());
+            self.profiler.end_cpu(render_scope);
 
-            let frame: hal::SwapImageIndex = {
-                let (acquire_semaphore, _) = self
-                    .framebuffer
-                    .get_frame_data(None, Some(sem_index))
-                    .1
-                    .unwrap();
-                match self
+            let record_scope = self.profiler.begin_cpu("record");
+            let clear_values = self.clear_values();
+            let profiler_graph = if self.profiler_overlay {
+                Some(self.queue_profiler_overlay())
+            } else {
+                None
+            };
+
+            // The camera may have moved since the cursor did.
+            context.picking.update(&self.camera, self.surface_size());
+            let highlights = self.highlights(&context.picking);
+            let terrain_constants = world_push_constants(&self.camera, Layer::Terrain);
+
+            self.resources.collect(self.frames.completed_serial());
+
+            let serial = self.frames.serial();
+            let (image, submit) = {
+                let frame = self.frames.begin_frame();
+                let image: hal::SwapImageIndex = match self
                     .swapchain
                     .as_mut()
                     .unwrap()
                     .swapchain
                     .as_mut()
                     .unwrap()
-                    .acquire_image(!0, hal::FrameSync::Semaphore(acquire_semaphore))
+                    .acquire_image(!0, hal::FrameSync::Semaphore(&frame.acquire_semaphore))
                 {
                     Ok(i) => i,
                     Err(_) => {
+                        // The frame is rendered again from scratch.
+                        self.debug_draw.clear();
                         recreate_swapchain = true;
                         continue;
                     }
+                };
+
+                let text_vertices = match self.text {
+                    Some(ref mut text) => text.upload(&mut frame.arena),
+                    None => None,
+                };
+                let debug_vertices = frame.arena.upload(self.debug_draw.vertices());
+                self.debug_draw.clear();
+                let highlight_instances: Vec<_> =
+                    highlights.iter().map(|&(instance, _)| instance).collect();
+                let highlight_instances = frame.arena.upload(&highlight_instances);
+                let profiler_vertices = match profiler_graph {
+                    Some(ref vertices) => frame.arena.upload(vertices),
+                    None => None,
+                };
+
+                let render_pass = self.render_pass.render_pass.as_ref().unwrap();
+                let framebuffer = self
+                    .framebuffer
+                    .as_ref()
+                    .unwrap()
+                    .framebuffer(image as usize);
+
+                if let Some(ref mut queries) = self.statistics_queries {
+                    if let Some((_, statistics)) = queries.begin_frame(frame.index, serial) {
+                        self.pipeline_statistics = Some(statistics);
+                    }
                 }
-            };
 
-            let (fid, sid) = self
-                .framebuffer
-                .get_frame_data(Some(frame as usize), Some(sem_index));
-
-            let (framebuffer_fence, framebuffer, command_pool) = fid.unwrap();
-            let (image_acquired, image_present) = sid.unwrap();
-
-            self.device
-                .borrow()
-                .device
-                .wait_for_fence(framebuffer_fence, !0)
-                .unwrap();
-            self.device
-                .borrow()
-                .device
-                .reset_fence(framebuffer_fence)
-                .unwrap();
-            command_pool.reset();
-
-            // Rendering
-            let submit = {
-                let mut cmd_buffer = command_pool.acquire_command_buffer(false);
-                let pipeline = self.pipelines.get("main").expect("Pipeline not found");
-                cmd_buffer.set_viewports(0, &[self.viewport.clone()]);
-                cmd_buffer.set_scissors(0, &[self.viewport.rect]);
-                cmd_buffer.bind_graphics_pipeline(pipeline.pipeline.as_ref().unwrap());
-                cmd_buffer.bind_vertex_buffers(0, Some((self.vertex_buffer.get_buffer(), 0)));
-                cmd_buffer.bind_graphics_descriptor_sets(
-                    pipeline.pipeline_layout.as_ref().unwrap(),
-                    0,
-                    vec![self.uniform.desc.as_ref().unwrap().set.as_ref().unwrap()],
-                    &[],
-                ); //TODO
+                // The map is recorded in chunks on worker threads.
+                let (map_commands, mut stats) = match self.instance_buffer {
+                    Some(instance_buffer) if self.instance_count > 0 => {
+                        let draw = MapDraw {
+                            render_pass,
+                            framebuffer,
+                            viewport: &self.viewport,
+                            main: &self.resources[self.pipelines["main"]],
+                            grid: self
+                                .grid_style
+                                .map(|style| (&self.resources[self.pipelines["grid"]], style)),
+                            descriptor_set: self
+                                .uniform
+                                .desc
+                                .as_ref()
+                                .unwrap()
+                                .set
+                                .as_ref()
+                                .unwrap(),
+                            vertex_buffer: self.vertex_buffer.get_buffer(),
+                            instance_buffer: self.resources[instance_buffer].get_buffer(),
+                            instance_count: self.instance_count,
+                            push_constants: &terrain_constants,
+                            statistics: self.statistics_queries.as_ref(),
+                        };
+                        self.map_chunks.record(frame.index, &draw)
+                    }
+                    _ => (Vec::new(), DrawStats::default()),
+                };
 
-                {
-                    let mut encoder = cmd_buffer.begin_render_pass_inline(
-                        self.render_pass.render_pass.as_ref().unwrap(),
-                        framebuffer,
-                        self.viewport.rect,
-                        &[command::ClearValue::Color(command::ClearColor::Float([
-                            cr, cg, cb, 1.0,
-                        ]))],
+                // Highlights, debug lines and text are drawn over the map on this thread.
+                let (overlay, overlay_stats) = {
+                    let subpass = pass::Subpass {
+                        index: 0,
+                        main_pass: render_pass,
+                    };
+                    let mut encoder = CountingEncoder::new(
+                        frame.command_pool.acquire_subpass_command_buffer(
+                            subpass,
+                            Some(framebuffer),
+                            false,
+                        ),
+                        self.statistics_queries
+                            .as_ref()
+                            .and_then(|queries| queries.next_query()),
                     );
-                    encoder.draw(0..18, 0..1);
-                }
+                    encoder.set_viewports(0, &[self.viewport.clone()]);
+                    encoder.set_scissors(0, &[self.viewport.rect]);
+                    encoder.bind_vertex_buffers(0, Some((self.vertex_buffer.get_buffer(), 0)));
 
-                cmd_buffer.finish()
+                    if let Some(highlight_instances) = highlight_instances {
+                        let grid = &self.resources[self.pipelines["grid"]];
+                        let layout = grid.pipeline_layout.as_ref().unwrap();
+                        encoder.bind_graphics_pipeline(grid.pipeline.as_ref().unwrap());
+                        encoder.bind_graphics_descriptor_sets(
+                            layout,
+                            0,
+                            Some(self.uniform.desc.as_ref().unwrap().set.as_ref().unwrap()),
+                            &[],
+                        );
+                        encoder
+                            .bind_vertex_buffers(1, Some(frame.arena.binding(highlight_instances)));
+                        encoder.push_graphics_constants(
+                            layout,
+                            pso::ShaderStageFlags::VERTEX,
+                            0,
+                            &terrain_constants,
+                        );
+                        for (i, &(_, style)) in highlights.iter().enumerate() {
+                            encoder.push_graphics_constants(
+                                layout,
+                                pso::ShaderStageFlags::FRAGMENT,
+                                grid::FRAGMENT_CONSTANTS_OFFSET,
+                                &style.push_constants(),
+                            );
+                            encoder.draw(0..18, i as u32..i as u32 + 1);
+                        }
+                    }
+
+                    if let Some(&fog_pipeline) = self.pipelines.get("fog") {
+                        let pipeline = &self.resources[fog_pipeline];
+                        let layout = pipeline.pipeline_layout.as_ref().unwrap();
+                        encoder.bind_graphics_pipeline(pipeline.pipeline.as_ref().unwrap());
+                        encoder.push_graphics_constants(
+                            layout,
+                            pso::ShaderStageFlags::VERTEX,
+                            0,
+                            &fog::vertex_constants(),
+                        );
+                        encoder.push_graphics_constants(
+                            layout,
+                            pso::ShaderStageFlags::FRAGMENT,
+                            fog::FRAGMENT_CONSTANTS_OFFSET,
+                            &fog::fragment_constants(FOG_COLOR),
+                        );
+                        encoder.draw(0..3, 0..1);
+                    }
+
+                    if let Some(debug_vertices) = debug_vertices {
+                        let pipeline = &self.resources[self.pipelines["debug"]];
+                        encoder.bind_graphics_pipeline(pipeline.pipeline.as_ref().unwrap());
+                        encoder.bind_vertex_buffers(0, Some(frame.arena.binding(debug_vertices)));
+                        encoder.push_graphics_constants(
+                            pipeline.pipeline_layout.as_ref().unwrap(),
+                            pso::ShaderStageFlags::VERTEX,
+                            0,
+                            &self.camera.push_constants(),
+                        );
+                        encoder.draw(0..debug_vertices.count, 0..1);
+                    }
+
+                    if let Some(profiler_vertices) = profiler_vertices {
+                        let pipeline = &self.resources[self.pipelines["debug"]];
+                        encoder.bind_graphics_pipeline(pipeline.pipeline.as_ref().unwrap());
+                        encoder
+                            .bind_vertex_buffers(0, Some(frame.arena.binding(profiler_vertices)));
+                        encoder.push_graphics_constants(
+                            pipeline.pipeline_layout.as_ref().unwrap(),
+                            pso::ShaderStageFlags::VERTEX,
+                            0,
+                            &screen_push_constants(&self.viewport),
+                        );
+                        encoder.draw(0..profiler_vertices.count, 0..1);
+                    }
+
+                    if let Some(text_vertices) = text_vertices {
+                        let text = self.text.as_ref().unwrap();
+                        let pipeline = &self.resources[self.pipelines["text"]];
+                        let layout = pipeline.pipeline_layout.as_ref().unwrap();
+                        encoder.bind_graphics_pipeline(pipeline.pipeline.as_ref().unwrap());
+                        encoder.bind_graphics_descriptor_sets(
+                            layout,
+                            0,
+                            Some(text.desc.set.as_ref().unwrap()),
+                            &[],
+                        );
+                        encoder.bind_vertex_buffers(0, Some(frame.arena.binding(text_vertices)));
+                        encoder.push_graphics_constants(
+                            layout,
+                            pso::ShaderStageFlags::VERTEX,
+                            0,
+                            &[
+                                (self.viewport.rect.w as f32).to_bits(),
+                                (self.viewport.rect.h as f32).to_bits(),
+                                Layer::Ui.depth().to_bits(),
+                            ],
+                        );
+                        encoder.draw(0..text_vertices.count, 0..1);
+                    }
+
+                    encoder.finish()
+                };
+                stats += overlay_stats;
+
+                let submit = {
+                    let mut cmd_buffer = frame.command_pool.acquire_command_buffer(false);
+                    if let Some((serial, scopes)) =
+                        self.gpu_timer
+                            .begin_frame(frame.index, serial, &mut cmd_buffer)
+                    {
+                        self.profiler.add_gpu(serial, scopes);
+                    }
+                    if let Some(ref mut queries) = self.statistics_queries {
+                        queries.reset(&mut cmd_buffer);
+                    }
+                    let frame_scope = self.gpu_timer.begin_scope(&mut cmd_buffer, FRAME_SCOPE);
+                    let pass_scope = self.gpu_timer.begin_scope(&mut cmd_buffer, "main pass");
+                    {
+                        let mut encoder = cmd_buffer.begin_render_pass_secondary(
+                            render_pass,
+                            framebuffer,
+                            self.viewport.rect,
+                            &clear_values,
+                        );
+                        encoder.execute_commands(map_commands.iter().chain(Some(&overlay)));
+                    }
+                    self.gpu_timer.end_scope(&mut cmd_buffer, pass_scope);
+                    self.gpu_timer.end_scope(&mut cmd_buffer, frame_scope);
+                    cmd_buffer.finish()
+                };
+                self.draw_stats = stats;
+
+                (image, submit)
             };
 
-            let submission = queue::Submission::new()
-                .wait_on(&[(&*image_acquired, pso::PipelineStage::BOTTOM_OF_PIPE)])
-                .signal(&[&*image_present])
-                .submit(Some(submit));
-            self.device.borrow_mut().queues.queues[0].submit(submission, Some(framebuffer_fence));
-
-            // present frame
-            if self
-                .swapchain
-                .as_ref()
-                .unwrap()
-                .swapchain
-                .as_ref()
-                .unwrap()
-                .present(
-                    &mut self.device.borrow_mut().queues.queues[0],
-                    frame,
-                    Some(&*image_present),
-                )
-                .is_err()
+            // Both are submitted before the frame so that the frame's fence covers them as well.
+            // Uploads go first, the pick may read from them.
+            self.frames.submit_uploads();
+            if let (Some(id_picking), Some(pick_buffer)) =
+                (self.id_picking.as_mut(), self.pick_buffer)
             {
+                let pick_buffer = &self.resources[pick_buffer];
+                match context.picking.cursor() {
+                    Some(cursor) => id_picking.submit(
+                        (cursor.x as u32, cursor.y as u32),
+                        self.vertex_buffer.get_buffer(),
+                        pick_buffer.get_buffer(),
+                        self.pick_count,
+                        &self.camera.push_constants(),
+                    ),
+                    None => context.picked_entity = None,
+                }
+            }
+
+            self.profiler.end_cpu(record_scope);
+
+            let present_scope = self.profiler.begin_cpu("present");
+            let presented = self.frames.end_frame(
+                submit,
+                self.swapchain.as_ref().unwrap().swapchain.as_ref().unwrap(),
+                image,
+            );
+            self.profiler.end_cpu(present_scope);
+            self.profiler.end_frame();
+            self.log_frame_stats();
+
+            if presented.is_err() {
                 recreate_swapchain = true;
-                continue;
             }
         }
     }
@@ -289,11 +1087,8 @@ impl RendererState {
 
 impl Drop for RendererState {
     fn drop(&mut self) {
-        self.device.borrow().device.wait_idle().unwrap();
-        self.device
-            .borrow()
-            .device
-            .destroy_descriptor_pool(self.uniform_desc_pool.take().unwrap());
+        self.device.device.wait_idle().unwrap();
+        self.framebuffer.take();
         self.swapchain.take();
     }
 }
@@ -0,0 +1,161 @@
+use super::{read_query_results, BackendImpl, DeviceState, PipelineStatistics, QueryPoolImpl};
+use hal::{command, query, Device, Graphics, PhysicalDevice};
+use std::cmp;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// Statistics gathered by every query, in the order the results are written in.
+fn statistics() -> query::PipelineStatistic {
+    query::PipelineStatistic::INPUT_ASSEMBLY_VERTICES
+        | query::PipelineStatistic::INPUT_ASSEMBLY_PRIMITIVES
+        | query::PipelineStatistic::VERTEX_SHADER_INVOCATIONS
+        | query::PipelineStatistic::CLIPPING_PRIMITIVES
+        | query::PipelineStatistic::FRAGMENT_SHADER_INVOCATIONS
+}
+
+const STATISTICS_COUNT: usize = 5;
+
+/// Queries per frame. Secondary command buffers beyond this many go uncounted.
+const MAX_QUERIES: usize = 64;
+
+struct FrameQueries {
+    pool: Option<QueryPoolImpl>,
+    /// Serial of the frame the queries were written by, `None` while there is nothing to read.
+    serial: Option<u64>,
+    /// Queries handed out to the frame.
+    count: usize,
+}
+
+/// Pipeline statistics queries per frame in flight, read back like `GpuTimer` reads its
+/// timestamps: once the frame comes around again, without stalling.
+///
+/// Queries active in the primary command buffer only cover secondary command buffers where the
+/// device inherits queries, so every secondary command buffer of the main render pass begins and
+/// ends a query of its own instead, and the results of a frame are summed.
+pub struct StatisticsQueries {
+    frames: Vec<FrameQueries>,
+    current: usize,
+    /// Next query of the current frame to hand out, taken from any thread recording a secondary
+    /// command buffer.
+    next: AtomicUsize,
+    device: Arc<DeviceState>,
+}
+
+impl StatisticsQueries {
+    /// `None` when the device doesn't support pipeline statistics queries.
+    pub fn new(device: &Arc<DeviceState>, frames_in_flight: usize) -> Option<Self> {
+        if !device
+            .physical_device
+            .features()
+            .contains(hal::Features::PIPELINE_STATISTICS_QUERY)
+        {
+            return None;
+        }
+
+        let frames = (0..frames_in_flight)
+            .map(|_| FrameQueries {
+                pool: Some(
+                    device
+                        .device
+                        .create_query_pool(
+                            query::Type::PipelineStatistics(statistics()),
+                            MAX_QUERIES as u32,
+                        )
+                        .expect("Can't create query pool"),
+                ),
+                serial: None,
+                count: 0,
+            })
+            .collect();
+
+        Some(StatisticsQueries {
+            frames,
+            current: 0,
+            next: AtomicUsize::new(0),
+            device: Arc::clone(device),
+        })
+    }
+
+    /// Switches to the queries of the frame at `frame_index`, which the GPU has to be done with,
+    /// for the frame with `serial`.
+    ///
+    /// Returns the serial and statistics of the frame that last used the queries, if any.
+    pub fn begin_frame(
+        &mut self,
+        frame_index: usize,
+        serial: u64,
+    ) -> Option<(u64, PipelineStatistics)> {
+        self.current = frame_index;
+        let results = self.read_results();
+
+        self.frames[frame_index].serial = Some(serial);
+        *self.next.get_mut() = 0;
+
+        results
+    }
+
+    /// Hands out the next query of the current frame, to be begun and ended inside one secondary
+    /// command buffer. `None` once every query is taken.
+    pub fn next_query(&self) -> Option<query::Query<BackendImpl>> {
+        let id = self.next.fetch_add(1, Ordering::Relaxed);
+        if id >= MAX_QUERIES {
+            return None;
+        }
+        Some(query::Query {
+            pool: self.frames[self.current].pool.as_ref().unwrap(),
+            id: id as query::Id,
+        })
+    }
+
+    /// Resets the queries handed out for the current frame in `cmd_buffer`, which has to run
+    /// before the secondary command buffers using them. Has to be recorded outside of a render
+    /// pass.
+    pub fn reset(&mut self, cmd_buffer: &mut command::CommandBuffer<BackendImpl, Graphics>) {
+        let count = cmp::min(*self.next.get_mut(), MAX_QUERIES);
+        let frame = &mut self.frames[self.current];
+        cmd_buffer.reset_query_pool(frame.pool.as_ref().unwrap(), 0..count as query::Id);
+        frame.count = count;
+    }
+
+    fn read_results(&mut self) -> Option<(u64, PipelineStatistics)> {
+        let frame = &mut self.frames[self.current];
+        let serial = frame.serial.take()?;
+        let count = frame.count;
+        frame.count = 0;
+
+        let mut sums = [0; STATISTICS_COUNT];
+        if count > 0 {
+            let values = read_query_results(
+                &self.device,
+                frame.pool.as_ref().unwrap(),
+                0..count as query::Id,
+                STATISTICS_COUNT,
+            )?;
+            for query in values.chunks(STATISTICS_COUNT) {
+                for (sum, value) in sums.iter_mut().zip(query) {
+                    *sum += value;
+                }
+            }
+        }
+        Some((
+            serial,
+            PipelineStatistics {
+                input_vertices: sums[0],
+                input_primitives: sums[1],
+                vertex_shader_invocations: sums[2],
+                clipped_primitives: sums[3],
+                fragment_shader_invocations: sums[4],
+            },
+        ))
+    }
+}
+
+impl Drop for StatisticsQueries {
+    fn drop(&mut self) {
+        for frame in &mut self.frames {
+            self.device
+                .device
+                .destroy_query_pool(frame.pool.take().unwrap());
+        }
+    }
+}
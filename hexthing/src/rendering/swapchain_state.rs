@@ -1,21 +1,70 @@
 use super::{BackendImpl, BackendState, DeviceState, SwapchainImpl};
-use hal::{self, format, image, Device, Surface};
-use std::cell::RefCell;
-use std::rc::Rc;
+use config::PresentMode;
+use hal::{self, format, image, window, Device, Surface};
+use std::ops::Range;
+use std::sync::Arc;
+
+fn to_hal(mode: PresentMode) -> hal::PresentMode {
+    match mode {
+        PresentMode::Immediate => hal::PresentMode::Immediate,
+        PresentMode::Mailbox => hal::PresentMode::Mailbox,
+        PresentMode::Fifo => hal::PresentMode::Fifo,
+        PresentMode::Relaxed => hal::PresentMode::Relaxed,
+    }
+}
+
+/// Picks the first of `preferred` that the surface supports. Fifo is the only mode every surface
+/// has to support, so it ends the chain.
+fn choose_present_mode(
+    preferred: &[PresentMode],
+    supported: &[hal::PresentMode],
+) -> (PresentMode, hal::PresentMode) {
+    preferred
+        .iter()
+        .map(|&mode| (mode, to_hal(mode)))
+        .find(|&(_, mode)| supported.contains(&mode))
+        .unwrap_or((PresentMode::Fifo, hal::PresentMode::Fifo))
+}
+
+/// Mailbox needs a third image so the application can keep rendering while one image is being
+/// presented and another one is queued.
+///
+/// The end of `image_count` is the largest supported count, not one past it. Surfaces without a
+/// limit report `!0`, or 0 as Vulkan does.
+fn choose_image_count(
+    present_mode: hal::PresentMode,
+    image_count: Range<hal::SwapImageIndex>,
+) -> hal::SwapImageIndex {
+    let desired = match present_mode {
+        hal::PresentMode::Mailbox => 3,
+        _ => 2,
+    };
+    let max = match image_count.end {
+        0 => !0,
+        end => end.max(image_count.start),
+    };
+    desired.max(image_count.start).min(max)
+}
 
 pub struct SwapchainState {
     pub backbuffer: Option<hal::Backbuffer<BackendImpl>>,
     pub extent: image::Extent,
     pub format: format::Format,
+    pub present_mode: PresentMode,
     pub swapchain: Option<SwapchainImpl>,
-    device: Rc<RefCell<DeviceState>>,
+    device: Arc<DeviceState>,
 }
 
 impl SwapchainState {
-    pub fn new(backend: &mut BackendState, device: Rc<RefCell<DeviceState>>) -> Self {
-        let (caps, formats, _present_modes) = backend
-            .surface
-            .compatibility(&device.borrow().physical_device);
+    pub fn new(
+        backend: &mut BackendState,
+        device: Arc<DeviceState>,
+        present_modes: &[PresentMode],
+        window_extent: window::Extent2D,
+        old_swapchain: Option<SwapchainState>,
+    ) -> Self {
+        let (caps, formats, supported_modes) =
+            backend.surface.compatibility(&device.physical_device);
         println!("formats: {:?}", formats);
         let format = formats.map_or(format::Format::Rgba8Srgb, |formats| {
             formats
@@ -26,12 +75,36 @@ impl SwapchainState {
         });
 
         println!("Surface format: {:?}", format);
-        let swap_config = hal::SwapchainConfig::from_caps(&caps, format);
+        let mut swap_config = hal::SwapchainConfig::from_caps(&caps, format);
+        let (present_mode, hal_present_mode) = choose_present_mode(present_modes, &supported_modes);
+        swap_config.present_mode = hal_present_mode;
+        swap_config.image_count = choose_image_count(hal_present_mode, caps.image_count.clone());
+        println!(
+            "Present mode: {:?} with {} images (requested {:?}, supported {:?})",
+            present_mode, swap_config.image_count, present_modes, supported_modes
+        );
+        // Surfaces that don't dictate their size take the window's, which has to be within the
+        // supported range.
+        if caps.current_extent.is_none() {
+            swap_config.extent = window::Extent2D {
+                width: window_extent
+                    .width
+                    .max(caps.extents.start.width)
+                    .min(caps.extents.end.width),
+                height: window_extent
+                    .height
+                    .max(caps.extents.start.height)
+                    .min(caps.extents.end.height),
+            };
+        }
         let extent = swap_config.extent.to_extent();
         let (swapchain, backbuffer) = device
-            .borrow()
             .device
-            .create_swapchain(&mut backend.surface, swap_config, None)
+            .create_swapchain(
+                &mut backend.surface,
+                swap_config,
+                old_swapchain.and_then(|mut old| old.swapchain.take()),
+            )
             .expect("Can't create swapchain");
 
         SwapchainState {
@@ -40,15 +113,102 @@ impl SwapchainState {
             device,
             extent,
             format,
+            present_mode,
         }
     }
 }
 
 impl Drop for SwapchainState {
     fn drop(&mut self) {
-        self.device
-            .borrow()
-            .device
-            .destroy_swapchain(self.swapchain.take().unwrap());
+        // Swapchains handed to `create_swapchain` as the old one are destroyed by it.
+        if let Some(swapchain) = self.swapchain.take() {
+            self.device.device.destroy_swapchain(swapchain);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::NO_VSYNC_PRESENT_MODES;
+
+    #[test]
+    fn image_count_with_fixed_count() {
+        assert_eq!(choose_image_count(hal::PresentMode::Fifo, 3..3), 3);
+        assert_eq!(choose_image_count(hal::PresentMode::Mailbox, 2..2), 2);
+    }
+
+    #[test]
+    fn image_count_without_limit() {
+        assert_eq!(choose_image_count(hal::PresentMode::Fifo, 1..0), 2);
+        assert_eq!(choose_image_count(hal::PresentMode::Mailbox, 1..0), 3);
+        assert_eq!(choose_image_count(hal::PresentMode::Mailbox, 2..!0), 3);
+        assert_eq!(choose_image_count(hal::PresentMode::Fifo, 4..0), 4);
+    }
+
+    #[test]
+    fn image_count_below_min() {
+        assert_eq!(choose_image_count(hal::PresentMode::Fifo, 3..8), 3);
+        assert_eq!(choose_image_count(hal::PresentMode::Mailbox, 4..8), 4);
+    }
+
+    #[test]
+    fn image_count_above_max() {
+        // The maximum itself is allowed.
+        assert_eq!(choose_image_count(hal::PresentMode::Mailbox, 2..3), 3);
+        assert_eq!(choose_image_count(hal::PresentMode::Mailbox, 1..2), 2);
+        assert_eq!(choose_image_count(hal::PresentMode::Fifo, 1..1), 1);
+    }
+
+    #[test]
+    fn present_mode_with_vsync() {
+        let all = [
+            hal::PresentMode::Immediate,
+            hal::PresentMode::Mailbox,
+            hal::PresentMode::Fifo,
+            hal::PresentMode::Relaxed,
+        ];
+        assert_eq!(
+            choose_present_mode(&[PresentMode::Fifo], &all),
+            (PresentMode::Fifo, hal::PresentMode::Fifo)
+        );
+        assert_eq!(
+            choose_present_mode(&[PresentMode::Relaxed, PresentMode::Fifo], &all),
+            (PresentMode::Relaxed, hal::PresentMode::Relaxed)
+        );
+        assert_eq!(
+            choose_present_mode(
+                &[PresentMode::Relaxed, PresentMode::Fifo],
+                &[hal::PresentMode::Fifo]
+            ),
+            (PresentMode::Fifo, hal::PresentMode::Fifo)
+        );
+    }
+
+    #[test]
+    fn present_mode_without_vsync() {
+        assert_eq!(
+            choose_present_mode(
+                &NO_VSYNC_PRESENT_MODES,
+                &[
+                    hal::PresentMode::Fifo,
+                    hal::PresentMode::Immediate,
+                    hal::PresentMode::Mailbox,
+                ]
+            ),
+            (PresentMode::Mailbox, hal::PresentMode::Mailbox)
+        );
+        assert_eq!(
+            choose_present_mode(
+                &NO_VSYNC_PRESENT_MODES,
+                &[hal::PresentMode::Fifo, hal::PresentMode::Immediate]
+            ),
+            (PresentMode::Immediate, hal::PresentMode::Immediate)
+        );
+        // Fifo ends every chain, even one that doesn't list it.
+        assert_eq!(
+            choose_present_mode(&NO_VSYNC_PRESENT_MODES, &[hal::PresentMode::Fifo]),
+            (PresentMode::Fifo, hal::PresentMode::Fifo)
+        );
     }
 }
@@ -0,0 +1,379 @@
+use super::atlas::{Atlas, AtlasBuilder, UvRect};
+use fnv::FnvHashMap;
+use rusttype::{point, Font, Scale};
+
+const ATLAS_WIDTH: u32 = 512;
+
+/// First and last character rasterized into the glyph atlas: printable ASCII.
+const FIRST_CHAR: u8 = b' ';
+const LAST_CHAR: u8 = b'~';
+
+#[derive(Debug, Copy, Clone)]
+#[repr(C)]
+pub struct TextVertex {
+    pub position: [f32; 2],
+    pub uv: [f32; 2],
+    pub color: [f32; 4],
+}
+
+#[derive(Debug, Copy, Clone)]
+struct GlyphInfo {
+    /// Offset of the bitmap's top left corner from the pen position on the baseline.
+    offset: [f32; 2],
+    size: [f32; 2],
+    uv: Option<UvRect>,
+    advance: f32,
+}
+
+/// A glyph positioned by `GlyphCache::layout`, in pixels relative to the top left corner of the
+/// text block.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct GlyphQuad {
+    pub min: [f32; 2],
+    pub max: [f32; 2],
+    pub uv: UvRect,
+}
+
+/// A font rasterized at a single pixel size, along with the metrics needed to lay out text.
+pub struct GlyphCache {
+    font: Font<'static>,
+    scale: Scale,
+    glyphs: FnvHashMap<char, GlyphInfo>,
+    ascent: f32,
+    line_height: f32,
+}
+
+impl GlyphCache {
+    /// Rasterizes the printable ASCII range of `font_data` at `pixel_height` and returns the cache
+    /// together with the RGBA atlas holding the glyph coverage in its alpha channel.
+    pub fn new(font_data: Vec<u8>, pixel_height: f32) -> Result<(Self, Atlas), String> {
+        let font = Font::from_bytes(font_data).map_err(|err| format!("{}", err))?;
+        let scale = Scale::uniform(pixel_height);
+        let v_metrics = font.v_metrics(scale);
+
+        let mut builder = AtlasBuilder::new(ATLAS_WIDTH).with_padding(1);
+        let mut glyphs = FnvHashMap::default();
+
+        for ch in (FIRST_CHAR..=LAST_CHAR).map(char::from) {
+            let glyph = font.glyph(ch).scaled(scale);
+            let advance = glyph.h_metrics().advance_width;
+            let glyph = glyph.positioned(point(0.0, 0.0));
+
+            let info = match glyph.pixel_bounding_box() {
+                Some(bounds) if bounds.width() > 0 && bounds.height() > 0 => {
+                    let (width, height) = (bounds.width() as u32, bounds.height() as u32);
+                    let mut pixels = vec![255u8; (width * height * 4) as usize];
+                    glyph.draw(|x, y, coverage| {
+                        let alpha = (y * width + x) as usize * 4 + 3;
+                        pixels[alpha] = (coverage * 255.0).round() as u8;
+                    });
+                    builder.add_tile(&ch.to_string(), width, height, pixels);
+
+                    GlyphInfo {
+                        offset: [bounds.min.x as f32, bounds.min.y as f32],
+                        size: [width as f32, height as f32],
+                        uv: None,
+                        advance,
+                    }
+                }
+                _ => GlyphInfo {
+                    offset: [0.0, 0.0],
+                    size: [0.0, 0.0],
+                    uv: None,
+                    advance,
+                },
+            };
+            glyphs.insert(ch, info);
+        }
+
+        let atlas = builder.build();
+        for (ch, info) in &mut glyphs {
+            info.uv = atlas.uv_rect(&ch.to_string());
+        }
+
+        let cache = GlyphCache {
+            font,
+            scale,
+            glyphs,
+            ascent: v_metrics.ascent,
+            line_height: v_metrics.ascent - v_metrics.descent + v_metrics.line_gap,
+        };
+        Ok((cache, atlas))
+    }
+
+    pub fn line_height(&self) -> f32 {
+        self.line_height
+    }
+
+    /// Width of a run of characters on a single line, including kerning between them.
+    pub fn measure(&self, text: &str) -> f32 {
+        measure(self, text)
+    }
+
+    /// Positions the glyphs of `text`. Lines are broken at `\n`, and when `max_width` is given,
+    /// before any word that would overflow it. Words wider than `max_width` are kept whole.
+    pub fn layout(&self, text: &str, max_width: Option<f32>) -> Vec<GlyphQuad> {
+        layout(self, text, max_width)
+    }
+}
+
+/// The metrics layout needs, split from `GlyphCache` so line breaking and pen advance can be
+/// tested without loading a font.
+trait GlyphMetrics {
+    fn glyph(&self, ch: char) -> Option<&GlyphInfo>;
+    fn kerning(&self, previous: Option<char>, ch: char) -> f32;
+    /// Distance from the top of a line to its baseline.
+    fn ascent(&self) -> f32;
+    fn line_height(&self) -> f32;
+}
+
+impl GlyphMetrics for GlyphCache {
+    fn glyph(&self, ch: char) -> Option<&GlyphInfo> {
+        self.glyphs.get(&ch).or_else(|| self.glyphs.get(&'?'))
+    }
+
+    fn kerning(&self, previous: Option<char>, ch: char) -> f32 {
+        previous.map_or(0.0, |previous| {
+            self.font.pair_kerning(self.scale, previous, ch)
+        })
+    }
+
+    fn ascent(&self) -> f32 {
+        self.ascent
+    }
+
+    fn line_height(&self) -> f32 {
+        self.line_height
+    }
+}
+
+fn measure<M: GlyphMetrics>(metrics: &M, text: &str) -> f32 {
+    let mut width = 0.0;
+    let mut previous = None;
+    for ch in text.chars() {
+        if let Some(glyph) = metrics.glyph(ch) {
+            width += metrics.kerning(previous, ch) + glyph.advance;
+        }
+        previous = Some(ch);
+    }
+    width
+}
+
+fn layout<M: GlyphMetrics>(metrics: &M, text: &str, max_width: Option<f32>) -> Vec<GlyphQuad> {
+    let mut quads = Vec::new();
+    let mut baseline = metrics.ascent();
+
+    for line in text.split('\n') {
+        let mut caret = 0.0;
+        let mut previous: Option<char> = None;
+
+        for (i, word) in line.split(' ').enumerate() {
+            let space = if i > 0 { measure(metrics, " ") } else { 0.0 };
+            let wraps = max_width.map_or(false, |max_width| {
+                caret > 0.0 && caret + space + measure(metrics, word) > max_width
+            });
+            if wraps {
+                caret = 0.0;
+                previous = None;
+                baseline += metrics.line_height();
+            } else if i > 0 {
+                caret += metrics.kerning(previous, ' ') + space;
+                previous = Some(' ');
+            }
+
+            for ch in word.chars() {
+                let glyph = match metrics.glyph(ch) {
+                    Some(glyph) => glyph,
+                    None => continue,
+                };
+                caret += metrics.kerning(previous, ch);
+                if let Some(uv) = glyph.uv {
+                    let min = [caret + glyph.offset[0], baseline + glyph.offset[1]];
+                    quads.push(GlyphQuad {
+                        min,
+                        max: [min[0] + glyph.size[0], min[1] + glyph.size[1]],
+                        uv,
+                    });
+                }
+                caret += glyph.advance;
+                previous = Some(ch);
+            }
+        }
+
+        baseline += metrics.line_height();
+    }
+
+    quads
+}
+
+/// Appends two triangles per glyph, offset by `origin` (in pixels), to `vertices`.
+pub fn push_quads(
+    vertices: &mut Vec<TextVertex>,
+    quads: &[GlyphQuad],
+    origin: [f32; 2],
+    color: [f32; 4],
+) {
+    for quad in quads {
+        let (x0, y0) = (origin[0] + quad.min[0], origin[1] + quad.min[1]);
+        let (x1, y1) = (origin[0] + quad.max[0], origin[1] + quad.max[1]);
+        let (u0, v0) = (quad.uv.u, quad.uv.v);
+        let (u1, v1) = (quad.uv.u + quad.uv.width, quad.uv.v + quad.uv.height);
+
+        let vertex = |x, y, u, v| TextVertex {
+            position: [x, y],
+            uv: [u, v],
+            color,
+        };
+        vertices.extend_from_slice(&[
+            vertex(x0, y0, u0, v0),
+            vertex(x1, y0, u1, v0),
+            vertex(x1, y1, u1, v1),
+            vertex(x0, y0, u0, v0),
+            vertex(x1, y1, u1, v1),
+            vertex(x0, y1, u0, v1),
+        ]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UV: UvRect = UvRect {
+        u: 0.0,
+        v: 0.0,
+        width: 0.1,
+        height: 0.1,
+    };
+
+    /// Every glyph advances 10 pixels except the space, which advances 5 and has no bitmap. `AV`
+    /// kerns by -2.
+    struct FixedMetrics {
+        glyph: GlyphInfo,
+        space: GlyphInfo,
+    }
+
+    impl FixedMetrics {
+        fn new() -> Self {
+            FixedMetrics {
+                glyph: GlyphInfo {
+                    offset: [1.0, -8.0],
+                    size: [8.0, 8.0],
+                    uv: Some(UV),
+                    advance: 10.0,
+                },
+                space: GlyphInfo {
+                    offset: [0.0, 0.0],
+                    size: [0.0, 0.0],
+                    uv: None,
+                    advance: 5.0,
+                },
+            }
+        }
+    }
+
+    impl GlyphMetrics for FixedMetrics {
+        fn glyph(&self, ch: char) -> Option<&GlyphInfo> {
+            match ch {
+                ' ' => Some(&self.space),
+                '\t' => None,
+                _ => Some(&self.glyph),
+            }
+        }
+
+        fn kerning(&self, previous: Option<char>, ch: char) -> f32 {
+            match (previous, ch) {
+                (Some('A'), 'V') => -2.0,
+                _ => 0.0,
+            }
+        }
+
+        fn ascent(&self) -> f32 {
+            10.0
+        }
+
+        fn line_height(&self) -> f32 {
+            15.0
+        }
+    }
+
+    /// Left edge and baseline of every quad.
+    fn pens(quads: &[GlyphQuad]) -> Vec<(f32, f32)> {
+        quads
+            .iter()
+            .map(|quad| (quad.min[0] - 1.0, quad.min[1] + 8.0))
+            .collect()
+    }
+
+    #[test]
+    fn measure_accumulates_advances_and_kerning() {
+        let metrics = FixedMetrics::new();
+        assert_eq!(measure(&metrics, ""), 0.0);
+        assert_eq!(measure(&metrics, "AB"), 20.0);
+        assert_eq!(measure(&metrics, "A B"), 25.0);
+        assert_eq!(measure(&metrics, "AV"), 18.0);
+        assert_eq!(measure(&metrics, "A\tB"), 20.0);
+    }
+
+    #[test]
+    fn layout_advances_pen_with_kerning() {
+        let metrics = FixedMetrics::new();
+        let quads = layout(&metrics, "AVA B", None);
+        assert_eq!(
+            pens(&quads),
+            vec![(0.0, 10.0), (8.0, 10.0), (18.0, 10.0), (33.0, 10.0)]
+        );
+        assert_eq!(quads[0].max, [9.0, 10.0]);
+        assert_eq!(quads[0].uv, UV);
+    }
+
+    #[test]
+    fn layout_breaks_lines_at_newlines() {
+        let metrics = FixedMetrics::new();
+        let quads = layout(&metrics, "AB\nC\n\nD", None);
+        assert_eq!(
+            pens(&quads),
+            vec![(0.0, 10.0), (10.0, 10.0), (0.0, 25.0), (0.0, 55.0)]
+        );
+    }
+
+    #[test]
+    fn layout_wraps_words_at_max_width() {
+        let metrics = FixedMetrics::new();
+        // "AB CD" is 45 wide, so CD moves to the next line.
+        let quads = layout(&metrics, "AB CD E", Some(40.0));
+        assert_eq!(
+            pens(&quads),
+            vec![
+                (0.0, 10.0),
+                (10.0, 10.0),
+                (0.0, 25.0),
+                (10.0, 25.0),
+                (25.0, 25.0),
+            ]
+        );
+
+        // Exactly fitting words stay on the line.
+        let quads = layout(&metrics, "AB CD", Some(45.0));
+        assert_eq!(pens(&quads)[3], (35.0, 10.0));
+    }
+
+    #[test]
+    fn layout_keeps_overlong_words_whole() {
+        let metrics = FixedMetrics::new();
+        let quads = layout(&metrics, "ABCDEF G", Some(25.0));
+        let positions = pens(&quads);
+        assert_eq!(positions.len(), 7);
+        for (i, &(x, baseline)) in positions[..6].iter().enumerate() {
+            assert_eq!((x, baseline), (i as f32 * 10.0, 10.0));
+        }
+        assert_eq!(positions[6], (0.0, 25.0));
+
+        // A long word following a short one wraps, then overflows on its own line.
+        let quads = layout(&metrics, "A BCDEF", Some(25.0));
+        let positions = pens(&quads);
+        assert_eq!(positions.len(), 6);
+        assert_eq!(positions[1], (0.0, 25.0));
+        assert_eq!(positions[5], (40.0, 25.0));
+    }
+}
@@ -0,0 +1,129 @@
+use super::text::{self, GlyphCache, TextVertex};
+use super::{
+    ArenaSlice, DescSet, DescSetLayout, DescriptorSetLayoutImpl, DeviceState, PendingUpload,
+    PipelineConfig, TextureState, UploadArena, UploadPools,
+};
+use hal::{self, format, image, pso};
+use std::mem::size_of;
+use std::sync::Arc;
+
+/// GPU side of text rendering: the glyph atlas texture and its descriptor set, plus the glyph
+/// quads queued for the current frame.
+pub struct TextState {
+    pub glyphs: GlyphCache,
+    pub desc: DescSet,
+    _texture: TextureState,
+    vertices: Vec<TextVertex>,
+}
+
+impl TextState {
+    /// Text can't be drawn before the returned upload of the glyph atlas has been submitted.
+    pub fn new(
+        device: &Arc<DeviceState>,
+        upload_pools: &mut UploadPools,
+        memory_types: &[hal::MemoryType],
+        limits: &hal::Limits,
+        font_data: Vec<u8>,
+        pixel_height: f32,
+    ) -> Result<(Self, PendingUpload), String> {
+        let (glyphs, atlas) = GlyphCache::new(font_data, pixel_height)?;
+
+        let (texture, upload) = TextureState::new(
+            Arc::clone(device),
+            upload_pools,
+            memory_types,
+            limits,
+            atlas.width,
+            atlas.height,
+            &atlas.pixels,
+            1,
+            image::Filter::Linear,
+        );
+
+        let mut desc =
+            DescSetLayout::new(Arc::clone(device), vec![TextureState::layout_binding(0)])
+                .create_desc_set();
+        desc.write_to_state(vec![texture.desc_write(0)], &device.device);
+
+        let text = TextState {
+            glyphs,
+            desc,
+            _texture: texture,
+            vertices: Vec::new(),
+        };
+        Ok((text, upload))
+    }
+
+    pub fn pipeline_config(with_depth: bool) -> PipelineConfig {
+        let stride = size_of::<TextVertex>() as u32;
+        let mut config = PipelineConfig {
+            vertex_shader: "src/shaders/text.vert".to_owned(),
+            fragment_shader: "src/shaders/text.frag".to_owned(),
+            primitive: hal::Primitive::TriangleList,
+            blend: pso::BlendState::ALPHA,
+            depth_stencil: pso::DepthStencilDesc::default(),
+            push_constants: vec![(pso::ShaderStageFlags::VERTEX, 0..12)],
+            vertex_buffers: vec![pso::VertexBufferDesc {
+                binding: 0,
+                stride,
+                rate: 0,
+            }],
+            attributes: vec![
+                pso::AttributeDesc {
+                    location: 0,
+                    binding: 0,
+                    element: pso::Element {
+                        format: format::Format::Rg32Float,
+                        offset: 0,
+                    },
+                },
+                pso::AttributeDesc {
+                    location: 1,
+                    binding: 0,
+                    element: pso::Element {
+                        format: format::Format::Rg32Float,
+                        offset: 8,
+                    },
+                },
+                pso::AttributeDesc {
+                    location: 2,
+                    binding: 0,
+                    element: pso::Element {
+                        format: format::Format::Rgba32Float,
+                        offset: 16,
+                    },
+                },
+            ],
+        };
+        if with_depth {
+            config.depth_stencil = pso::DepthStencilDesc {
+                depth: pso::DepthTest::On {
+                    fun: pso::Comparison::LessEqual,
+                    write: false,
+                },
+                depth_bounds: false,
+                stencil: pso::StencilTest::Off,
+            };
+        }
+        config
+    }
+
+    pub fn get_layout(&self) -> &DescriptorSetLayoutImpl {
+        self.desc.get_layout()
+    }
+
+    /// Lays out `text` with its top left corner at `origin` (in pixels) and queues it for the next
+    /// frame.
+    pub fn queue(&mut self, text: &str, origin: [f32; 2], color: [f32; 4], max_width: Option<f32>) {
+        let quads = self.glyphs.layout(text, max_width);
+        text::push_quads(&mut self.vertices, &quads, origin, color);
+    }
+
+    /// Uploads the queued vertices into the frame's arena and clears the queue. Returns `None`
+    /// when nothing was queued.
+    pub fn upload(&mut self, arena: &mut UploadArena) -> Option<ArenaSlice> {
+        let slice = arena.upload(&self.vertices);
+        self.vertices.clear();
+        slice
+    }
+}
@@ -0,0 +1,380 @@
+use super::mipmap::{self, MipLevel};
+use super::{
+    BackendImpl, BufferState, DescSetWrite, DeviceState, ImageImpl, ImageState, PendingUpload,
+    SamplerImpl, UploadPools,
+};
+use hal::{self, buffer, command, format, image, memory, pso, Device, PhysicalDevice};
+use std::sync::Arc;
+
+const TEXTURE_FORMAT: format::Format = format::Format::Rgba8Srgb;
+const BYTES_PER_PIXEL: u32 = 4;
+
+/// A sampled RGBA texture with a mip chain. Pixel data is copied into a staging buffer and from
+/// there into a device local image on the transfer queue, and the image is handed over to the
+/// graphics queue in `ShaderReadOnlyOptimal` layout.
+///
+/// Mip levels are generated on the GPU by blitting each level from the previous one when the
+/// device supports linear blits for the texture format, otherwise they are box filtered on the
+/// CPU and uploaded together with the base level.
+///
+/// The texture can't be sampled before the upload returned by `new` has been submitted.
+pub struct TextureState {
+    image: ImageState,
+    sampler: Option<SamplerImpl>,
+    device: Arc<DeviceState>,
+    pub width: u32,
+    pub height: u32,
+    pub mip_levels: image::Level,
+}
+
+impl TextureState {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        device_ptr: Arc<DeviceState>,
+        upload_pools: &mut UploadPools,
+        memory_types: &[hal::MemoryType],
+        limits: &hal::Limits,
+        width: u32,
+        height: u32,
+        pixels: &[u8],
+        mip_levels: image::Level,
+        filter: image::Filter,
+    ) -> (Self, PendingUpload) {
+        assert_eq!(pixels.len(), (width * height * BYTES_PER_PIXEL) as usize);
+        let mip_levels = mip_levels
+            .min(mipmap::mip_level_count(width, height))
+            .max(1);
+
+        let blit_supported = device_ptr
+            .physical_device
+            .format_properties(Some(TEXTURE_FORMAT))
+            .optimal_tiling
+            .contains(
+                format::ImageFeature::BLIT_SRC
+                    | format::ImageFeature::BLIT_DST
+                    | format::ImageFeature::SAMPLED_LINEAR,
+            );
+
+        // With GPU generation only the base level is uploaded.
+        let uploaded_levels = if blit_supported {
+            vec![MipLevel {
+                width,
+                height,
+                pixels: pixels.to_vec(),
+            }]
+        } else {
+            mipmap::generate_mip_chain(width, height, pixels, mip_levels)
+        };
+
+        let (staging_data, copies) = TextureState::pack_levels(&uploaded_levels, limits);
+        let staging_buffer = BufferState::new(
+            Arc::clone(&device_ptr),
+            &staging_data,
+            buffer::Usage::TRANSFER_SRC,
+            memory_types,
+        );
+
+        let image = ImageState::new(
+            Arc::clone(&device_ptr),
+            memory_types,
+            image::Kind::D2(width, height, 1, 1),
+            mip_levels,
+            TEXTURE_FORMAT,
+            image::Usage::TRANSFER_SRC | image::Usage::TRANSFER_DST | image::Usage::SAMPLED,
+            format::Aspects::COLOR,
+        );
+
+        let target = image.get_image();
+        let upload = device_ptr.upload(
+            upload_pools,
+            staging_buffer,
+            |cmd_buffer, staging_buffer, families| {
+                cmd_buffer.pipeline_barrier(
+                    pso::PipelineStage::TOP_OF_PIPE..pso::PipelineStage::TRANSFER,
+                    memory::Dependencies::empty(),
+                    &[memory::Barrier::Image {
+                        states: (image::Access::empty(), image::Layout::Undefined)
+                            ..(
+                                image::Access::TRANSFER_WRITE,
+                                image::Layout::TransferDstOptimal,
+                            ),
+                        target,
+                        families: None,
+                        range: color_range(0..mip_levels),
+                    }],
+                );
+
+                cmd_buffer.copy_buffer_to_image(
+                    staging_buffer.get_buffer(),
+                    target,
+                    image::Layout::TransferDstOptimal,
+                    &copies,
+                );
+
+                // Release to the graphics queue, which generates the mip chain or transitions the
+                // image for sampling.
+                if families.is_some() {
+                    cmd_buffer.pipeline_barrier(
+                        pso::PipelineStage::TRANSFER..pso::PipelineStage::BOTTOM_OF_PIPE,
+                        memory::Dependencies::empty(),
+                        &[memory::Barrier::Image {
+                            states: (
+                                image::Access::TRANSFER_WRITE,
+                                image::Layout::TransferDstOptimal,
+                            )
+                                ..(image::Access::empty(), image::Layout::TransferDstOptimal),
+                            target,
+                            families: families.clone(),
+                            range: color_range(0..mip_levels),
+                        }],
+                    );
+                }
+            },
+            |cmd_buffer, families| {
+                if families.is_some() {
+                    cmd_buffer.pipeline_barrier(
+                        pso::PipelineStage::TOP_OF_PIPE..pso::PipelineStage::TRANSFER,
+                        memory::Dependencies::empty(),
+                        &[memory::Barrier::Image {
+                            states: (image::Access::empty(), image::Layout::TransferDstOptimal)
+                                ..(
+                                    image::Access::TRANSFER_WRITE,
+                                    image::Layout::TransferDstOptimal,
+                                ),
+                            target,
+                            families: families.clone(),
+                            range: color_range(0..mip_levels),
+                        }],
+                    );
+                }
+
+                if blit_supported {
+                    TextureState::blit_mip_chain(cmd_buffer, target, width, height, mip_levels);
+                } else {
+                    cmd_buffer.pipeline_barrier(
+                        pso::PipelineStage::TRANSFER..pso::PipelineStage::FRAGMENT_SHADER,
+                        memory::Dependencies::empty(),
+                        &[memory::Barrier::Image {
+                            states: (
+                                image::Access::TRANSFER_WRITE,
+                                image::Layout::TransferDstOptimal,
+                            )
+                                ..(
+                                    image::Access::SHADER_READ,
+                                    image::Layout::ShaderReadOnlyOptimal,
+                                ),
+                            target,
+                            families: None,
+                            range: color_range(0..mip_levels),
+                        }],
+                    );
+                }
+            },
+        );
+
+        let mut sampler_info = image::SamplerInfo::new(filter, image::WrapMode::Clamp);
+        sampler_info.mip_filter = filter;
+        let sampler = device_ptr
+            .device
+            .create_sampler(sampler_info)
+            .expect("Can't create sampler");
+
+        let texture = TextureState {
+            image,
+            sampler: Some(sampler),
+            device: device_ptr,
+            width,
+            height,
+            mip_levels,
+        };
+        (texture, upload)
+    }
+
+    /// Lays out every level in a single staging buffer, respecting the device's row pitch and
+    /// offset alignment for buffer to image copies.
+    fn pack_levels(
+        levels: &[MipLevel],
+        limits: &hal::Limits,
+    ) -> (Vec<u8>, Vec<command::BufferImageCopy>) {
+        let row_alignment_mask = limits.min_buffer_copy_pitch_alignment as u32 - 1;
+        let offset_alignment_mask = limits.min_buffer_copy_offset_alignment as usize - 1;
+
+        let mut staging_data = Vec::new();
+        let mut copies = Vec::with_capacity(levels.len());
+        for (level, mip) in levels.iter().enumerate() {
+            let offset = (staging_data.len() + offset_alignment_mask) & !offset_alignment_mask;
+            let row_size = mip.width * BYTES_PER_PIXEL;
+            let row_pitch = (row_size + row_alignment_mask) & !row_alignment_mask;
+
+            staging_data.resize(offset + (row_pitch * mip.height) as usize, 0);
+            for y in 0..mip.height as usize {
+                let row = &mip.pixels[y * row_size as usize..(y + 1) * row_size as usize];
+                let dest_base = offset + y * row_pitch as usize;
+                staging_data[dest_base..dest_base + row.len()].copy_from_slice(row);
+            }
+
+            copies.push(command::BufferImageCopy {
+                buffer_offset: offset as u64,
+                buffer_width: row_pitch / BYTES_PER_PIXEL,
+                buffer_height: mip.height,
+                image_layers: image::SubresourceLayers {
+                    aspects: format::Aspects::COLOR,
+                    level: level as image::Level,
+                    layers: 0..1,
+                },
+                image_offset: image::Offset { x: 0, y: 0, z: 0 },
+                image_extent: image::Extent {
+                    width: mip.width,
+                    height: mip.height,
+                    depth: 1,
+                },
+            });
+        }
+
+        (staging_data, copies)
+    }
+
+    /// Fills levels `1..mip_levels` by blitting each from the one above it. Expects every level to
+    /// be in `TransferDstOptimal` with level 0 already written, and leaves every level in
+    /// `ShaderReadOnlyOptimal`.
+    fn blit_mip_chain(
+        cmd_buffer: &mut command::CommandBuffer<BackendImpl, hal::Graphics>,
+        target: &ImageImpl,
+        width: u32,
+        height: u32,
+        mip_levels: image::Level,
+    ) {
+        let mut src_width = width as i32;
+        let mut src_height = height as i32;
+
+        for level in 1..mip_levels {
+            let dst_width = (src_width / 2).max(1);
+            let dst_height = (src_height / 2).max(1);
+
+            cmd_buffer.pipeline_barrier(
+                pso::PipelineStage::TRANSFER..pso::PipelineStage::TRANSFER,
+                memory::Dependencies::empty(),
+                &[memory::Barrier::Image {
+                    states: (
+                        image::Access::TRANSFER_WRITE,
+                        image::Layout::TransferDstOptimal,
+                    )
+                        ..(
+                            image::Access::TRANSFER_READ,
+                            image::Layout::TransferSrcOptimal,
+                        ),
+                    target,
+                    families: None,
+                    range: color_range(level - 1..level),
+                }],
+            );
+
+            cmd_buffer.blit_image(
+                target,
+                image::Layout::TransferSrcOptimal,
+                target,
+                image::Layout::TransferDstOptimal,
+                image::Filter::Linear,
+                &[command::ImageBlit {
+                    src_subresource: image::SubresourceLayers {
+                        aspects: format::Aspects::COLOR,
+                        level: level - 1,
+                        layers: 0..1,
+                    },
+                    src_bounds: image::Offset { x: 0, y: 0, z: 0 }..image::Offset {
+                        x: src_width,
+                        y: src_height,
+                        z: 1,
+                    },
+                    dst_subresource: image::SubresourceLayers {
+                        aspects: format::Aspects::COLOR,
+                        level,
+                        layers: 0..1,
+                    },
+                    dst_bounds: image::Offset { x: 0, y: 0, z: 0 }..image::Offset {
+                        x: dst_width,
+                        y: dst_height,
+                        z: 1,
+                    },
+                }],
+            );
+
+            cmd_buffer.pipeline_barrier(
+                pso::PipelineStage::TRANSFER..pso::PipelineStage::FRAGMENT_SHADER,
+                memory::Dependencies::empty(),
+                &[memory::Barrier::Image {
+                    states: (
+                        image::Access::TRANSFER_READ,
+                        image::Layout::TransferSrcOptimal,
+                    )
+                        ..(
+                            image::Access::SHADER_READ,
+                            image::Layout::ShaderReadOnlyOptimal,
+                        ),
+                    target,
+                    families: None,
+                    range: color_range(level - 1..level),
+                }],
+            );
+
+            src_width = dst_width;
+            src_height = dst_height;
+        }
+
+        // The last level is only ever written to.
+        cmd_buffer.pipeline_barrier(
+            pso::PipelineStage::TRANSFER..pso::PipelineStage::FRAGMENT_SHADER,
+            memory::Dependencies::empty(),
+            &[memory::Barrier::Image {
+                states: (
+                    image::Access::TRANSFER_WRITE,
+                    image::Layout::TransferDstOptimal,
+                )
+                    ..(
+                        image::Access::SHADER_READ,
+                        image::Layout::ShaderReadOnlyOptimal,
+                    ),
+                target,
+                families: None,
+                range: color_range(mip_levels - 1..mip_levels),
+            }],
+        );
+    }
+
+    pub fn layout_binding(binding: u32) -> pso::DescriptorSetLayoutBinding {
+        pso::DescriptorSetLayoutBinding {
+            binding,
+            ty: pso::DescriptorType::CombinedImageSampler,
+            count: 1,
+            stage_flags: pso::ShaderStageFlags::FRAGMENT,
+            immutable_samplers: false,
+        }
+    }
+
+    pub fn desc_write(&self, binding: u32) -> DescSetWrite<Option<pso::Descriptor<BackendImpl>>> {
+        DescSetWrite {
+            binding,
+            array_offset: 0,
+            descriptors: Some(pso::Descriptor::CombinedImageSampler(
+                self.image.get_view(),
+                image::Layout::ShaderReadOnlyOptimal,
+                self.sampler.as_ref().unwrap(),
+            )),
+        }
+    }
+}
+
+fn color_range(levels: std::ops::Range<image::Level>) -> image::SubresourceRange {
+    image::SubresourceRange {
+        aspects: format::Aspects::COLOR,
+        levels,
+        layers: 0..1,
+    }
+}
+
+impl Drop for TextureState {
+    fn drop(&mut self) {
+        let device = &self.device.device;
+        device.destroy_sampler(self.sampler.take().unwrap());
+    }
+}
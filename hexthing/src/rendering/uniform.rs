@@ -1,6 +1,5 @@
 use hal::{self, buffer, pso};
-use std::cell::RefCell;
-use std::rc::Rc;
+use std::sync::Arc;
 
 use super::{BufferState, DescSet, DescSetWrite, DescriptorSetLayoutImpl, DeviceState};
 
@@ -11,7 +10,7 @@ pub struct Uniform {
 
 impl Uniform {
     pub fn new<T>(
-        device: &Rc<RefCell<DeviceState>>,
+        device: &Arc<DeviceState>,
         memory_types: &[hal::MemoryType],
         data: &[T],
         mut desc: DescSet,
@@ -21,7 +20,7 @@ impl Uniform {
         T: Copy,
     {
         let buffer = BufferState::new(
-            Rc::clone(&device),
+            Arc::clone(&device),
             &data,
             buffer::Usage::UNIFORM,
             memory_types,
@@ -37,7 +36,7 @@ impl Uniform {
                     None..None,
                 )),
             }],
-            &mut device.borrow_mut().device,
+            &device.device,
         );
 
         Uniform {
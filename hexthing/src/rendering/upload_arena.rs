@@ -0,0 +1,194 @@
+use super::{BufferImpl, DeviceState, MemoryImpl};
+use hal::{self, buffer, memory, Device, PhysicalDevice};
+use std::mem::size_of;
+use std::ptr;
+use std::sync::Arc;
+
+/// Size of a block unless a single upload needs more.
+const BLOCK_SIZE: u64 = 256 * 1024;
+
+/// Every suballocation starts at a multiple of this, which satisfies vertex attribute alignment.
+const ALIGNMENT: u64 = 256;
+
+/// Where an upload ended up. Only valid until the arena is reset.
+#[derive(Debug, Copy, Clone)]
+pub struct ArenaSlice {
+    block: usize,
+    pub offset: u64,
+    /// Number of elements uploaded.
+    pub count: u32,
+}
+
+struct Block {
+    buffer: BufferImpl,
+    memory: MemoryImpl,
+    /// Persistently mapped for the lifetime of the block.
+    mapping: *mut u8,
+    /// Size of the buffer, which suballocations have to fit in.
+    size: u64,
+    /// Size of the memory backing the buffer, at least `size`.
+    allocation_size: u64,
+    coherent: bool,
+}
+
+/// Linear allocator for data written by the CPU every frame, such as debug lines, highlights and
+/// text quads.
+///
+/// Uploads are appended to a persistently mapped buffer. When it is full, the next block in the
+/// chain is used, and a new one is allocated once the chain runs out. Everything is released at
+/// once by `reset`, which may only happen when the GPU is done with the frame that used the
+/// arena, so `FrameContext` keeps one arena per frame in flight and resets it in `begin_frame`.
+pub struct UploadArena {
+    blocks: Vec<Block>,
+    /// Block currently being appended to and the offset of its free space.
+    current: usize,
+    offset: u64,
+    memory_types: Vec<hal::MemoryType>,
+    /// Flushed ranges of non-coherent memory are rounded out to multiples of this.
+    non_coherent_atom_size: u64,
+    device: Arc<DeviceState>,
+}
+
+impl UploadArena {
+    pub fn new(device: &Arc<DeviceState>, memory_types: &[hal::MemoryType]) -> Self {
+        UploadArena {
+            blocks: Vec::new(),
+            current: 0,
+            offset: 0,
+            memory_types: memory_types.to_vec(),
+            non_coherent_atom_size: device.physical_device.limits().non_coherent_atom_size as u64,
+            device: Arc::clone(device),
+        }
+    }
+
+    /// Copies `data` into the arena. Returns `None` for empty data, which has nothing to draw.
+    pub fn upload<T>(&mut self, data: &[T]) -> Option<ArenaSlice>
+    where
+        T: Copy,
+    {
+        if data.is_empty() {
+            return None;
+        }
+
+        let size = (data.len() * size_of::<T>()) as u64;
+        let (block, offset) = self.allocate(size);
+        unsafe {
+            ptr::copy_nonoverlapping(
+                data.as_ptr() as *const u8,
+                self.blocks[block].mapping.offset(offset as isize),
+                size as usize,
+            );
+        }
+
+        Some(ArenaSlice {
+            block,
+            offset,
+            count: data.len() as u32,
+        })
+    }
+
+    /// The buffer and offset to bind to draw from `slice`.
+    pub fn binding(&self, slice: ArenaSlice) -> (&BufferImpl, u64) {
+        (&self.blocks[slice.block].buffer, slice.offset)
+    }
+
+    fn allocate(&mut self, size: u64) -> (usize, u64) {
+        loop {
+            if self.current == self.blocks.len() {
+                let block = self.create_block(size.max(BLOCK_SIZE));
+                self.blocks.push(block);
+            }
+
+            let offset = align(self.offset, ALIGNMENT);
+            if offset + size <= self.blocks[self.current].size {
+                self.offset = offset + size;
+                return (self.current, offset);
+            }
+
+            // Chain to the next block. Blocks too small for this upload stay in the chain for
+            // later, smaller uploads.
+            self.current += 1;
+            self.offset = 0;
+        }
+    }
+
+    fn create_block(&self, size: u64) -> Block {
+        let device = &self.device.device;
+        let unbound = device.create_buffer(size, buffer::Usage::VERTEX).unwrap();
+        let requirements = device.get_buffer_requirements(&unbound);
+
+        let find_type = |properties: memory::Properties| {
+            self.memory_types
+                .iter()
+                .enumerate()
+                .position(|(id, memory_type)| {
+                    requirements.type_mask & (1 << id) != 0
+                        && memory_type.properties.contains(properties)
+                })
+        };
+        let coherent_type =
+            find_type(memory::Properties::CPU_VISIBLE | memory::Properties::COHERENT);
+        let coherent = coherent_type.is_some();
+        let memory_type = coherent_type
+            .or_else(|| find_type(memory::Properties::CPU_VISIBLE))
+            .expect("No CPU visible memory for upload arena");
+
+        let memory = device
+            .allocate_memory(memory_type.into(), requirements.size)
+            .unwrap();
+        let buffer = device.bind_buffer_memory(&memory, 0, unbound).unwrap();
+        let mapping = device.map_memory(&memory, 0..requirements.size).unwrap();
+
+        Block {
+            buffer,
+            memory,
+            mapping,
+            size,
+            allocation_size: requirements.size,
+            coherent,
+        }
+    }
+
+    /// Makes the uploads visible to the GPU. Has to be called after the last upload and before
+    /// submitting commands that read from the arena.
+    pub fn flush(&self) {
+        let device = &self.device.device;
+        let used = self.blocks.iter().enumerate().filter_map(|(index, block)| {
+            if block.coherent || index > self.current {
+                return None;
+            }
+            if index == self.current {
+                if self.offset == 0 {
+                    return None;
+                }
+                let end = align(self.offset, self.non_coherent_atom_size);
+                Some((&block.memory, 0..end.min(block.allocation_size)))
+            } else {
+                Some((&block.memory, 0..block.allocation_size))
+            }
+        });
+        device.flush_mapped_memory_ranges(used).unwrap();
+    }
+
+    /// Releases every upload. The caller is responsible for making sure the GPU is done with them.
+    pub fn reset(&mut self) {
+        self.current = 0;
+        self.offset = 0;
+    }
+}
+
+impl Drop for UploadArena {
+    fn drop(&mut self) {
+        let device = &self.device.device;
+        for block in self.blocks.drain(..) {
+            device.unmap_memory(&block.memory);
+            device.destroy_buffer(block.buffer);
+            device.free_memory(block.memory);
+        }
+    }
+}
+
+fn align(offset: u64, alignment: u64) -> u64 {
+    let alignment = alignment.max(1);
+    (offset + alignment - 1) / alignment * alignment
+}
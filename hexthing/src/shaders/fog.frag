@@ -0,0 +1,12 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+layout(location = 0) out vec4 target0;
+
+layout(push_constant) uniform FogConsts {
+    layout(offset = 16) vec4 color;
+} fog;
+
+void main() {
+    target0 = fog.color;
+}
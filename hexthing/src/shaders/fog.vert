@@ -0,0 +1,16 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+layout(push_constant) uniform FogConsts {
+    float depth;
+} fog;
+
+out gl_PerVertex {
+    vec4 gl_Position;
+};
+
+void main() {
+    // A single triangle with its corners at (-1, -1), (3, -1) and (-1, 3) covers the screen.
+    vec2 pos = vec2((gl_VertexIndex << 1) & 2, gl_VertexIndex & 2);
+    gl_Position = vec4(pos * 2.0 - 1.0, fog.depth, 1.0);
+}
@@ -1,12 +1,16 @@
 #version 450
 #extension GL_ARB_separate_shader_objects : enable
 
+layout(location = 0) in vec2 v_uv;
+
 layout(location = 0) out vec4 target0;
 
 layout(set = 0, binding = 0) uniform UBOCol {
     vec4 color;
 } color_dat;
 
+layout(set = 0, binding = 1) uniform sampler2D u_atlas;
+
 void main() {
-    target0 = color_dat.color;
+    target0 = texture(u_atlas, v_uv) * color_dat.color;
 }
\ No newline at end of file
@@ -2,11 +2,23 @@
 #extension GL_ARB_separate_shader_objects : enable
 
 layout(location = 0) in vec2 a_pos;
+layout(location = 1) in vec2 a_offset;
+layout(location = 2) in vec4 a_uv_rect;
+
+layout(location = 0) out vec2 v_uv;
+
+layout(push_constant) uniform PushConsts {
+    mat4 view_proj;
+    float depth;
+} push;
 
 out gl_PerVertex {
     vec4 gl_Position;
 };
 
 void main() {
-    gl_Position = vec4(a_pos, 0.0, 1.0);
+    vec2 local_uv = vec2(a_pos.x / 1.7320508 + 0.5, 0.5 - a_pos.y * 0.5);
+    v_uv = a_uv_rect.xy + local_uv * a_uv_rect.zw;
+    vec4 position = push.view_proj * vec4(a_pos + a_offset, 0.0, 1.0);
+    gl_Position = vec4(position.xy, push.depth, 1.0);
 }
\ No newline at end of file
@@ -0,0 +1,33 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+// Position inside the unit (circumradius 1, pointy top) hex being drawn.
+layout(location = 0) in vec2 v_local;
+
+layout(location = 0) out vec4 target0;
+
+layout(push_constant) uniform GridConsts {
+    layout(offset = 80) vec4 color;
+    layout(offset = 96) float line_width;
+    layout(offset = 100) float feather;
+} grid;
+
+const float INRADIUS = 0.8660254;
+
+// Signed distance to the hex border, negative inside.
+float hex_sdf(vec2 p) {
+    p = abs(p);
+    return max(p.x, dot(p, vec2(0.5, INRADIUS))) - INRADIUS;
+}
+
+void main() {
+    float d = hex_sdf(v_local);
+    // Screen space derivative keeps the antialiased edge about `feather` pixels wide at any zoom.
+    float aa = max(fwidth(d) * grid.feather, 1e-5);
+    float edge = -d;
+    float coverage = 1.0 - smoothstep(grid.line_width - aa, grid.line_width + aa, edge);
+    if (coverage <= 0.0) {
+        discard;
+    }
+    target0 = vec4(grid.color.rgb, grid.color.a * coverage);
+}
@@ -0,0 +1,23 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+layout(location = 0) in vec2 a_pos;
+layout(location = 1) in vec2 a_offset;
+layout(location = 2) in vec4 a_uv_rect;
+
+layout(location = 0) out vec2 v_local;
+
+layout(push_constant) uniform PushConsts {
+    mat4 view_proj;
+    float depth;
+} push;
+
+out gl_PerVertex {
+    vec4 gl_Position;
+};
+
+void main() {
+    v_local = a_pos;
+    vec4 position = push.view_proj * vec4(a_pos + a_offset, 0.0, 1.0);
+    gl_Position = vec4(position.xy, push.depth, 1.0);
+}
@@ -0,0 +1,10 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+layout(location = 0) flat in uint v_id;
+
+layout(location = 0) out uint target0;
+
+void main() {
+    target0 = v_id;
+}
\ No newline at end of file
@@ -0,0 +1,24 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+layout(location = 0) in vec2 a_pos;
+layout(location = 1) in vec2 a_offset;
+layout(location = 2) in float a_scale;
+layout(location = 3) in float a_depth;
+layout(location = 4) in uint a_id;
+
+layout(location = 0) flat out uint v_id;
+
+layout(push_constant) uniform PushConsts {
+    mat4 view_proj;
+} push;
+
+out gl_PerVertex {
+    vec4 gl_Position;
+};
+
+void main() {
+    v_id = a_id;
+    vec4 position = push.view_proj * vec4(a_pos * a_scale + a_offset, 0.0, 1.0);
+    gl_Position = vec4(position.xy, a_depth, 1.0);
+}
\ No newline at end of file
@@ -0,0 +1,15 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+layout(location = 0) in vec2 v_uv;
+layout(location = 1) in vec4 v_color;
+
+layout(location = 0) out vec4 target0;
+
+// Glyph coverage is stored in the alpha channel.
+layout(set = 0, binding = 0) uniform sampler2D u_glyphs;
+
+void main() {
+    float coverage = texture(u_glyphs, v_uv).a;
+    target0 = vec4(v_color.rgb, v_color.a * coverage);
+}
@@ -0,0 +1,25 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+// Position in pixels, origin at the top left corner of the window.
+layout(location = 0) in vec2 a_pos;
+layout(location = 1) in vec2 a_uv;
+layout(location = 2) in vec4 a_color;
+
+layout(location = 0) out vec2 v_uv;
+layout(location = 1) out vec4 v_color;
+
+layout(push_constant) uniform PushConsts {
+    vec2 screen_size;
+    float depth;
+} push;
+
+out gl_PerVertex {
+    vec4 gl_Position;
+};
+
+void main() {
+    v_uv = a_uv;
+    v_color = a_color;
+    gl_Position = vec4(a_pos / push.screen_size * 2.0 - 1.0, push.depth, 1.0);
+}